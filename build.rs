@@ -0,0 +1,34 @@
+/* Generates the gRPC server for proto/todo.proto (see src/grpc.rs). tonic's usual 'compile_protos' needs the protoc compiler installed; describing the service here instead keeps the build pure Rust. The messages are written out by hand in src/grpc.rs, so if you change the .proto, change them (and this list of methods) to match. */
+
+use tonic_build::manual::{Builder, Method, Service};
+
+fn main() {
+    /* (Rust name, name in the .proto, request type, response type, whether it streams responses) */
+    let methods = [
+        ("get", "Get", "GetTodoRequest", "Todo", false),
+        ("list", "List", "ListTodosRequest", "ListTodosResponse", false),
+        ("create", "Create", "CreateTodoRequest", "Todo", false),
+        ("update", "Update", "UpdateTodoRequest", "UpdateTodoResponse", false),
+        ("delete", "Delete", "DeleteTodoRequest", "DeleteTodoResponse", false),
+        ("watch", "Watch", "WatchRequest", "TodoEvent", true),
+    ];
+
+    let mut service = Service::builder().name("TodoService").package("todo.v1");
+    for (name, route_name, input, output, streaming) in methods {
+        let mut method = Method::builder()
+            .name(name)
+            .route_name(route_name)
+            .input_type(format!("crate::grpc::{}", input))
+            .output_type(format!("crate::grpc::{}", output))
+            .codec_path("tonic::codec::ProstCodec");
+        if streaming {
+            method = method.server_streaming();
+        }
+        service = service.method(method.build());
+    }
+
+    Builder::new()
+        .build_client(false)
+        .compile(&[service.build()]);
+    println!("cargo:rerun-if-changed=build.rs");
+}
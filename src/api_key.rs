@@ -0,0 +1,118 @@
+/* This file handles API keys for bots and scheduled jobs, so they don't have to log in with a person's password. A key is a random string shown to its owner once; we only keep its SHA-256 hash. Callers send it in the "X-API-Key" header and act as the user who created it. Keys are either read-only (GET requests only) or read-write, and can be revoked at any time. */
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::model::ApiKeyDB;
+
+/* The header API keys are sent in. */
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/* What a key is allowed to do. */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    ReadOnly,
+    ReadWrite,
+}
+
+/* A stored API key. The key itself is never stored, only 'keyHash'. 'prefix' is the start of the key, so owners can tell their keys apart. */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ApiKey {
+    pub id: String,
+    pub ownerId: String,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip_serializing)]
+    pub keyHash: String,
+    pub scope: ApiKeyScope,
+    pub createdAt: DateTime<Utc>,
+    pub lastUsedAt: Option<DateTime<Utc>>,
+    pub revokedAt: Option<DateTime<Utc>>,
+}
+
+/* Hashes a key for storage and lookup. Keys are long and random, so a plain SHA-256 is enough (unlike passwords, which need Argon2). */
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/* Makes a new key for a user. Returns the stored record and the plain key, which has to be handed to the user now because it can't be recovered later. */
+pub fn generate(owner_id: &str, name: String, scope: ApiKeyScope) -> (ApiKey, String) {
+    let key = format!("tk_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let record = ApiKey {
+        id: Uuid::new_v4().to_string(),
+        ownerId: owner_id.to_string(),
+        name,
+        prefix: key[..11].to_string(),
+        keyHash: hash_key(&key),
+        scope,
+        createdAt: Utc::now(),
+        lastUsedAt: None,
+        revokedAt: None,
+    };
+    (record, key)
+}
+
+/* Looks up a key sent by a client. Revoked and unknown keys both give None. A key that is found gets its 'lastUsedAt' updated. */
+pub async fn authenticate(keys: &ApiKeyDB, key: &str) -> Option<ApiKey> {
+    let hash = hash_key(key);
+    let mut keys = keys.lock().await;
+    let record = keys
+        .iter_mut()
+        .find(|record| record.keyHash == hash && record.revokedAt.is_none())?;
+    record.lastUsedAt = Some(Utc::now());
+    Some(record.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::testing::{app, login, send};
+
+    #[tokio::test]
+    async fn keys_act_as_their_owner_until_revoked() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+        let mut keys = Vec::new();
+        for scope in ["read_write", "read_only"] {
+            let (status, body) = send(&router, Method::POST, "/api/keys", &auth, json!({ "name": scope, "scope": scope })).await;
+            assert_eq!(status, StatusCode::CREATED);
+            keys.push((body["data"]["apiKey"]["id"].as_str().unwrap().to_string(), body["data"]["key"].as_str().unwrap().to_string()));
+        }
+        let (read_write_id, read_write) = &keys[0];
+        let (_, read_only) = &keys[1];
+        let todo = json!({ "title": "From a bot", "content": "a" });
+
+        /* A read-write key can change things, as its owner. */
+        let (status, body) = send(&router, Method::POST, "/api/todos", &[("x-api-key", read_write.as_str())], todo.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, mine) = send(&router, Method::GET, "/api/todos", &auth, Value::Null).await;
+        assert_eq!(mine["todos"][0]["id"], body["data"]["todo"]["id"]);
+
+        /* A read-only key can only read. */
+        let (status, _) = send(&router, Method::GET, "/api/todos", &[("x-api-key", read_only.as_str())], Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&router, Method::POST, "/api/todos", &[("x-api-key", read_only.as_str())], todo.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        /* Listing never shows the keys themselves. */
+        let (_, body) = send(&router, Method::GET, "/api/keys", &auth, Value::Null).await;
+        assert!(!body.to_string().contains(read_write.as_str()));
+        assert!(!body.to_string().contains("keyHash"));
+
+        /* A revoked key, or one we never issued, gets a 401. */
+        let (status, body) = send(&router, Method::DELETE, &format!("/api/keys/{}", read_write_id), &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["data"]["apiKey"]["revokedAt"].is_string());
+        let (status, _) = send(&router, Method::GET, "/api/todos", &[("x-api-key", read_write.as_str())], Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(&router, Method::GET, "/api/todos", &[("x-api-key", "tk_made_up")], Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,273 @@
+/* This file keeps an append-only audit trail: every change to a todo is written down with who made it, when, from where, and what the todo looked like before and after. Since every change already passes through the 'Auditor', it also feeds the revision history, the undo stacks, live updates and the event log. */
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Request},
+    http::{request::Parts, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use chrono::prelude::*;
+use serde::Serialize;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    auth::CurrentUser,
+    event_store::{self, EventStore},
+    live::EventBus,
+    model::{AppState, Todo},
+    revision::RevisionStore,
+    undo::{self, Change, UndoStore},
+};
+
+/* The header a request ID is read from and sent back in. */
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/* What happened to the todo. Anything that changes an existing todo (dependencies, reminders, moves, sharing...) counts as an edit. 'Delete' moves a todo to the trash, 'Restore' takes it back out, and 'Purge' removes it for good. 'Share' and 'Unshare' mean the todo's list was shared with, or unshared from, the entry's 'targetUserId'; the todo itself didn't change. */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Edit,
+    Delete,
+    Restore,
+    Purge,
+    Share,
+    Unshare,
+}
+
+/* One entry in the trail. 'before' is empty for a create and 'after' is empty for a purge. */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct AuditEntry {
+    pub id: String,
+    pub tenantId: String,
+    pub action: AuditAction,
+    pub todoId: String,
+    pub actorId: String,
+    pub apiKeyId: Option<String>,
+    pub requestId: String,
+    pub ip: Option<String>,
+    pub at: DateTime<Utc>,
+    pub before: Option<Todo>,
+    pub after: Option<Todo>,
+    pub targetUserId: Option<String>,
+        // Only for 'Share' and 'Unshare': who gained or lost access.
+}
+
+/* Every audit entry, oldest first, kept per workspace like the todos themselves. An entry's place in its workspace's list is what sync tokens count (see sync.rs). */
+pub type AuditLog = Arc<Mutex<HashMap<String, Vec<AuditEntry>>>>;
+
+/* The ID of the current request, put on the request by 'request_id_middleware'. */
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/* Runs before everything else. Keeps the client's request ID (if it's a sensible length) or makes one up, and adds it to the response. */
+pub async fn request_id_middleware(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    request.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    response
+}
+
+/* Writes entries to the audit log for one request. Add it as a handler argument; it already knows the request ID and the client's IP, so the handler only says what changed. */
+pub struct Auditor {
+    log: AuditLog,
+    revisions: Arc<RevisionStore>,
+    undo: Arc<UndoStore>,
+    events: Arc<EventBus>,
+    event_store: Arc<EventStore>,
+    request_id: String,
+    ip: Option<String>,
+    track_undo: bool,
+        // False for background work, which has no user to undo it.
+}
+
+impl Auditor {
+    /* Makes a change to a workspace's todos ('todos'): appends an entry, keeps the todo's revision history up to date, makes the change undoable, and only then puts 'after' into the list (or takes the todo out when there is no 'after'). Handlers work out the new version and hand it over here instead of changing the list themselves, so the list is always what replaying the events gives. */
+    pub async fn record(
+        &self,
+        todos: &mut Vec<Todo>,
+        user: &CurrentUser,
+        action: AuditAction,
+        before: Option<&Todo>,
+        after: Option<&Todo>,
+    ) {
+        self.write(todos, user, action, before, after).await;
+
+        if self.track_undo {
+            let change = Change {
+                before: before.cloned(),
+                after: after.cloned(),
+            };
+            self.undo.push(&user.tenant, &user.id, &self.request_id, action, change).await;
+        }
+    }
+
+    /* Records a change made by undo or redo. It goes in the audit log and revision history like any other, but not on the undo stack, which the undo handler manages itself. */
+    pub async fn record_replay(&self, todos: &mut Vec<Todo>, user: &CurrentUser, change: &Change) {
+        self.write(todos, user, undo::action_for(change), change.before.as_ref(), change.after.as_ref()).await;
+    }
+
+    /* Appends the audit entry and the event, applies the event to the list, updates the revision history, and tells live clients. The todo ID is taken from whichever snapshot there is. */
+    async fn write(
+        &self,
+        todos: &mut Vec<Todo>,
+        user: &CurrentUser,
+        action: AuditAction,
+        before: Option<&Todo>,
+        after: Option<&Todo>,
+    ) {
+        let todo_id = after
+            .or(before)
+            .and_then(|todo| todo.id.clone())
+            .unwrap_or_default();
+
+        self.append(user, action, &todo_id, before, after, None).await;
+
+        self.event_store.append(&user.tenant, &user.id, before, after).await;
+        event_store::apply(todos, &todo_id, after);
+            // The same step a rebuild replays the event log with.
+
+        match (action, after) {
+            (AuditAction::Purge, _) => self.revisions.forget(&user.tenant, &todo_id).await,
+            (_, Some(after)) => self.revisions.record(&user.tenant, after, &user.id).await,
+            _ => {}
+        }
+        self.events.publish(&user.tenant, &user.id, before, after);
+    }
+
+    /* Records that one of the todos in a shared list became visible to 'target_user_id' ('Share') or stopped being visible ('Unshare'). Only the audit log hears about it, since the todo itself didn't change; sync reads it to tell clients about access they gained or lost. */
+    pub async fn record_access(&self, user: &CurrentUser, action: AuditAction, todo: &Todo, target_user_id: &str) {
+        let todo_id = todo.id.clone().unwrap_or_default();
+        self.append(user, action, &todo_id, Some(todo), Some(todo), Some(target_user_id)).await;
+    }
+
+    /* Pushes one entry onto the end of the workspace's log. */
+    async fn append(
+        &self,
+        user: &CurrentUser,
+        action: AuditAction,
+        todo_id: &str,
+        before: Option<&Todo>,
+        after: Option<&Todo>,
+        target_user_id: Option<&str>,
+    ) {
+        self.log.lock().await.entry(user.tenant.clone()).or_default().push(AuditEntry {
+            id: Uuid::new_v4().to_string(),
+            tenantId: user.tenant.clone(),
+            action,
+            todoId: todo_id.to_string(),
+            actorId: user.id.clone(),
+            apiKeyId: user.api_key_id.clone(),
+            requestId: self.request_id.clone(),
+            ip: self.ip.clone(),
+            at: Utc::now(),
+            before: before.cloned(),
+            after: after.cloned(),
+            targetUserId: target_user_id.map(str::to_string),
+        });
+    }
+}
+
+impl Auditor {
+    /* An auditor for work that isn't part of a request, like the trash purge task. It gets a request ID of its own so its entries can still be grouped. */
+    pub fn background(state: &AppState) -> Self {
+        Auditor {
+            log: state.audit.clone(),
+            revisions: state.revisions.clone(),
+            undo: state.undo.clone(),
+            events: state.events.clone(),
+            event_store: state.event_store.clone(),
+            request_id: Uuid::new_v4().to_string(),
+            ip: None,
+            track_undo: false,
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Auditor {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        Ok(Auditor {
+            log: state.audit.clone(),
+            revisions: state.revisions.clone(),
+            undo: state.undo.clone(),
+            events: state.events.clone(),
+            event_store: state.event_store.clone(),
+            track_undo: true,
+            request_id: parts
+                .extensions
+                .get::<RequestId>()
+                .map(|RequestId(id)| id.clone())
+                .unwrap_or_else(|| Uuid::new_v4().to_string()),
+            ip: parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::{
+        model::AppState,
+        testing::{app_with, create_todo, login, send},
+    };
+
+    #[tokio::test]
+    async fn the_log_shows_who_changed_what() {
+        let (router, _) = app_with(AppState::for_tests());
+        let alice = login(&router, "default", "alice").await;
+        let bob = login(&router, "default", "bob").await;
+        let auth = [("authorization", alice.as_str())];
+        let bob_auth = [("authorization", bob.as_str())];
+        let todo = create_todo(&router, &alice, json!({ "title": "Audited", "content": "a" })).await;
+        let id = todo["id"].as_str().unwrap();
+        let todo_uri = format!("/api/todos/{}", id);
+        create_todo(&router, &alice, json!({ "title": "Other", "content": "b" })).await;
+        let (status, _) = send(&router, Method::PATCH, &todo_uri, &auth, json!({ "title": "Renamed" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&router, Method::DELETE, &todo_uri, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let actions = |body: &Value| -> Vec<Value> {
+            body["entries"].as_array().unwrap().iter().map(|entry| entry["action"].clone()).collect()
+        };
+
+        /* One todo's entries come newest first, each with the todo before and after. */
+        let (status, body) = send(&router, Method::GET, &format!("/api/audit?todoId={}", id), &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(actions(&body), vec![json!("delete"), json!("edit"), json!("create")]);
+        assert_eq!(body["entries"][1]["before"]["title"], "Audited");
+        assert_eq!(body["entries"][1]["after"]["title"], "Renamed");
+        assert!(body["entries"][2]["before"].is_null());
+
+        /* 'limit' keeps the newest entries. */
+        let (_, body) = send(&router, Method::GET, "/api/audit?limit=2", &auth, Value::Null).await;
+        assert_eq!(body["results"], 2);
+        assert_eq!(actions(&body), vec![json!("delete"), json!("edit")]);
+
+        /* Someone who can't see the todos sees none of their entries. */
+        let (_, body) = send(&router, Method::GET, "/api/audit", &bob_auth, Value::Null).await;
+        assert_eq!(body["results"], 0);
+    }
+}
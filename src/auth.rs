@@ -0,0 +1,328 @@
+/* This file handles who is calling the API. Passwords are stored only as salted Argon2 hashes, logging in hands out a random session token that expires after SESSION_TTL_SECONDS (default 86400, one day) (or a signed JWT, see jwt.rs), and the 'CurrentUser' extractor turns the "Authorization: Bearer <token>" header back into a user. Any handler that takes a 'CurrentUser' argument automatically rejects requests that aren't logged in. */
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::{
+    collections::HashMap,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    api_key::{self, ApiKeyScope, API_KEY_HEADER},
+    jwt::{unauthorized, Claims},
+    model::{AppState, Todo},
+    tenant::{self, DEFAULT_TENANT, TENANT_CLAIM},
+};
+
+/* The logged-in user making the request. Add it as a handler argument to require a login. */
+#[derive(Debug, Clone)]
+pub struct CurrentUser {
+    pub id: String,
+    pub api_key_id: Option<String>,
+        // Set when the request was made with an API key instead of a login.
+    pub tenant: String,
+        // The workspace the request works in. Handlers only ever open this workspace's todos.
+}
+
+impl CurrentUser {
+    /* True if the todo belongs to this user. Handlers treat anyone else's todo as if it didn't exist (404, not 403), so IDs can't be probed. */
+    pub fn owns(&self, todo: &Todo) -> bool {
+        todo.ownerId.as_deref() == Some(self.id.as_str())
+    }
+
+    /* Like 'owns', but a todo in the trash doesn't count. Trashed todos can only be reached through the trash endpoints. */
+    pub fn owns_active(&self, todo: &Todo) -> bool {
+        self.owns(todo) && todo.deletedAt.is_none()
+    }
+}
+
+/* Hashes a password with a fresh random salt. The salt and settings are stored inside the returned string. */
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| err.to_string())
+}
+
+/* Checks a password against a stored hash. */
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
+
+/* A hash of a password nobody has. Logging in as a user that doesn't exist checks the password against it, so the answer takes as long as for a real user and doesn't give away which usernames exist. */
+pub fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password(&Uuid::new_v4().to_string()).unwrap_or_default())
+}
+
+/* Makes a new session token: two random UUIDs back to back, so it can't be guessed. */
+pub fn new_session_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/* Logged-in sessions: session token -> the user and when the token stops working. */
+pub struct SessionStore {
+    ttl: Duration,
+    sessions: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl SessionStore {
+    /* Reads SESSION_TTL_SECONDS. */
+    pub fn from_env() -> Self {
+        SessionStore {
+            ttl: Duration::from_secs(
+                std::env::var("SESSION_TTL_SECONDS")
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .filter(|ttl| *ttl > 0)
+                    .unwrap_or(86_400),
+            ),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /* Starts a session for a user and returns its token. Expired sessions are dropped at the same time, so they don't pile up. */
+    pub async fn create(&self, user_id: &str) -> String {
+        let token = new_session_token();
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().await;
+        sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        sessions.insert(token.clone(), (user_id.to_string(), now + self.ttl));
+        token
+    }
+
+    /* The user a token belongs to, or None if it is unknown or has expired. */
+    pub async fn user(&self, token: &str) -> Option<String> {
+        let mut sessions = self.sessions.lock().await;
+        match sessions.get(token) {
+            Some((user_id, expires_at)) if *expires_at > Instant::now() => Some(user_id.clone()),
+            Some(_) => {
+                sessions.remove(token);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+/* Makes sure a workspace named by the request's header or subdomain is the one the caller belongs to. Asking for another workspace is refused rather than quietly ignored, so a misconfigured client notices. Returns the error message when they differ. */
+fn same_tenant(requested: Option<String>, tenant: String) -> Result<String, String> {
+    match requested {
+        Some(requested) if requested != tenant => Err(format!("You don't have access to workspace '{}'", requested)),
+        _ => Ok(tenant),
+    }
+}
+
+/* The workspace a stored user belongs to. */
+async fn user_tenant(state: &AppState, user_id: &str) -> Option<String> {
+    state
+        .users
+        .lock()
+        .await
+        .iter()
+        .find(|user| user.id == user_id)
+        .map(|user| user.tenantId.clone())
+}
+
+/* Lets Axum build a 'CurrentUser' from the request before the handler runs. A JWT already verified by 'jwt_middleware' wins (its 'sub' is the user ID, and its "tenant" claim the workspace), then an API key, and otherwise the Bearer token has to be one of our session tokens. */
+#[async_trait]
+impl FromRequestParts<AppState> for CurrentUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let requested = state
+            .tenants
+            .requested(parts)
+            .map_err(|message| tenant::rejection(StatusCode::BAD_REQUEST, message))?;
+
+        /* Tokens from another issuer may not carry a workspace. They then work in the workspace of the user their 'sub' names, or the default one for users we don't know; naming any other workspace is refused, just like with a claim. */
+        if let Some(claims) = parts.extensions.get::<Claims>() {
+            let claimed = claims
+                .extra
+                .get(TENANT_CLAIM)
+                .and_then(|value| value.as_str())
+                .map(str::to_string);
+            let tenant = match claimed {
+                Some(claimed) => claimed,
+                None => user_tenant(state, &claims.sub)
+                    .await
+                    .unwrap_or_else(|| DEFAULT_TENANT.to_string()),
+            };
+            let tenant = same_tenant(requested, tenant)
+                .map_err(|message| tenant::rejection(StatusCode::FORBIDDEN, message))?;
+            return Ok(CurrentUser {
+                id: claims.sub.clone(),
+                api_key_id: None,
+                tenant,
+            });
+        }
+
+        /* An "X-API-Key" header acts as the user who created the key. Read-only keys may only make safe (GET/HEAD) requests. */
+        if let Some(key) = parts.headers.get(API_KEY_HEADER).and_then(|value| value.to_str().ok()) {
+            let Some(record) = api_key::authenticate(&state.api_keys, key).await else {
+                return Err(unauthorized("Invalid or revoked API key", true));
+            };
+            if record.scope == ApiKeyScope::ReadOnly && !parts.method.is_safe() {
+                let error_response = serde_json::json!({
+                    "status": "fail",
+                    "message": "This API key is read-only",
+                });
+                return Err((StatusCode::FORBIDDEN, Json(error_response)).into_response());
+            }
+            let tenant = user_tenant(state, &record.ownerId)
+                .await
+                .ok_or_else(|| unauthorized("This user no longer exists", true))?;
+            let tenant = same_tenant(requested, tenant)
+                .map_err(|message| tenant::rejection(StatusCode::FORBIDDEN, message))?;
+            return Ok(CurrentUser {
+                id: record.ownerId,
+                api_key_id: Some(record.id),
+                tenant,
+            });
+        }
+
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("You are not logged in, please provide a token", false))?;
+
+        let user_id = state
+            .sessions
+            .user(token)
+            .await
+            .ok_or_else(|| unauthorized("Invalid or expired token", true))?;
+        let tenant = user_tenant(state, &user_id)
+            .await
+            .ok_or_else(|| unauthorized("This user no longer exists", true))?;
+        let tenant = same_tenant(requested, tenant)
+            .map_err(|message| tenant::rejection(StatusCode::FORBIDDEN, message))?;
+        Ok(CurrentUser {
+            id: user_id,
+            api_key_id: None,
+            tenant,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use axum::{
+        extract::FromRequestParts,
+        http::{Method, Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+
+    use super::{dummy_hash, verify_password, CurrentUser, SessionStore};
+    use crate::{
+        jwt::Claims,
+        model::AppState,
+        testing::{app, create_todo, login, send},
+    };
+
+    /* Builds the 'CurrentUser' for a request carrying verified JWT claims, and optionally naming a workspace. */
+    async fn jwt_user(state: &AppState, claims: Claims, requested: Option<&str>) -> Result<CurrentUser, StatusCode> {
+        let mut request = Request::builder();
+        if let Some(tenant) = requested {
+            request = request.header("x-tenant-id", tenant);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+        parts.extensions.insert(claims);
+        CurrentUser::from_request_parts(&mut parts, state)
+            .await
+            .map_err(|response| response.status())
+    }
+
+    fn claims(sub: &str, tenant: Option<&str>) -> Claims {
+        Claims {
+            sub: sub.to_string(),
+            exp: u64::MAX,
+            iat: None,
+            iss: None,
+            aud: None,
+            extra: tenant
+                .map(|tenant| HashMap::from([("tenant".to_string(), Value::from(tenant))]))
+                .unwrap_or_default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_cant_read_another_workspace() {
+        let (router, _) = app();
+        let alice = login(&router, "team-a", "alice").await;
+        let mallory = login(&router, "team-b", "mallory").await;
+        let todo = create_todo(&router, &alice, json!({ "title": "Secret", "content": "a" })).await;
+        let uri = format!("/api/todos/{}", todo["id"].as_str().unwrap());
+
+        let (status, _) = send(&router, Method::GET, &uri, &[("authorization", &mallory)], Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, list) = send(&router, Method::GET, "/api/todos", &[("authorization", &mallory)], Value::Null).await;
+        assert_eq!(list["results"], 0);
+
+        let headers = [("authorization", mallory.as_str()), ("x-tenant-id", "team-a")];
+        let (status, _) = send(&router, Method::GET, &uri, &headers, Value::Null).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&router, Method::GET, "/api/todos", &headers, Value::Null).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn jwts_cant_pick_another_workspace() {
+        let (router, state) = app();
+        login(&router, "team-a", "alice").await;
+        let alice = state.users.lock().await[0].id.clone();
+
+        /* A claim naming one workspace can't be overridden by the header. */
+        let result = jwt_user(&state, claims(&alice, Some("team-a")), Some("team-b")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+
+        /* Without a claim, a known user stays in their own workspace. */
+        let user = jwt_user(&state, claims(&alice, None), None).await.unwrap();
+        assert_eq!(user.tenant, "team-a");
+        let result = jwt_user(&state, claims(&alice, None), Some("team-b")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+
+        /* A user we don't know only gets the default workspace. */
+        let user = jwt_user(&state, claims("someone-else", None), None).await.unwrap();
+        assert_eq!(user.tenant, "default");
+        let result = jwt_user(&state, claims("someone-else", None), Some("team-a")).await;
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn sessions_expire() {
+        let sessions = SessionStore {
+            ttl: Duration::from_millis(50),
+            ..SessionStore::from_env()
+        };
+        let token = sessions.create("alice").await;
+        assert_eq!(sessions.user(&token).await.as_deref(), Some("alice"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(sessions.user(&token).await, None);
+    }
+
+    #[test]
+    fn dummy_hash_is_a_real_hash() {
+        assert!(argon2::PasswordHash::new(dummy_hash()).is_ok());
+        assert!(!verify_password("password123", dummy_hash()));
+    }
+}
@@ -0,0 +1,196 @@
+/* This file holds the logic for "B can't start until A is done" relationships between todos. Each todo stores the IDs it is waiting on in 'blockedBy'; everything else ('blocks', 'blocked', the planning order) is worked out from those edges here, so the handlers never have to walk the graph themselves. */
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use chrono::Utc;
+
+use crate::model::Todo;
+
+/* Returns true if making 'todo_id' wait on 'blocker_id' would close a loop. That happens when the blocker is already (directly or through other todos) waiting on 'todo_id'. */
+pub fn creates_cycle(todos: &[Todo], todo_id: &str, blocker_id: &str) -> bool {
+    if todo_id == blocker_id {
+        return true;
+    }
+
+    let mut stack = vec![blocker_id.to_string()];
+    let mut seen = HashSet::new();
+        // Depth-first walk along 'blockedBy' edges starting from the blocker. 'seen' stops us looping forever on bad data.
+    while let Some(current) = stack.pop() {
+        if current == todo_id {
+            return true;
+        }
+        if !seen.insert(current.clone()) {
+            continue;
+        }
+        if let Some(todo) = todos.iter().find(|todo| todo.id.as_deref() == Some(current.as_str())) {
+            stack.extend(todo.blockedBy.iter().cloned());
+        }
+    }
+
+    false
+}
+
+/* True while at least one of the todos this one waits on exists, isn't in the trash, and is not completed yet. */
+pub fn is_blocked(todos: &[Todo], todo: &Todo) -> bool {
+    todo.blockedBy.iter().any(|blocker_id| {
+        todos
+            .iter()
+            .find(|other| other.id.as_deref() == Some(blocker_id.as_str()))
+            .map(|other| other.deletedAt.is_none() && !other.completed.unwrap_or(false))
+            .unwrap_or(false)
+    })
+}
+
+/* Returns a copy of the todo with the computed 'blocks' and 'blocked' fields filled in, ready to be sent to the client. Trashed todos don't count as waiting on anything. */
+pub fn annotate(todos: &[Todo], todo: &Todo) -> Todo {
+    let mut todo = todo.clone();
+    todo.blocks = todos
+        .iter()
+        .filter(|other| other.deletedAt.is_none())
+        .filter(|other| {
+            todo.id
+                .as_ref()
+                .map(|id| other.blockedBy.contains(id))
+                .unwrap_or(false)
+        })
+        .filter_map(|other| other.id.clone())
+        .collect();
+    todo.blocked = is_blocked(todos, &todo);
+    todo
+}
+
+/* Works out how to remove every reference to a todo that is being purged, so nothing stays blocked by a todo that no longer exists. Returns each todo that has to change, before and after; the caller records them (see trash.rs). */
+pub fn remove_references(todos: &[Todo], id: &str) -> Vec<(Todo, Todo)> {
+    let now = Utc::now();
+    todos
+        .iter()
+        .filter(|todo| todo.blockedBy.iter().any(|blocker_id| blocker_id == id))
+        .map(|todo| {
+            let mut after = todo.clone();
+            after.blockedBy.retain(|blocker_id| blocker_id != id);
+            after.updatedAt = Some(now);
+            (todo.clone(), after)
+        })
+        .collect()
+}
+
+/* Orders the todos so every todo comes after all of the todos it is blocked by (Kahn's algorithm). Todos with no ordering between them keep their insertion order, so the result is stable between calls. */
+pub fn topological_order(todos: &[Todo]) -> Vec<Todo> {
+    let ids: HashSet<&str> = todos.iter().filter_map(|todo| todo.id.as_deref()).collect();
+
+    /* Counts how many existing todos each todo is still waiting on. */
+    let mut waiting_on: HashMap<&str, usize> = HashMap::new();
+    for todo in todos {
+        if let Some(id) = todo.id.as_deref() {
+            let count = todo
+                .blockedBy
+                .iter()
+                .filter(|blocker_id| ids.contains(blocker_id.as_str()))
+                .count();
+            waiting_on.insert(id, count);
+        }
+    }
+
+    let mut ready: VecDeque<&Todo> = todos
+        .iter()
+        .filter(|todo| todo.id.as_deref().map(|id| waiting_on[id] == 0).unwrap_or(false))
+        .collect();
+    let mut ordered = Vec::with_capacity(todos.len());
+
+    while let Some(todo) = ready.pop_front() {
+        ordered.push(annotate(todos, todo));
+        let id = todo.id.as_deref().unwrap_or_default();
+
+        /* Every todo waiting on the one we just placed has one fewer blocker left. */
+        for next in todos.iter().filter(|other| other.blockedBy.iter().any(|b| b == id)) {
+            if let Some(next_id) = next.id.as_deref() {
+                let count = waiting_on.get_mut(next_id).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.push_back(next);
+                }
+            }
+        }
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::testing::{app, create_todo, login, send};
+
+    /* Creates todos with the given titles and returns their IDs. */
+    async fn todos(router: &axum::Router, auth: &str, titles: &[&str]) -> Vec<String> {
+        let mut ids = Vec::new();
+        for title in titles {
+            let todo = create_todo(router, auth, json!({ "title": title, "content": "x" })).await;
+            ids.push(todo["id"].as_str().unwrap().to_string());
+        }
+        ids
+    }
+
+    /* Makes 'todo' wait on 'blocker' and returns the status. */
+    async fn block(router: &axum::Router, auth: &str, todo: &str, blocker: &str) -> StatusCode {
+        let uri = format!("/api/todos/{}/dependencies", todo);
+        send(router, Method::POST, &uri, &[("authorization", auth)], json!({ "blockedBy": blocker })).await.0
+    }
+
+    #[tokio::test]
+    async fn cycles_are_rejected() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let ids = todos(&router, &alice, &["a", "b", "c"]).await;
+
+        assert_eq!(block(&router, &alice, &ids[1], &ids[0]).await, StatusCode::OK);
+        assert_eq!(block(&router, &alice, &ids[2], &ids[1]).await, StatusCode::OK);
+        assert_eq!(block(&router, &alice, &ids[0], &ids[2]).await, StatusCode::CONFLICT);
+        assert_eq!(block(&router, &alice, &ids[0], &ids[0]).await, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn a_blocked_todo_cant_be_completed() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+        let ids = todos(&router, &alice, &["Foundation", "Walls"]).await;
+        assert_eq!(block(&router, &alice, &ids[1], &ids[0]).await, StatusCode::OK);
+
+        /* Walls can be worked on, but not finished before the foundation. */
+        let walls = format!("/api/todos/{}", ids[1]);
+        for column in ["in_progress", "review"] {
+            let (status, _) = send(&router, Method::PATCH, &walls, &auth, json!({ "status": column })).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, body) = send(&router, Method::PATCH, &walls, &auth, json!({ "status": "done" })).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(body["message"].as_str().unwrap().contains("blocked"));
+
+        let foundation = format!("/api/todos/{}", ids[0]);
+        for column in ["in_progress", "review", "done"] {
+            let (status, _) = send(&router, Method::PATCH, &foundation, &auth, json!({ "status": column })).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, body) = send(&router, Method::PATCH, &walls, &auth, json!({ "status": "done" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["todo"]["completed"], true);
+    }
+
+    #[tokio::test]
+    async fn the_order_puts_blockers_first() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let ids = todos(&router, &alice, &["Roof", "Walls", "Foundation", "Garden"]).await;
+        assert_eq!(block(&router, &alice, &ids[0], &ids[1]).await, StatusCode::OK);
+        assert_eq!(block(&router, &alice, &ids[1], &ids[2]).await, StatusCode::OK);
+
+        let (status, body) = send(&router, Method::GET, "/api/todos/order", &[("authorization", alice.as_str())], Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let titles: Vec<&str> = body["todos"].as_array().unwrap().iter().map(|todo| todo["title"].as_str().unwrap()).collect();
+        assert_eq!(titles, ["Foundation", "Garden", "Walls", "Roof"]);
+            // Foundation and Garden wait on nothing and keep the order they were created in; each of the others follows its blocker.
+    }
+}
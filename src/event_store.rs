@@ -0,0 +1,256 @@
+/* This file adds an event-sourced storage mode (STORAGE_MODE=events). Every change is kept in an append-only event log, and each workspace's todo list is a projection of it that can be rebuilt at any time or queried as it was in the past ('?as_of='). */
+
+use chrono::prelude::*;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::model::Todo;
+
+/* The event names are part of the API, so they keep their "Todo" prefix. */
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum TodoEventType {
+    TodoCreated,
+        // A todo was created (or a purged one brought back by undo).
+    TodoUpdated,
+        // Any other change, including restoring from the trash.
+    TodoCompleted,
+    TodoDeleted,
+        // Moved to the trash, or purged for good.
+}
+
+/* One event in the log. 'sequence' numbers every event, across all workspaces, in the order they happened. */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct TodoEvent {
+    pub sequence: u64,
+    #[serde(rename = "type")]
+    pub kind: TodoEventType,
+    pub tenantId: String,
+    pub todoId: String,
+    pub actorId: String,
+    pub at: DateTime<Utc>,
+    pub todo: Option<Todo>,
+        // The todo after the event; None once it has been purged.
+}
+
+/* What a rebuild did: how many events were replayed, how many todos the new projection has, and how many of them differed from the projection it replaced (0 unless something changed the todo list without recording an event). */
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Rebuild {
+    pub events: usize,
+    pub todos: usize,
+    pub changed: usize,
+}
+
+/* The event log. Empty, and never written to, unless the event-sourced mode is on. It is kept in memory and has no limit: every change adds an event and nothing is ever compacted or snapshotted, because rebuilds and '?as_of=' replay from the first event. Memory grows with the number of changes until the server restarts (which also loses the log), so this mode suits development and auditing rather than long-running busy servers. */
+pub struct EventStore {
+    enabled: bool,
+    events: Mutex<Vec<TodoEvent>>,
+}
+
+impl EventStore {
+    /* Reads STORAGE_MODE: "events" turns the event log on; anything else (or nothing) keeps the plain in-memory list. */
+    pub fn from_env() -> Self {
+        let mode = std::env::var("STORAGE_MODE").unwrap_or_default();
+        EventStore {
+            enabled: mode.eq_ignore_ascii_case("events"),
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    /* An event log that is switched on whatever STORAGE_MODE says. */
+    #[cfg(test)]
+    pub fn recording() -> Self {
+        EventStore {
+            enabled: true,
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    /* Appends the event for one change, given the todo before and after it. */
+    pub async fn append(&self, tenant: &str, actor_id: &str, before: Option<&Todo>, after: Option<&Todo>) {
+        if !self.enabled {
+            return;
+        }
+        let Some(todo_id) = after.or(before).and_then(|todo| todo.id.clone()) else {
+            return;
+        };
+
+        let mut events = self.events.lock().await;
+        let sequence = events.last().map(|event| event.sequence + 1).unwrap_or(1);
+        events.push(TodoEvent {
+            sequence,
+            kind: event_type(before, after),
+            tenantId: tenant.to_string(),
+            todoId: todo_id,
+            actorId: actor_id.to_string(),
+            at: Utc::now(),
+            todo: after.cloned(),
+        });
+    }
+
+    /* Replays a workspace's events up to and including 'as_of' (all of them if None) into its todo list as it was then. Returns None when the event log is off. */
+    pub async fn project(&self, tenant: &str, as_of: Option<DateTime<Utc>>) -> Option<Vec<Todo>> {
+        if !self.enabled {
+            return None;
+        }
+        let events = self.events.lock().await;
+        let mut todos = Vec::new();
+        for event in events.iter().filter(|event| event.tenantId == tenant) {
+            if as_of.is_some_and(|as_of| event.at > as_of) {
+                break;
+                    // Events are in time order, so nothing after this one counts either.
+            }
+            apply(&mut todos, &event.todoId, event.todo.as_ref());
+        }
+        Some(todos)
+    }
+
+    /* Throws away a workspace's todo list ('todos') and replaces it with a fresh projection of the log. Returns None when the event log is off. */
+    pub async fn rebuild(&self, tenant: &str, todos: &mut Vec<Todo>) -> Option<Rebuild> {
+        let projection = self.project(tenant, None).await?;
+        let events = self.events.lock().await.iter().filter(|event| event.tenantId == tenant).count();
+
+        let as_json = |todo: &Todo| serde_json::to_value(todo).unwrap_or_default();
+        let changed = projection
+            .iter()
+            .filter(|todo| {
+                todos
+                    .iter()
+                    .find(|old| old.id == todo.id)
+                    .is_none_or(|old| as_json(old) != as_json(todo))
+            })
+            .count()
+            + todos
+                .iter()
+                .filter(|old| !projection.iter().any(|todo| todo.id == old.id))
+                .count();
+            // Todos that differ or are new, plus todos the log says shouldn't exist.
+
+        *todos = projection;
+        Some(Rebuild {
+            events,
+            todos: todos.len(),
+            changed,
+        })
+    }
+}
+
+/* Names the change from the todo before and after it. */
+fn event_type(before: Option<&Todo>, after: Option<&Todo>) -> TodoEventType {
+    match (before, after) {
+        (None, _) => TodoEventType::TodoCreated,
+        (_, None) => TodoEventType::TodoDeleted,
+        (Some(before), Some(after)) if after.deletedAt.is_some() && before.deletedAt.is_none() => TodoEventType::TodoDeleted,
+        (Some(before), Some(after)) if after.completed == Some(true) && before.completed != Some(true) => {
+            TodoEventType::TodoCompleted
+        }
+        _ => TodoEventType::TodoUpdated,
+    }
+}
+
+/* Applies one event to a projection: the todo is replaced by its new version, added if it's new, or removed if it was purged. The Auditor applies every change to the live list with this too, whether or not the log is on (see audit.rs). */
+pub fn apply(todos: &mut Vec<Todo>, todo_id: &str, todo: Option<&Todo>) {
+    let pos = todos.iter().position(|todo| todo.id.as_deref() == Some(todo_id));
+    match (pos, todo) {
+        (Some(pos), Some(todo)) => todos[pos] = todo.clone(),
+        (None, Some(todo)) => todos.push(todo.clone()),
+        (Some(pos), None) => {
+            todos.remove(pos);
+        }
+        (None, None) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use super::EventStore;
+    use crate::{
+        model::AppState,
+        tenant::TenantConfig,
+        testing::{app_with, create_todo, login, send},
+    };
+
+    /* A router with the event log on, and Alice as the admin of the default workspace. */
+    fn app() -> (axum::Router, AppState) {
+        let mut state = AppState::for_tests();
+        state.event_store = Arc::new(EventStore::recording());
+        state.tenants = Arc::new(TenantConfig::with_admin("default", "alice"));
+        app_with(state)
+    }
+
+    #[tokio::test]
+    async fn only_admins_can_rebuild() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let bob = login(&router, "default", "bob").await;
+        let (status, _) = send(&router, Method::POST, "/api/events/rebuild", &[("authorization", bob.as_str())], Value::Null).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&router, Method::POST, "/api/events/rebuild", &[("authorization", alice.as_str())], Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+
+        /* Being an admin of one workspace gives nothing in another. */
+        let other_alice = login(&router, "other", "alice").await;
+        let (status, _) = send(&router, Method::POST, "/api/events/rebuild", &[("authorization", other_alice.as_str())], Value::Null).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn rebuilding_gives_back_the_live_list() {
+        let (router, state) = app();
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+        let mut ids = Vec::new();
+        for title in ["a", "b", "c"] {
+            let todo = create_todo(&router, &alice, json!({ "title": title, "content": "x" })).await;
+            ids.push(todo["id"].as_str().unwrap().to_string());
+        }
+
+        /* 'c' waits on 'a'. */
+        let uri = format!("/api/todos/{}/dependencies", ids[2]);
+        let (status, _) = send(&router, Method::POST, &uri, &auth, json!({ "blockedBy": ids[0] })).await;
+        assert_eq!(status, StatusCode::OK);
+
+        /* Enough reordering to rebalance the list (see position.rs). */
+        for round in 0..100 {
+            let (moved, anchor) = if round % 2 == 0 { (&ids[2], &ids[1]) } else { (&ids[1], &ids[2]) };
+            let uri = format!("/api/todos/{}/move", moved);
+            let (status, _) = send(&router, Method::POST, &uri, &auth, json!({ "before": anchor })).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let live_after_reorder = serde_json::to_value(&state.db.lock().await["default"]).unwrap();
+        let (status, body) = send(&router, Method::POST, "/api/events/rebuild", &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["changed"], 0);
+        assert_eq!(serde_json::to_value(&state.db.lock().await["default"]).unwrap(), live_after_reorder);
+
+        /* Purging 'a' drops the dependency on it; undoing the purge brings both back. */
+        for (method, uri) in [
+            (Method::DELETE, format!("/api/todos/{}", ids[0])),
+            (Method::DELETE, format!("/api/trash/{}", ids[0])),
+        ] {
+            let (status, _) = send(&router, method, &uri, &auth, Value::Null).await;
+            assert_eq!(status, StatusCode::NO_CONTENT);
+        }
+        let blocked_by = |todos: &[crate::model::Todo]| {
+            todos.iter().find(|todo| todo.id.as_deref() == Some(ids[2].as_str())).unwrap().blockedBy.clone()
+        };
+        assert!(blocked_by(&state.db.lock().await["default"]).is_empty());
+        let live_after_purge = serde_json::to_value(&state.db.lock().await["default"]).unwrap();
+        let (status, body) = send(&router, Method::POST, "/api/events/rebuild", &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["changed"], 0);
+        assert_eq!(serde_json::to_value(&state.db.lock().await["default"]).unwrap(), live_after_purge);
+
+        let (status, body) = send(&router, Method::POST, "/api/undo", &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(blocked_by(&state.db.lock().await["default"]), vec![ids[0].clone()]);
+        let (_, body) = send(&router, Method::POST, "/api/events/rebuild", &auth, Value::Null).await;
+        assert_eq!(body["data"]["changed"], 0);
+    }
+}
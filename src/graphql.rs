@@ -0,0 +1,388 @@
+/* This file serves the same todos over GraphQL ("POST /graphql", with subscriptions on "/graphql/ws"). The resolvers call the same helpers as the REST handlers, so the rules work the same whichever API made a change. */
+
+use std::cmp::Ordering;
+
+use async_graphql::{
+    http::{WebSocket as GraphQLWebSocket, WebSocketProtocols, WsMessage},
+    Context, Data, Enum, Error, ErrorExtensions, InputObject, Object, Result, Schema, SimpleObject, Subscription, ID,
+};
+use axum::{
+    extract::ws::{CloseFrame, Message, WebSocket},
+    http::StatusCode,
+    Json,
+};
+use chrono::prelude::*;
+use futures_util::{future, stream, SinkExt, Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{
+    audit::Auditor,
+    auth::CurrentUser,
+    dependency,
+    handler::{apply_edit, authorize, authorize_including_trash, insert_todo, trash_todo},
+    live::{self, ChangeKind, LiveFilter},
+    model::{AppState, CreateTodoSchema, Todo, UpdateTodoSchema},
+    policy::{self, Action},
+    position,
+    response::TodoData,
+};
+
+/* The deepest a query may nest. Todos don't link to each other in the schema, so real queries never come close; it only stops abusive ones. */
+const MAX_DEPTH: usize = 10;
+
+pub type TodoSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/* Builds the schema. Resolvers reach the shared state through it; the caller ('CurrentUser', and the 'Auditor' for mutations) is added to each request by the handler. */
+pub fn build_schema(state: AppState) -> TodoSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(state)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+/* Whether to serve GraphiQL: GRAPHIQL if it is set, otherwise only in debug builds. */
+pub fn graphiql_from_env() -> bool {
+    match std::env::var("GRAPHIQL") {
+        Ok(value) => value.eq_ignore_ascii_case("true"),
+        Err(_) => cfg!(debug_assertions),
+    }
+}
+
+/* Turns a REST-style error response into a GraphQL error with the same message. */
+fn graphql_error((status, Json(body)): (StatusCode, Json<serde_json::Value>)) -> Error {
+    let message = body["message"].as_str().unwrap_or("Something went wrong").to_string();
+    let code = status
+        .canonical_reason()
+        .unwrap_or("ERROR")
+        .to_uppercase()
+        .replace(' ', "_");
+    Error::new(message).extend_with(|_, extensions| {
+        extensions.set("code", code);
+        extensions.set("status", status.as_u16());
+    })
+}
+
+/* Reads a todo ID argument. Anything that isn't a UUID can't be a todo, so it gets the same error as an unknown ID. */
+fn todo_id(id: &ID) -> Result<String> {
+    Uuid::parse_str(id).map(|id| id.to_string()).map_err(|_| {
+        Error::new(format!("Todo with ID: {} not found", id.as_str())).extend_with(|_, extensions| {
+            extensions.set("code", "NOT_FOUND");
+            extensions.set("status", 404);
+        })
+    })
+}
+
+/* Narrows 'todos'. Every field that is set has to match. */
+#[derive(InputObject, Default)]
+pub struct TodoFilter {
+    list: Option<String>,
+    status: Option<String>,
+    completed: Option<bool>,
+    actionable: Option<bool>,
+        // When true, only todos that are incomplete and not blocked by another todo are listed.
+    include_deleted: Option<bool>,
+        // When true, todos in the trash are listed too.
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TodoSortField {
+    #[default]
+    Position,
+        // The user's own drag-and-drop order, as in "GET /api/todos".
+    CreatedAt,
+    UpdatedAt,
+    DueDate,
+    Title,
+}
+
+/* One page of 'todos'. 'total' counts every match, not just this page. */
+#[derive(SimpleObject)]
+pub struct TodoPage {
+    total: usize,
+    page: usize,
+    limit: usize,
+    todos: Vec<Todo>,
+}
+
+/* A change pushed to a 'todoChanges' subscription. For DELETED the todo is the last version the subscriber could see. */
+#[derive(SimpleObject)]
+pub struct TodoChangeEvent {
+    #[graphql(name = "type")]
+    kind: ChangeKind,
+    id: u64,
+    at: DateTime<Utc>,
+    actor_id: String,
+        // Fields are exposed in camelCase, so this is 'actorId' like everywhere else.
+    todo: Todo,
+}
+
+/* Orders two todos by one field. Todos without the field (no due date, say) come first. */
+fn compare(field: TodoSortField, a: &Todo, b: &Todo) -> Ordering {
+    match field {
+        TodoSortField::Position => Ordering::Equal,
+            // The list is already in position order before it is sorted by anything else.
+        TodoSortField::CreatedAt => a.createdAt.cmp(&b.createdAt),
+        TodoSortField::UpdatedAt => a.updatedAt.cmp(&b.updatedAt),
+        TodoSortField::DueDate => a.dueDate.cmp(&b.dueDate),
+        TodoSortField::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// One todo by ID, if the caller can see it. Todos in the trash are only returned with includeDeleted.
+    async fn todo(&self, ctx: &Context<'_>, id: ID, #[graphql(default)] include_deleted: bool) -> Result<Todo> {
+        let state = ctx.data::<AppState>()?;
+        let user = ctx.data::<CurrentUser>()?;
+        let id = todo_id(&id)?;
+
+        let list_shares = state.list_shares.lock().await.clone();
+        let mut store = state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+        let pos = authorize_including_trash(vec, &id, user, &list_shares, Action::View, include_deleted)
+            .map_err(graphql_error)?;
+        Ok(dependency::annotate(vec, &vec[pos]))
+    }
+
+    /// The todos the caller can see, filtered, sorted and split into pages (page 1 is the first).
+    async fn todos(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TodoFilter>,
+        #[graphql(default)] sort_by: TodoSortField,
+        #[graphql(default)] descending: bool,
+        #[graphql(default = 1)] page: usize,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<TodoPage> {
+        let state = ctx.data::<AppState>()?;
+        let user = ctx.data::<CurrentUser>()?;
+        let filter = filter.unwrap_or_default();
+        let page = page.max(1);
+
+        let list_shares = state.list_shares.lock().await.clone();
+        let mut store = state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+
+        let mut ordered = vec.clone();
+        position::sort(&mut ordered);
+        let mut todos: Vec<Todo> = ordered
+            .iter()
+            .filter(|todo| policy::role_for(&user.id, todo, &list_shares).is_some())
+            .filter(|todo| filter.include_deleted.unwrap_or(false) || todo.deletedAt.is_none())
+            .filter(|todo| filter.list.is_none() || todo.list == filter.list)
+            .filter(|todo| filter.status.is_none() || todo.status == filter.status)
+            .filter(|todo| filter.completed.is_none_or(|completed| todo.completed.unwrap_or(false) == completed))
+            .map(|todo| dependency::annotate(vec, todo))
+            .filter(|todo| !filter.actionable.unwrap_or(false) || (!todo.completed.unwrap_or(false) && !todo.blocked))
+            .collect();
+            // The same visibility rules as "GET /api/todos", with a few more filters.
+
+        todos.sort_by(|a, b| match descending {
+            false => compare(sort_by, a, b),
+            true => compare(sort_by, b, a),
+        });
+            // A stable sort, so todos that tie stay in position order.
+
+        Ok(TodoPage {
+            total: todos.len(),
+            page,
+            limit,
+            todos: todos.into_iter().skip((page - 1) * limit).take(limit).collect(),
+        })
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Creates a todo, with the same checks as "POST /api/todos".
+    async fn create_todo(&self, ctx: &Context<'_>, input: CreateTodoSchema) -> Result<Todo> {
+        let state = ctx.data::<AppState>()?;
+        let user = ctx.data::<CurrentUser>()?;
+        let audit = ctx.data::<Auditor>()?;
+
+        let mut store = state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+        let todo = insert_todo(vec, Uuid::new_v4(), &state.workflow, &state.tenants, user, audit, input)
+            .await
+            .map_err(graphql_error)?;
+        Ok(todo)
+    }
+
+    /// Changes a todo, like "PATCH /api/todos/:id". Only the fields given are changed. 'next' is the new occurrence when completing a recurring todo scheduled one.
+    async fn edit_todo(&self, ctx: &Context<'_>, id: ID, input: UpdateTodoSchema) -> Result<TodoData> {
+        let state = ctx.data::<AppState>()?;
+        let user = ctx.data::<CurrentUser>()?;
+        let audit = ctx.data::<Auditor>()?;
+        let id = todo_id(&id)?;
+
+        let list_shares = state.list_shares.lock().await.clone();
+        let mut store = state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+        let pos = authorize(vec, &id, user, &list_shares, Action::Edit).map_err(graphql_error)?;
+        let (todo, next) = apply_edit(vec, pos, &state.workflow, user, audit, input)
+            .await
+            .map_err(graphql_error)?;
+        Ok(TodoData {
+            todo: dependency::annotate(vec, &todo),
+            next: next.map(|next| dependency::annotate(vec, &next)),
+        })
+    }
+
+    /// Moves a todo to the trash, like "DELETE /api/todos/:id". Returns its ID.
+    async fn delete_todo(&self, ctx: &Context<'_>, id: ID) -> Result<ID> {
+        let state = ctx.data::<AppState>()?;
+        let user = ctx.data::<CurrentUser>()?;
+        let audit = ctx.data::<Auditor>()?;
+        let todo_id = todo_id(&id)?;
+
+        let list_shares = state.list_shares.lock().await.clone();
+        let mut store = state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+        let pos = authorize(vec, &todo_id, user, &list_shares, Action::Delete).map_err(graphql_error)?;
+        trash_todo(vec, pos, user, audit).await;
+        Ok(id)
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Todo changes as they happen, narrowed by the filter. A subscriber that falls too far behind gets an error saying how many changes it missed, and should fetch the todos again.
+    async fn todo_changes(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<LiveFilter>,
+    ) -> Result<impl Stream<Item = Result<TodoChangeEvent>>> {
+        let state = ctx.data::<AppState>()?.clone();
+        let user = ctx.data::<CurrentUser>()?.clone();
+        let receiver = state.events.subscribe();
+        let filter = filter.unwrap_or_default();
+
+        Ok(stream::unfold(
+            (receiver, state, user, filter),
+            |(mut receiver, state, user, filter)| async move {
+                loop {
+                    let item = match receiver.recv().await {
+                        Ok(change) => {
+                            let list_shares = state.list_shares.lock().await.clone();
+                            let Some(event) = live::event_for(&change, &user, &filter, &list_shares) else {
+                                continue;
+                                    // Not something this subscriber can see.
+                            };
+                            Ok(TodoChangeEvent {
+                                kind: event.kind,
+                                id: event.id,
+                                at: event.at,
+                                actor_id: event.actorId.to_string(),
+                                todo: event.todo.clone(),
+                            })
+                        }
+                        Err(RecvError::Lagged(missed)) => {
+                            Err(Error::new(format!("Missed {} changes; fetch the todos again", missed)))
+                        }
+                        Err(RecvError::Closed) => return None,
+                    };
+                    return Some((item, (receiver, state, user, filter)));
+                }
+            },
+        ))
+    }
+}
+
+/* Runs one "/graphql/ws" connection: messages from the client go into async-graphql's protocol handler, and what it answers goes back out. The caller was logged in when the connection was opened. */
+pub async fn serve(socket: WebSocket, schema: TodoSchema, protocol: WebSocketProtocols, user: CurrentUser) {
+    let (mut sink, source) = socket.split();
+    let incoming = source
+        .take_while(|message| future::ready(message.is_ok()))
+        .filter_map(|message| {
+            future::ready(match message {
+                Ok(Message::Text(text)) => Some(text.into_bytes()),
+                Ok(Message::Binary(bytes)) => Some(bytes),
+                _ => None,
+            })
+        });
+        // Pings are answered by axum itself, and a read error ends the connection.
+
+    let mut data = Data::default();
+    data.insert(user);
+    let mut outgoing = GraphQLWebSocket::new(schema, incoming, protocol).connection_data(data);
+
+    while let Some(message) = outgoing.next().await {
+        let message = match message {
+            WsMessage::Text(text) => Message::Text(text),
+            WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                code,
+                reason: reason.into(),
+            })),
+        };
+        if sink.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::testing::{app, login, send};
+
+    /* Runs a GraphQL query as the given user and returns the GraphQL response. */
+    async fn graphql(router: &axum::Router, auth: &str, query: &str, variables: Value) -> Value {
+        let body = json!({ "query": query, "variables": variables });
+        let (status, response) = send(router, Method::POST, "/graphql", &[("authorization", auth)], body).await;
+        assert_eq!(status, StatusCode::OK);
+        response
+    }
+
+    const CREATE: &str = "mutation ($title: String!) { createTodo(input: { title: $title, content: \"a\" }) { id title } }";
+
+    #[tokio::test]
+    async fn mutations_and_queries_follow_the_rest_rules() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+
+        let mut ids = Vec::new();
+        for title in ["Banana", "apple", "Cherry"] {
+            let response = graphql(&router, &alice, CREATE, json!({ "title": title })).await;
+            ids.push(response["data"]["createTodo"]["id"].as_str().unwrap().to_string());
+        }
+
+        /* A duplicate title is a conflict, as in REST. */
+        let response = graphql(&router, &alice, CREATE, json!({ "title": "apple" })).await;
+        assert_eq!(response["errors"][0]["extensions"]["status"], 409);
+
+        /* Sorted by title, two to a page. */
+        let query = "{ todos(sortBy: TITLE, page: 2, limit: 2) { total page todos { title } } }";
+        let response = graphql(&router, &alice, query, Value::Null).await;
+        assert_eq!(response["data"]["todos"]["total"], 3);
+        assert_eq!(response["data"]["todos"]["todos"], json!([{ "title": "Cherry" }]));
+
+        /* Editing, then deleting: the trashed todo is only found with includeDeleted. */
+        let edit = "mutation ($id: ID!) { editTodo(id: $id, input: { title: \"Banana split\" }) { todo { title } } }";
+        let response = graphql(&router, &alice, edit, json!({ "id": ids[0] })).await;
+        assert_eq!(response["data"]["editTodo"]["todo"]["title"], "Banana split");
+        let delete = "mutation ($id: ID!) { deleteTodo(id: $id) }";
+        let response = graphql(&router, &alice, delete, json!({ "id": ids[0] })).await;
+        assert_eq!(response["data"]["deleteTodo"], ids[0].as_str());
+        let find = "query ($id: ID!, $trash: Boolean!) { todo(id: $id, includeDeleted: $trash) { id } }";
+        let response = graphql(&router, &alice, find, json!({ "id": ids[0], "trash": false })).await;
+        assert_eq!(response["errors"][0]["extensions"]["code"], "NOT_FOUND");
+        let response = graphql(&router, &alice, find, json!({ "id": ids[0], "trash": true })).await;
+        assert_eq!(response["data"]["todo"]["id"], ids[0].as_str());
+
+        /* Another user sees none of it, and nobody gets in without logging in. */
+        let bob = login(&router, "default", "bob").await;
+        let response = graphql(&router, &bob, find, json!({ "id": ids[1], "trash": false })).await;
+        assert_eq!(response["errors"][0]["extensions"]["status"], 404);
+        let (status, _) = send(&router, Method::POST, "/graphql", &[], json!({ "query": "{ todos { total } }" })).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
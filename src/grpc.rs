@@ -0,0 +1,574 @@
+/* This file serves the todos over gRPC (see proto/todo.proto) on its own port, for backend services. It uses the same helpers as the REST handlers, and callers log in with the same headers, sent as gRPC metadata. */
+
+/* tonic's 'Status' is large, but it is what every gRPC method has to return, so the helpers return it too. */
+#![allow(clippy::result_large_err)]
+
+use std::{net::SocketAddr, pin::Pin};
+
+use axum::{
+    body::to_bytes,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::AUTHORIZATION, Method, StatusCode},
+    response::Response as HttpResponse,
+    Json,
+};
+use chrono::prelude::*;
+use futures_util::{stream, Stream};
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{transport::Server, Code, Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    audit::{Auditor, RequestId, REQUEST_ID_HEADER},
+    auth::CurrentUser,
+    dependency,
+    handler::{apply_edit, authorize, authorize_including_trash, insert_todo, trash_todo},
+    live::{self, ChangeKind, LiveFilter},
+    model::{self, AppState, CreateTodoSchema, UpdateTodoSchema},
+    policy::{self, Action},
+    position,
+};
+
+/* The server code generated from the service definition (see build.rs). */
+mod proto {
+    include!(concat!(env!("OUT_DIR"), "/todo.v1.TodoService.rs"));
+}
+use proto::todo_service_server::{TodoService, TodoServiceServer};
+
+/* The messages from proto/todo.proto, field for field. */
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Todo {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub title: String,
+    #[prost(string, tag = "3")]
+    pub content: String,
+    #[prost(bool, tag = "4")]
+    pub completed: bool,
+    #[prost(string, tag = "5")]
+    pub status: String,
+    #[prost(string, tag = "6")]
+    pub created_at: String,
+    #[prost(string, tag = "7")]
+    pub updated_at: String,
+    #[prost(string, optional, tag = "8")]
+    pub due_date: Option<String>,
+    #[prost(string, optional, tag = "9")]
+    pub recurrence: Option<String>,
+    #[prost(string, optional, tag = "10")]
+    pub list: Option<String>,
+    #[prost(string, tag = "11")]
+    pub position: String,
+    #[prost(string, tag = "12")]
+    pub owner_id: String,
+    #[prost(string, repeated, tag = "13")]
+    pub blocked_by: Vec<String>,
+    #[prost(string, repeated, tag = "14")]
+    pub blocks: Vec<String>,
+    #[prost(bool, tag = "15")]
+    pub blocked: bool,
+    #[prost(string, optional, tag = "16")]
+    pub series_id: Option<String>,
+    #[prost(uint32, optional, tag = "17")]
+    pub occurrence: Option<u32>,
+    #[prost(string, optional, tag = "18")]
+    pub deleted_at: Option<String>,
+    #[prost(string, repeated, tag = "19")]
+    pub tags: Vec<String>,
+}
+
+/* Wraps a list of tags, so an update can tell "leave them alone" (not set) from "remove them all" (an empty list). */
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Tags {
+    #[prost(string, repeated, tag = "1")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetTodoRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(bool, tag = "2")]
+    pub include_deleted: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListTodosRequest {
+    #[prost(uint32, tag = "1")]
+    pub page: u32,
+    #[prost(uint32, tag = "2")]
+    pub limit: u32,
+    #[prost(string, optional, tag = "3")]
+    pub list: Option<String>,
+    #[prost(bool, tag = "4")]
+    pub actionable: bool,
+    #[prost(bool, tag = "5")]
+    pub include_deleted: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListTodosResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub todos: Vec<Todo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CreateTodoRequest {
+    #[prost(string, tag = "1")]
+    pub title: String,
+    #[prost(string, tag = "2")]
+    pub content: String,
+    #[prost(string, optional, tag = "3")]
+    pub status: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub due_date: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub recurrence: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub list: Option<String>,
+    #[prost(string, repeated, tag = "7")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UpdateTodoRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, optional, tag = "2")]
+    pub title: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub content: Option<String>,
+    #[prost(bool, optional, tag = "4")]
+    pub completed: Option<bool>,
+    #[prost(string, optional, tag = "5")]
+    pub status: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub due_date: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub recurrence: Option<String>,
+    #[prost(string, optional, tag = "8")]
+    pub list: Option<String>,
+    #[prost(message, optional, tag = "9")]
+    pub tags: Option<Tags>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UpdateTodoResponse {
+    #[prost(message, optional, tag = "1")]
+    pub todo: Option<Todo>,
+    #[prost(message, optional, tag = "2")]
+    pub next: Option<Todo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteTodoRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteTodoResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchRequest {
+    #[prost(string, optional, tag = "1")]
+    pub list: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub status: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub owner: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub tag: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TodoEvent {
+    #[prost(enumeration = "EventKind", tag = "1")]
+    pub kind: i32,
+    #[prost(uint64, tag = "2")]
+    pub id: u64,
+    #[prost(string, tag = "3")]
+    pub at: String,
+    #[prost(string, tag = "4")]
+    pub actor_id: String,
+    #[prost(message, optional, tag = "5")]
+    pub todo: Option<Todo>,
+    #[prost(uint64, tag = "6")]
+    pub missed: u64,
+}
+
+/* TodoEvent.Kind in the .proto. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum EventKind {
+    Unspecified = 0,
+    Created = 1,
+    Updated = 2,
+    Deleted = 3,
+    Resync = 4,
+}
+
+/* Converts a stored todo into its message. Missing times and keys become empty strings, as proto3 has no "unset" for plain strings. */
+fn to_message(todo: &model::Todo) -> Todo {
+    let time = |time: Option<DateTime<Utc>>| time.map(|time| time.to_rfc3339());
+    Todo {
+        id: todo.id.clone().unwrap_or_default(),
+        title: todo.title.clone(),
+        content: todo.content.clone(),
+        completed: todo.completed.unwrap_or(false),
+        status: todo.status.clone().unwrap_or_default(),
+        created_at: time(todo.createdAt).unwrap_or_default(),
+        updated_at: time(todo.updatedAt).unwrap_or_default(),
+        due_date: time(todo.dueDate),
+        recurrence: todo.recurrence.clone(),
+        list: todo.list.clone(),
+        position: todo.position.clone().unwrap_or_default(),
+        owner_id: todo.ownerId.clone().unwrap_or_default(),
+        blocked_by: todo.blockedBy.clone(),
+        blocks: todo.blocks.clone(),
+        blocked: todo.blocked,
+        series_id: todo.seriesId.clone(),
+        occurrence: todo.occurrence,
+        deleted_at: time(todo.deletedAt),
+        tags: todo.tags.clone(),
+    }
+}
+
+/* Reads an optional RFC 3339 time from a request. */
+fn parse_time(field: &str, value: Option<String>) -> Result<Option<DateTime<Utc>>, Status> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(&value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|_| Status::invalid_argument(format!("'{}' must be an RFC 3339 time", field)))
+        })
+        .transpose()
+}
+
+/* Reads a todo ID from a request. Anything that isn't a UUID can't be a todo, so it gets the same error as an unknown ID. */
+fn todo_id(id: &str) -> Result<String, Status> {
+    Uuid::parse_str(id)
+        .map(|id| id.to_string())
+        .map_err(|_| Status::not_found(format!("Todo with ID: {} not found", id)))
+}
+
+/* Turns a REST-style error from the shared helpers into a gRPC status with the same message. A 403 or 409 means different things in different calls (a full workspace or a forbidden change; a duplicate title or a disallowed move), so the caller says which code each one gets. */
+fn grpc_status(
+    (status, Json(body)): (StatusCode, Json<serde_json::Value>),
+    forbidden: Code,
+    conflict: Code,
+) -> Status {
+    let message = body["message"].as_str().unwrap_or("Something went wrong").to_string();
+    let code = match status {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => forbidden,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::CONFLICT => conflict,
+        _ => Code::Internal,
+    };
+    Status::new(code, message)
+}
+
+/* The same, for a rejection from the 'CurrentUser' extractor, whose message is inside the response body. */
+async fn rejection_status(response: HttpResponse) -> Status {
+    let code = match response.status() {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        _ => Code::Unauthenticated,
+    };
+    let body = to_bytes(response.into_body(), 64 * 1024).await.unwrap_or_default();
+    let message = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|body| body["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| "You are not logged in, please provide a token".to_string());
+    Status::new(code, message)
+}
+
+/* The todo service. It holds the same shared state as the Axum router. */
+pub struct TodoGrpc {
+    state: AppState,
+}
+
+impl TodoGrpc {
+    /* Works out who is calling, the same way as a REST request: the metadata is put into an HTTP request (as a GET for reads, so read-only API keys pass) and handed to the 'CurrentUser' and 'Auditor' extractors. A JWT is verified first, as 'jwt_middleware' would. */
+    async fn caller<T>(&self, request: &Request<T>, write: bool) -> Result<(CurrentUser, Auditor), Status> {
+        let method = if write { Method::POST } else { Method::GET };
+        let (mut parts, ()) = axum::http::Request::builder()
+            .method(method)
+            .body(())
+            .map_err(|err| Status::internal(err.to_string()))?
+            .into_parts();
+        parts.headers = request.metadata().clone().into_headers();
+
+        let jwt = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .filter(|token| token.split('.').count() == 3);
+        if let (Some(token), Some(verifier)) = (jwt, &self.state.jwt) {
+            let claims = verifier.verify(token).await.map_err(Status::unauthenticated)?;
+            parts.extensions.insert(claims);
+        }
+
+        let request_id = parts
+            .headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= 128)
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        parts.extensions.insert(RequestId(request_id));
+        if let Some(addr) = request.remote_addr() {
+            parts.extensions.insert(ConnectInfo(addr));
+        }
+
+        let user = match CurrentUser::from_request_parts(&mut parts, &self.state).await {
+            Ok(user) => user,
+            Err(response) => return Err(rejection_status(response).await),
+        };
+        let Ok(audit) = Auditor::from_request_parts(&mut parts, &self.state).await;
+        Ok((user, audit))
+    }
+}
+
+#[tonic::async_trait]
+impl TodoService for TodoGrpc {
+    async fn get(&self, request: Request<GetTodoRequest>) -> Result<Response<Todo>, Status> {
+        let (user, _) = self.caller(&request, false).await?;
+        let request = request.into_inner();
+        let id = todo_id(&request.id)?;
+
+        let list_shares = self.state.list_shares.lock().await.clone();
+        let mut store = self.state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+        let pos = authorize_including_trash(vec, &id, &user, &list_shares, Action::View, request.include_deleted)
+            .map_err(|err| grpc_status(err, Code::PermissionDenied, Code::FailedPrecondition))?;
+        Ok(Response::new(to_message(&dependency::annotate(vec, &vec[pos]))))
+    }
+
+    async fn list(&self, request: Request<ListTodosRequest>) -> Result<Response<ListTodosResponse>, Status> {
+        let (user, _) = self.caller(&request, false).await?;
+        let request = request.into_inner();
+        let page = request.page.max(1) as usize;
+        let limit = if request.limit == 0 { 10 } else { request.limit as usize };
+
+        let list_shares = self.state.list_shares.lock().await.clone();
+        let mut store = self.state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+
+        let mut ordered = vec.clone();
+        position::sort(&mut ordered);
+        let todos = ordered
+            .iter()
+            .filter(|todo| policy::role_for(&user.id, todo, &list_shares).is_some())
+            .filter(|todo| request.list.is_none() || todo.list == request.list)
+            .filter(|todo| request.include_deleted || todo.deletedAt.is_none())
+            .map(|todo| dependency::annotate(vec, todo))
+            .filter(|todo| !request.actionable || (!todo.completed.unwrap_or(false) && !todo.blocked))
+            .skip((page - 1) * limit)
+            .take(limit)
+            .map(|todo| to_message(&todo))
+            .collect();
+            // The same todos, in the same order, as "GET /api/todos".
+        Ok(Response::new(ListTodosResponse { todos }))
+    }
+
+    async fn create(&self, request: Request<CreateTodoRequest>) -> Result<Response<Todo>, Status> {
+        let (user, audit) = self.caller(&request, true).await?;
+        let request = request.into_inner();
+        let body = CreateTodoSchema {
+            title: request.title,
+            content: request.content,
+            status: request.status,
+            dueDate: parse_time("due_date", request.due_date)?,
+            recurrence: request.recurrence,
+            list: request.list,
+            tags: Some(request.tags),
+        };
+
+        let mut store = self.state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+        let todo = insert_todo(vec, Uuid::new_v4(), &self.state.workflow, &self.state.tenants, &user, &audit, body)
+            .await
+            .map_err(|err| grpc_status(err, Code::ResourceExhausted, Code::AlreadyExists))?;
+        Ok(Response::new(to_message(&todo)))
+    }
+
+    async fn update(&self, request: Request<UpdateTodoRequest>) -> Result<Response<UpdateTodoResponse>, Status> {
+        let (user, audit) = self.caller(&request, true).await?;
+        let request = request.into_inner();
+        let id = todo_id(&request.id)?;
+        let body = UpdateTodoSchema {
+            title: request.title,
+            content: request.content,
+            completed: request.completed,
+            status: request.status,
+            dueDate: parse_time("due_date", request.due_date)?,
+            recurrence: request.recurrence,
+            list: request.list,
+            tags: request.tags.map(|tags| tags.tags),
+        };
+
+        let list_shares = self.state.list_shares.lock().await.clone();
+        let mut store = self.state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+        let to_status = |err| grpc_status(err, Code::PermissionDenied, Code::FailedPrecondition);
+        let pos = authorize(vec, &id, &user, &list_shares, Action::Edit).map_err(to_status)?;
+        let (todo, next) = apply_edit(vec, pos, &self.state.workflow, &user, &audit, body)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(UpdateTodoResponse {
+            todo: Some(to_message(&dependency::annotate(vec, &todo))),
+            next: next.map(|next| to_message(&dependency::annotate(vec, &next))),
+        }))
+    }
+
+    async fn delete(&self, request: Request<DeleteTodoRequest>) -> Result<Response<DeleteTodoResponse>, Status> {
+        let (user, audit) = self.caller(&request, true).await?;
+        let id = todo_id(&request.into_inner().id)?;
+
+        let list_shares = self.state.list_shares.lock().await.clone();
+        let mut store = self.state.db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+        let pos = authorize(vec, &id, &user, &list_shares, Action::Delete)
+            .map_err(|err| grpc_status(err, Code::PermissionDenied, Code::FailedPrecondition))?;
+        trash_todo(vec, pos, &user, &audit).await;
+        Ok(Response::new(DeleteTodoResponse {}))
+    }
+
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<TodoEvent, Status>> + Send>>;
+
+    /* Sends the same changes as "/api/ws" (see live.rs). A caller that falls behind gets a RESYNC event saying how many it missed. */
+    async fn watch(&self, request: Request<WatchRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let (user, _) = self.caller(&request, false).await?;
+        let request = request.into_inner();
+        let filter = LiveFilter {
+            list: request.list,
+            status: request.status,
+            owner: request.owner,
+            tag: request.tag,
+        };
+        let receiver = self.state.events.subscribe();
+        let list_shares = self.state.list_shares.clone();
+
+        let events = stream::unfold(
+            (receiver, list_shares, user, filter),
+            |(mut receiver, list_shares, user, filter)| async move {
+                loop {
+                    let event = match receiver.recv().await {
+                        Ok(change) => {
+                            let shares = list_shares.lock().await.clone();
+                            let Some(event) = live::event_for(&change, &user, &filter, &shares) else {
+                                continue;
+                                    // Not something this caller can see.
+                            };
+                            let kind = match event.kind {
+                                ChangeKind::Created => EventKind::Created,
+                                ChangeKind::Updated => EventKind::Updated,
+                                ChangeKind::Deleted => EventKind::Deleted,
+                            };
+                            TodoEvent {
+                                kind: kind as i32,
+                                id: event.id,
+                                at: event.at.to_rfc3339(),
+                                actor_id: event.actorId.to_string(),
+                                todo: Some(to_message(event.todo)),
+                                missed: 0,
+                            }
+                        }
+                        Err(RecvError::Lagged(missed)) => TodoEvent {
+                            kind: EventKind::Resync as i32,
+                            missed,
+                            ..Default::default()
+                        },
+                        Err(RecvError::Closed) => return None,
+                    };
+                    return Some((Ok(event), (receiver, list_shares, user, filter)));
+                }
+            },
+        );
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/* Starts the gRPC server on GRPC_PORT (default 50051), next to the REST server. */
+pub fn spawn_grpc_server(state: AppState) {
+    let port = std::env::var("GRPC_PORT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(50051);
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+    tokio::spawn(async move {
+        let service = TodoServiceServer::new(TodoGrpc { state });
+        if let Err(err) = Server::builder().add_service(service).serve(addr).await {
+            eprintln!("gRPC server on {} stopped: {}", addr, err);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::Value;
+    use tonic::{Code, Request};
+
+    use super::{proto::todo_service_server::TodoService, *};
+    use crate::testing::{app_with, login, send};
+
+    /* A request carrying the caller's login as metadata, as a gRPC client would send it. */
+    fn signed<T>(auth: &str, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.metadata_mut().insert("authorization", auth.parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn grpc_follows_the_rest_rules() {
+        let (router, state) = app_with(AppState::for_tests());
+        let alice = login(&router, "default", "alice").await;
+        let service = TodoGrpc { state };
+        let create = || CreateTodoRequest {
+            title: "Over gRPC".to_string(),
+            content: "a".to_string(),
+            ..Default::default()
+        };
+
+        /* Calls without a login are refused. */
+        let err = service.create(Request::new(create())).await.unwrap_err();
+        assert_eq!(err.code(), Code::Unauthenticated);
+
+        /* A todo made over gRPC is the same todo REST sees, and titles stay unique. */
+        let todo = service.create(signed(&alice, create())).await.unwrap().into_inner();
+        let auth = [("authorization", alice.as_str())];
+        let (status, body) = send(&router, Method::GET, &format!("/api/todos/{}", todo.id), &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["todo"]["title"], "Over gRPC");
+        let err = service.create(signed(&alice, create())).await.unwrap_err();
+        assert_eq!(err.code(), Code::AlreadyExists);
+
+        let update = UpdateTodoRequest {
+            id: todo.id.clone(),
+            title: Some("Renamed".to_string()),
+            ..Default::default()
+        };
+        let updated = service.update(signed(&alice, update)).await.unwrap().into_inner();
+        assert_eq!(updated.todo.unwrap().title, "Renamed");
+        let listed = service.list(signed(&alice, ListTodosRequest::default())).await.unwrap().into_inner();
+        assert_eq!(listed.todos.len(), 1);
+
+        /* A deleted todo is only found when the trash is included. */
+        service.delete(signed(&alice, DeleteTodoRequest { id: todo.id.clone() })).await.unwrap();
+        let get = |include_deleted| GetTodoRequest { id: todo.id.clone(), include_deleted };
+        let err = service.get(signed(&alice, get(false))).await.unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+        let trashed = service.get(signed(&alice, get(true))).await.unwrap().into_inner();
+        assert!(trashed.deleted_at.is_some());
+        let err = service.get(signed(&alice, GetTodoRequest { id: "not-a-uuid".to_string(), include_deleted: false })).await.unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+    }
+}
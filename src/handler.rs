@@ -5,22 +5,63 @@ response::IntoResponse: allows handler functions to return different types as HT
 Json: helper for sending and receiving JSON data. */
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
-    Json,
+    extract::{ws::WebSocketUpgrade, Path, Query, State},
+    http::{
+        header::{ACCEPT, CONTENT_DISPOSITION, CONTENT_TYPE, SEC_WEBSOCKET_PROTOCOL},
+        HeaderMap, StatusCode,
+    },
+    response::{Html, IntoResponse, Response},
+    Extension, Json,
 };
+/* Imports the pieces of async-graphql the GraphQL endpoints need (see graphql.rs). */
+use async_graphql::http::{GraphiQLSource, WebSocketProtocols, ALL_WEBSOCKET_PROTOCOLS};
+
+/* Imports the trait that builds the OpenAPI document, which the Postman collection is made from. */
+use utoipa::OpenApi;
 
 /* Imports Uuid from the uuid crate, used for generating and handling unique identifiers. */
 use uuid::Uuid;
+/* Imports Arc so handlers can share read-only settings like the workflow. */
+use std::sync::Arc;
 
 /* Imports structures and functions from our local crate (IE our project). */
 use crate::{
-    model::{CreateTodoSchema, QueryOptions, Todo, UpdateTodoSchema, DB},
-    response::{SingleTodoResponse, TodoData, TodoListResponse},
+    api_key,
+    audit::{AuditAction, AuditEntry, AuditLog, Auditor},
+    auth::{self, CurrentUser},
+    graphql::{self, TodoSchema},
+    openapi::ApiDoc,
+    postman,
+    jwt::{Claims, JwtVerifier},
+    live::{self, EventBus, LiveFilter},
+    dependency, policy::{self, Action, Denied}, position, recurrence,
+    model::{
+        AddDependencySchema, ApiKeyDB, AuditQuery, AuthSchema, GetTodoQuery, CreateApiKeySchema, CreateReminderSchema,
+        CreateTodoSchema, ListShare, ListShareDB, MoveTodoSchema, QueryOptions, Reminder, SessionDB, clean_tags,
+        title_taken, Todo, UpdateTodoSchema, User, UserDB, DB,
+    },
+    response::{
+        ApiKeyData, ApiKeyListResponse, ApiKeyResponse, AuditListResponse, BoardColumn, BoardResponse, FilteredUser,
+        GenericResponse, LoginResponse, RevisionListResponse, SingleTodoResponse, TodoData, TodoListResponse, UserData,
+        UserResponse, RebuildResponse,
+    },
+    event_store::EventStore,
+    revision::RevisionStore,
+    tenant::{Tenant, TenantConfig},
+    workflow::Workflow,
 };
 
 /* When user navigates to health checker route, print a message. */
+#[utoipa::path(
+    get,
+    path = "/api/healthchecker",
+    tag = "health",
+    summary = "Checks that the server is running",
+    responses(
+        (status = 200, description = "The server is running", body = GenericResponse),
+    ),
+    security(),
+)]
 pub async fn health_checker_handler() -> impl IntoResponse {
     const MESSAGE: &str = "Build Simple CRUD API in Rust using Axum";
 
@@ -33,22 +74,68 @@ pub async fn health_checker_handler() -> impl IntoResponse {
 }
 
 /* Asynchronous function that handles use trying to get a list of todos. */
+#[utoipa::path(
+    get,
+    path = "/api/todos",
+    tag = "todos",
+    summary = "Lists the caller's todos in their own order",
+    params(QueryOptions),
+    responses(
+        (status = 200, description = "One page of todos", body = TodoListResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
 pub async fn todos_list_handler(
     opts: Option<Query<QueryOptions>>,
     State(db): State<DB>,
-) -> impl IntoResponse {
-    let todos = db.lock().await;
-        // Accesses and locks databse for reading - The database needs to be 'locked' so that the data isn't being changed from multiple requests running at the same time.
+    State(list_shares): State<ListShareDB>,
+    State(event_store): State<Arc<EventStore>>,
+    user: CurrentUser,
+) -> Result<Json<TodoListResponse>, (StatusCode, Json<serde_json::Value>)> {
     let Query(opts) = opts.unwrap_or_default();
         // If query parameters are provided, use them. Otherwise use default values.
+    let list_shares = list_shares.lock().await.clone();
+    let mut store = db.lock().await;
+        // Accesses and locks databse for reading - The database needs to be 'locked' so that the data isn't being changed from multiple requests running at the same time.
+    let mut past;
+    let todos = match opts.as_of {
+        Some(as_of) => {
+            past = todos_as_of(&event_store, &user, as_of).await?;
+            &mut past
+        }
+        None => store.entry(user.tenant.clone()).or_default(),
+    };
+        // Opens only the caller's workspace; todos in other workspaces can't be reached from here.
+        // With 'as_of', the workspace is rebuilt from the event log as it was at that time instead.
     let limit = opts.limit.unwrap_or(10);
         // Checks if a limit is provided in the query for how many todo items can be listed per page. Not integral to our current program. Mainly used for organization in front-end pages.
     let offset = (opts.page.unwrap_or(1) - 1) * limit;
         // Calculates where to start in the todo list for pagination, uses the page number from the query or defaults to page 1.
-    let todos: Vec<Todo> = todos.clone().into_iter().skip(offset).take(limit).collect();
+    let actionable = opts.actionable.unwrap_or(false);
+        // When 'actionable' is set, only todos that can be started right now (incomplete and not blocked) are listed.
+    let include_deleted = opts.includeDeleted.unwrap_or(false);
+        // Todos in the trash are left out unless the client asks for them.
+    let mut ordered = todos.clone();
+    position::sort(&mut ordered);
+        // Sorts a copy by each todo's drag-and-drop position, so the page shows the user's own order.
+    let todos: Vec<Todo> = ordered
+        .iter()
+        .filter(|todo| policy::role_for(&user.id, todo, &list_shares).is_some())
+        .filter(|todo| opts.list.is_none() || todo.list == opts.list)
+        .filter(|todo| include_deleted || todo.deletedAt.is_none())
+        .map(|todo| dependency::annotate(todos, todo))
+        .filter(|todo| !actionable || (!todo.completed.unwrap_or(false) && !todo.blocked))
+        .skip(offset)
+        .take(limit)
+        .collect();
         // Creates a list of todos for the current page.
-        // todos.clone(): Clones the todo list so we can work with it.
-        // into_iter(): turns the list into an iterator
+        // iter(): turns the list into an iterator
+        // filter(role_for): only todos the caller owns or that were shared with them are listed.
+        // filter(list): keeps only the requested list, if the client asked for one.
+        // filter(deletedAt): drops todos in the trash, unless 'includeDeleted' was set.
+        // map(annotate): fills in the computed 'blocks'/'blocked' fields for each todo.
+        // filter(): drops todos that aren't actionable when the client asked for only actionable ones.
         // skip(offset) Skips todos before the current page we are accessing - offset is defined earlier in this function.
         // take(limit): Takes only the number of todos for this page.
         // collect(): Collects the results into a new vector.
@@ -61,27 +148,103 @@ pub async fn todos_list_handler(
     };
 
     /* Wraps the response in Axum's 'Json' type so it can be sent as a JSON HTTP response. */
-    Json(json_response)
+    Ok(Json(json_response))
+}
+
+/* A workspace's todos as they were at 'as_of', replayed from the event log (see event_store.rs). Sharing is judged by today's list shares, since those aren't part of the log. */
+async fn todos_as_of(
+    event_store: &EventStore,
+    user: &CurrentUser,
+    as_of: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<Todo>, (StatusCode, Json<serde_json::Value>)> {
+    event_store.project(&user.tenant, Some(as_of)).await.ok_or_else(|| {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "Point-in-time queries need the event log (STORAGE_MODE=events)",
+        });
+        (StatusCode::BAD_REQUEST, Json(error_response))
+    })
 }
 
 /* Function handling creating a new todo. */
+#[utoipa::path(
+    post,
+    path = "/api/todos",
+    tag = "todos",
+    summary = "Creates a todo",
+    request_body = CreateTodoSchema,
+    responses(
+        (status = 201, description = "The new todo", body = SingleTodoResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The workspace is full", body = GenericResponse),
+        (status = 409, description = "A todo with this title already exists", body = GenericResponse),
+    ),
+)]
 pub async fn create_todo_handler(
     State(db): State<DB>,
+    State(workflow): State<Arc<Workflow>>,
+    State(tenants): State<Arc<TenantConfig>>,
+    user: CurrentUser,
+    audit: Auditor,
     Json(body): Json<CreateTodoSchema>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let mut vec = db.lock().await;
+) -> Result<(StatusCode, Json<SingleTodoResponse>), (StatusCode, Json<serde_json::Value>)> {
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    /* Generates a unique ID for this todo, and checks and stores it. */
+    let todo = insert_todo(vec, Uuid::new_v4(), &workflow, &tenants, &user, &audit, body).await?;
 
-    /* Checks to see if this todo already exists. */
-    if let Some(todo) = vec.iter().find(|todo| todo.title == body.title) {
+    /* Prepares the data to be sent back as a JSON response to the client. */
+    let json_response = SingleTodoResponse {
+        status: "success".to_string(),
+        data: TodoData { todo, next: None },
+    };
+
+    /* Returns an HTTP 201 Created status and JSON response to the client. */
+    Ok((StatusCode::CREATED, Json(json_response)))
+}
+
+/* Checks a new todo against the workspace's rules and stores it under the given ID. Shared by the create handler and sync (where the client picks the ID). */
+pub async fn insert_todo(
+    vec: &mut Vec<Todo>,
+    uuid_id: Uuid,
+    workflow: &Workflow,
+    tenants: &TenantConfig,
+    user: &CurrentUser,
+    audit: &Auditor,
+    body: CreateTodoSchema,
+) -> Result<Todo, (StatusCode, Json<serde_json::Value>)> {
+    check_quota(vec, tenants, user)?;
+
+    /* Checks to see if this todo already exists. Titles have to be unique within the workspace, but other workspaces can reuse them, and so can a new todo once the old one is in the trash. */
+    if title_taken(vec, &body.title, None, None) {
+        return Err(title_conflict(&body.title));
+    }
+
+    /* Rejects recurrence rules we can't understand before anything is stored. */
+    if let Err(message) = recurrence::validate(body.recurrence.as_deref(), body.dueDate) {
         let error_response = serde_json::json!({
             "status": "fail",
-            "message": format!("Todo with title: '{}' already exists", todo.title),
+            "message": message,
         });
-        return Err((StatusCode::CONFLICT, Json(error_response)));
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    /* New todos start in the workflow's first column unless the client picked a column that exists. */
+    let status = body.status.unwrap_or_else(|| workflow.initial().to_string());
+    if !workflow.has_column(&status) {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Unknown status: '{}'", status),
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
     }
 
-    /* Generates a unique ID and time stamp for this todo */
-    let uuid_id = Uuid::new_v4();
+    /* New todos go to the end of their list. */
+    let position = position::append(vec, &user.id, body.list.as_deref(), user, audit).await;
+
+    /* Time stamps this todo */
     let datetime = chrono::Utc::now();
 
     
@@ -90,117 +253,1593 @@ pub async fn create_todo_handler(
         id: Some(uuid_id.to_string()),
         title: body.title,
         content: body.content,
-        completed: Some(false),
-            // Automatically sets to false when you create a new task.
+        completed: Some(workflow.is_done(&status)),
+            // False for a new task, unless it was created straight into the done column.
+        status: Some(status),
         createdAt: Some(datetime),
         updatedAt: Some(datetime),
+        dueDate: body.dueDate,
+        seriesId: body.recurrence.as_ref().map(|_| uuid_id.to_string()),
+        occurrence: body.recurrence.as_ref().map(|_| 1),
+        seriesStart: body.recurrence.as_ref().and(body.dueDate),
+            // A recurring todo starts a new series, and this is its first occurrence.
+        recurrence: body.recurrence,
+        position: Some(position),
+        list: body.list,
+        tags: body.tags.map(clean_tags).unwrap_or_default(),
+        ownerId: Some(user.id.clone()),
+        ..Default::default()
+            // A new todo doesn't depend on anything yet; dependencies are added through their own endpoint.
     };
 
     /* Adds the new todo to the database/shared todo list. */
-    vec.push(todo.clone());
-        // We have to clone it because the todo is used again the response.
+    audit.record(vec, user, AuditAction::Create, None, Some(&todo)).await;
+    Ok(todo)
+}
+
+/* Stops a workspace from growing past its quota (see tenant.rs). Called before a todo is added, or taken back out of the trash. */
+pub fn check_quota(
+    vec: &[Todo],
+    tenants: &TenantConfig,
+    user: &CurrentUser,
+) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let active = vec.iter().filter(|todo| todo.deletedAt.is_none()).count();
+        // Todos in the trash don't count towards the quota.
+    if let Some(quota) = tenants.quota(&user.tenant).filter(|quota| active >= *quota) {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Workspace '{}' has reached its limit of {} todos", user.tenant, quota),
+        });
+        return Err((StatusCode::FORBIDDEN, Json(error_response)));
+    }
+    Ok(())
+}
+
+/* The error sent back when another todo in the workspace already has the title (see 'title_taken' in model.rs). */
+pub fn title_conflict(title: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response = serde_json::json!({
+        "status": "fail",
+        "message": format!("Todo with title: '{}' already exists", title),
+    });
+    (StatusCode::CONFLICT, Json(error_response))
+}
+
+/* Finds a todo by ID and runs the sharing policy (policy.rs) for the action the caller wants. Returns the todo's index in 'vec', or the error response: 404 if the caller can't see the todo at all (or it's in the trash), 403 if they can see it but their role doesn't allow the action. */
+pub fn authorize(
+    vec: &[Todo],
+    id: &str,
+    user: &CurrentUser,
+    list_shares: &[ListShare],
+    action: Action,
+) -> Result<usize, (StatusCode, Json<serde_json::Value>)> {
+    authorize_including_trash(vec, id, user, list_shares, action, false)
+}
+
+/* Same as 'authorize', but todos in the trash are found too when 'include_deleted' is set. */
+pub fn authorize_including_trash(
+    vec: &[Todo],
+    id: &str,
+    user: &CurrentUser,
+    list_shares: &[ListShare],
+    action: Action,
+    include_deleted: bool,
+) -> Result<usize, (StatusCode, Json<serde_json::Value>)> {
+    let found = vec
+        .iter()
+        .position(|todo| todo.id.as_deref() == Some(id) && (include_deleted || todo.deletedAt.is_none()))
+        .ok_or(Denied::NotFound)
+        .and_then(|pos| policy::check(&user.id, &vec[pos], list_shares, action).map(|_| pos));
+
+    match found {
+        Ok(pos) => Ok(pos),
+        Err(Denied::NotFound) => {
+            let error_response = serde_json::json!({
+                "status": "fail",
+                "message": format!("Todo with ID: {} not found", id)
+            });
+            Err((StatusCode::NOT_FOUND, Json(error_response)))
+        }
+        Err(Denied::Forbidden) => {
+            let error_response = serde_json::json!({
+                "status": "fail",
+                "message": format!("You don't have permission to do that to todo with ID: {}", id)
+            });
+            Err((StatusCode::FORBIDDEN, Json(error_response)))
+        }
+    }
+}
+
+/* Retrieves the requested todo item. Todos in the trash are only returned with '?includeDeleted=true'. */
+#[utoipa::path(
+    get,
+    path = "/api/todos/{id}",
+    tag = "todos",
+    summary = "Gets a todo",
+    params(("id" = Uuid, Path, description = "The todo's ID"), GetTodoQuery),
+    responses(
+        (status = 200, description = "The todo", body = SingleTodoResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn get_todo_handler(
+    Path(id): Path<Uuid>,
+    opts: Option<Query<GetTodoQuery>>,
+    State(db): State<DB>,
+    State(list_shares): State<ListShareDB>,
+    State(event_store): State<Arc<EventStore>>,
+    user: CurrentUser,
+) -> Result<(StatusCode, Json<SingleTodoResponse>), (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let Query(opts) = opts.unwrap_or_default();
+    let list_shares = list_shares.lock().await.clone();
+    let mut store = db.lock().await;
+    let mut past;
+    let vec = match opts.as_of {
+        Some(as_of) => {
+            past = todos_as_of(&event_store, &user, as_of).await?;
+            &mut past
+        }
+        None => store.entry(user.tenant.clone()).or_default(),
+    };
+        // With 'as_of', the todo is looked up as it was at that time (see event_store.rs).
+
+    /* Looks the todo up and checks the caller may view it (their own, or shared with them). If not, 'authorize' returns the error and the '?' sends it straight back. */
+    let include_deleted = opts.includeDeleted.unwrap_or(false);
+    let pos = authorize_including_trash(vec, &id, &user, &list_shares, Action::View, include_deleted)?;
 
-    /* Prepares the data to be sent back as a JSON response to the client. */
     let json_response = SingleTodoResponse {
         status: "success".to_string(),
-        data: TodoData { todo },
+        data: TodoData {
+            todo: dependency::annotate(vec, &vec[pos]),
+            next: None,
+        },
     };
-
-    /* Returns an HTTP 201 Created status and JSON response to the client. */
-    Ok((StatusCode::CREATED, Json(json_response)))
+    Ok((StatusCode::OK, Json(json_response)))
 }
 
-/* Retrieves the requested todo item. */
-pub async fn get_todo_handler(
+/* Allows us to edit a todo item by ID. */
+#[utoipa::path(
+    patch,
+    path = "/api/todos/{id}",
+    tag = "todos",
+    summary = "Changes the fields that are sent",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    request_body = UpdateTodoSchema,
+    responses(
+        (status = 200, description = "The todo, and its next occurrence if completing it scheduled one", body = SingleTodoResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+        (status = 409, description = "The title is taken, the workflow doesn't allow the move, or the todo is blocked by unfinished todos", body = GenericResponse),
+    ),
+)]
+pub async fn edit_todo_handler(
     Path(id): Path<Uuid>,
     State(db): State<DB>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    State(workflow): State<Arc<Workflow>>,
+    State(list_shares): State<ListShareDB>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(body): Json<UpdateTodoSchema>,
+) -> Result<(StatusCode, Json<SingleTodoResponse>), (StatusCode, Json<serde_json::Value>)> {
     let id = id.to_string();
-    let vec = db.lock().await;
+    let list_shares = list_shares.lock().await.clone();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
 
-    /* The match keyword lets us compare a values. It's ideal for working with enums where we want a specific outcome for a specific variable. The match below lets us search through a list of todo's for a requested ID which is in the Route. If no todo is found with that ID it will return an error. */
-    match vec.iter().find(|todo| todo.id == Some(id.clone())) {
-        Some(todo) => {
-            let json_response = SingleTodoResponse {
-                status: "success".to_string(),
-                data: TodoData { todo: todo.clone() },
+    /* Owners and editors may change a todo; viewers get a 403 and everyone else a 404. */
+    let pos = authorize(vec, &id, &user, &list_shares, Action::Edit)?;
+    let (todo, next) = apply_edit(vec, pos, &workflow, &user, &audit, body).await?;
+
+    let json_response = SingleTodoResponse {
+        status: "success".to_string(),
+        data: TodoData {
+            todo: dependency::annotate(vec, &todo),
+            next: next.map(|next| dependency::annotate(vec, &next)),
+        },
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Applies an edit to the todo at 'pos', which the caller has already been authorized to change. Returns the changed todo, and the next occurrence if completing it scheduled one. Shared by the edit handler and sync. */
+pub async fn apply_edit(
+    vec: &mut Vec<Todo>,
+    pos: usize,
+    workflow: &Workflow,
+    user: &CurrentUser,
+    audit: &Auditor,
+    body: UpdateTodoSchema,
+) -> Result<(Todo, Option<Todo>), (StatusCode, Json<serde_json::Value>)> {
+    let blocked = dependency::is_blocked(vec, &vec[pos]);
+    let todo = &mut vec[pos];
+    let before = todo.clone();
+        // Kept for the audit log.
+
+    let datetime = chrono::Utc::now();
+    let title = body.title.clone().unwrap_or_else(|| todo.title.clone());
+    let content = body.content.clone().unwrap_or_else(|| todo.content.clone());
+    let current_status = workflow.status_of(todo);
+
+    /* Works out the new workflow column. Older clients that only send 'completed' still work: true moves the todo to done, false moves a done todo back to the first column. Either way the move has to be one the workflow allows. */
+    let status = match (&body.status, body.completed) {
+        (Some(status), _) => status.clone(),
+        (None, Some(true)) => workflow.done.clone(),
+        (None, Some(false)) if workflow.is_done(&current_status) => workflow.initial().to_string(),
+        _ => current_status.clone(),
+    };
+    if let Err(message) = workflow.check_transition(&current_status, &status) {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": message,
+        });
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+    let completed = workflow.is_done(&status);
+        // 'completed' always follows the status so the two can never disagree.
+
+    /* "B can't start until A is done": a todo can't be completed while a todo it waits on is unfinished. */
+    if completed && !workflow.is_done(&current_status) && blocked {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Todo with ID: {} is blocked by unfinished todos and can't be completed", todo.id.clone().unwrap_or_default()),
+        });
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+    let due_date = body.dueDate.or(todo.dueDate);
+    let recurrence = match body.recurrence.clone() {
+        Some(rule) if rule.is_empty() => None,
+            // An empty string turns recurrence off.
+        Some(rule) => Some(rule),
+        None => todo.recurrence.clone(),
+    };
+
+    if let Err(message) = recurrence::validate(recurrence.as_deref(), due_date) {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": message,
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    /* A new title has to be free in the workspace, just like when creating a todo. */
+    if !title.is_empty() && title != before.title && title_taken(vec, &title, before.id.as_deref(), before.seriesId.as_deref()) {
+        return Err(title_conflict(&title));
+    }
+    let todo = &vec[pos];
+
+    let payload = Todo {
+        id: todo.id.to_owned(),
+        title: if !title.is_empty() {
+            title
+        } else {
+            todo.title.to_owned()
+        },
+        content: if !content.is_empty() {
+            content
+        } else {
+            todo.content.to_owned()
+        },
+        completed: Some(completed),
+        status: Some(status),
+        createdAt: todo.createdAt,
+        updatedAt: Some(datetime),
+        seriesId: todo
+            .seriesId
+            .clone()
+            .or_else(|| recurrence.as_ref().and(todo.id.clone())),
+        occurrence: todo.occurrence.or(recurrence.as_ref().map(|_| 1)),
+            // A todo that just became recurring starts its own series.
+        seriesStart: match &recurrence {
+            Some(_) if body.dueDate.is_some() || body.recurrence.is_some() => due_date,
+            Some(_) => todo.seriesStart.or(todo.dueDate),
+            None => None,
+        },
+            // A new due date or rule makes the series count from the new due date.
+        dueDate: due_date,
+        recurrence,
+        tags: body.tags.clone().map(clean_tags).unwrap_or_else(|| todo.tags.clone()),
+        ..todo.clone()
+            // Everything else (like dependencies) stays the same.
+    };
+    let just_completed = completed && !todo.completed.unwrap_or(false);
+    let mut todo = payload;
+
+    /* Moving a todo to another list puts it at the end of that list. */
+    if let Some(list) = body.list.clone() {
+        let list = if list.is_empty() { None } else { Some(list) };
+        if list != todo.list {
+            let owner = todo.ownerId.clone().unwrap_or_default();
+            let key = position::append(vec, &owner, list.as_deref(), user, audit).await;
+            todo.list = list;
+            todo.position = Some(key);
+        }
+    }
+    audit.record(vec, user, AuditAction::Edit, Some(&before), Some(&todo)).await;
+
+    /* Completing an occurrence of a recurring todo schedules the next one. The completed todo stays in the list as history. */
+    let next = match recurrence::next_occurrence(&todo).filter(|_| just_completed) {
+        Some(next) => {
+            let owner = todo.ownerId.clone().unwrap_or_default();
+            let position = position::append(vec, &owner, todo.list.as_deref(), user, audit).await;
+            let next = Todo {
+                completed: Some(false),
+                status: Some(workflow.initial().to_string()),
+                    // The next occurrence starts back at the beginning of the workflow.
+                position: Some(position),
+                ..next
             };
-            Ok((StatusCode::OK, Json(json_response)))
+            audit.record(vec, user, AuditAction::Create, None, Some(&next)).await;
+            Some(next)
         }
-        None => {
-            let err_response = serde_json::json!({
+        None => None,
+    };
+    let todo = vec[pos].clone();
+        // Picks up a new position in case the list was rebalanced.
+    Ok((todo, next))
+}
+
+/* Function to delete a todo item by ID. The todo is moved to the trash rather than removed, so it can be restored until it is purged (see trash.rs). */
+#[utoipa::path(
+    delete,
+    path = "/api/todos/{id}",
+    tag = "todos",
+    summary = "Moves a todo to the trash",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    responses(
+        (status = 204, description = "The todo is in the trash"),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn delete_todo_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    State(list_shares): State<ListShareDB>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let list_shares = list_shares.lock().await.clone();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    /* Only the owner may delete a todo. */
+    let pos = authorize(vec, &id, &user, &list_shares, Action::Delete)?;
+    trash_todo(vec, pos, &user, &audit).await;
+
+    Ok((StatusCode::NO_CONTENT, Json("")))
+}
+
+/* Moves the todo at 'pos' to the trash. */
+pub async fn trash_todo(vec: &mut Vec<Todo>, pos: usize, user: &CurrentUser, audit: &Auditor) -> Todo {
+    let before = vec[pos].clone();
+    let todo = Todo {
+        deletedAt: Some(chrono::Utc::now()),
+        ..before.clone()
+    };
+    audit.record(vec, user, AuditAction::Delete, Some(&before), Some(&todo)).await;
+    todo
+}
+
+/* Makes the todo in the URL wait on another todo ("B can't start until A is done"). Rejects unknown IDs and any edge that would create a cycle. */
+#[utoipa::path(
+    post,
+    path = "/api/todos/{id}/dependencies",
+    tag = "dependencies",
+    summary = "Makes a todo wait on another todo",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    request_body = AddDependencySchema,
+    responses(
+        (status = 200, description = "The blocked todo", body = SingleTodoResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+        (status = 409, description = "The dependency would create a cycle", body = GenericResponse),
+    ),
+)]
+pub async fn add_dependency_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(body): Json<AddDependencySchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let blocker_id = body.blockedBy.to_string();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    /* Both todos have to exist (and belong to the caller) before we can link them. */
+    for todo_id in [&id, &blocker_id] {
+        if !vec.iter().any(|todo| todo.id.as_ref() == Some(todo_id) && user.owns_active(todo)) {
+            let error_response = serde_json::json!({
                 "status": "fail",
-                "message": format!("Todo with ID: {} not found", id)
+                "message": format!("Todo with ID: {} not found", todo_id)
             });
-            Err((StatusCode::NOT_FOUND, Json(err_response)))
+            return Err((StatusCode::NOT_FOUND, Json(error_response)));
+        }
+    }
+
+    if dependency::creates_cycle(vec, &id, &blocker_id) {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Todo with ID: {} can't be blocked by {} because it would create a cycle", id, blocker_id)
+        });
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+
+    let before = vec.iter().find(|todo| todo.id == Some(id.clone()) && user.owns_active(todo)).unwrap().clone();
+    let mut todo = before.clone();
+    if !todo.blockedBy.contains(&blocker_id) {
+        todo.blockedBy.push(blocker_id);
+        todo.updatedAt = Some(chrono::Utc::now());
+        audit.record(vec, &user, AuditAction::Edit, Some(&before), Some(&todo)).await;
+    }
+
+    let json_response = SingleTodoResponse {
+        status: "success".to_string(),
+        data: TodoData {
+            todo: dependency::annotate(vec, &todo),
+            next: None,
+        },
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Removes a dependency so the todo in the URL no longer waits on 'blocker_id'. */
+#[utoipa::path(
+    delete,
+    path = "/api/todos/{id}/dependencies/{blocker_id}",
+    tag = "dependencies",
+    summary = "Stops a todo waiting on another todo",
+    params(("id" = Uuid, Path, description = "The todo's ID"), ("blocker_id" = Uuid, Path, description = "The ID of the todo it was waiting on")),
+    responses(
+        (status = 200, description = "The dependency was removed", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn remove_dependency_handler(
+    Path((id, blocker_id)): Path<(Uuid, Uuid)>,
+    State(db): State<DB>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let blocker_id = blocker_id.to_string();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    if let Some(before) = vec.iter().find(|todo| todo.id == Some(id.clone()) && user.owns_active(todo)).cloned() {
+        if let Some(pos) = before.blockedBy.iter().position(|b| *b == blocker_id) {
+            let mut todo = before.clone();
+            todo.blockedBy.remove(pos);
+            todo.updatedAt = Some(chrono::Utc::now());
+            audit.record(vec, &user, AuditAction::Edit, Some(&before), Some(&todo)).await;
+
+            let json_response = GenericResponse {
+                status: "success".to_string(),
+                message: format!("Todo with ID: {} is no longer blocked by {}", id, blocker_id),
+            };
+            return Ok((StatusCode::OK, Json(json_response)));
         }
+
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Todo with ID: {} is not blocked by {}", id, blocker_id)
+        });
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
     }
+
+    let error_response = serde_json::json!({
+        "status": "fail",
+        "message": format!("Todo with ID: {} not found", id)
+    });
+    Err((StatusCode::NOT_FOUND, Json(error_response)))
 }
 
-/* Allows us to edit a todo item by ID. */
-pub async fn edit_todo_handler(
+/* Returns every todo in an order where each todo comes after the todos blocking it. Used by our planner to schedule work. */
+#[utoipa::path(
+    get,
+    path = "/api/todos/order",
+    tag = "dependencies",
+    summary = "Lists every todo after the todos blocking it",
+    responses(
+        (status = 200, description = "Every todo, blockers first", body = TodoListResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn todos_order_handler(
+    State(db): State<DB>,
+    user: CurrentUser,
+) -> impl IntoResponse {
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+    let mine: Vec<Todo> = vec.iter().filter(|todo| user.owns_active(todo)).cloned().collect();
+    let todos = dependency::topological_order(&mine);
+
+    let json_response = TodoListResponse {
+        status: "success".to_string(),
+        results: todos.len(),
+        todos,
+    };
+
+    Json(json_response)
+}
+
+/* Skips the current occurrence of a recurring todo: its due date moves forward to the next occurrence without it being marked completed. */
+#[utoipa::path(
+    post,
+    path = "/api/todos/{id}/skip",
+    tag = "recurrence",
+    summary = "Moves a recurring todo on to its next occurrence",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    responses(
+        (status = 200, description = "The todo at its next occurrence", body = SingleTodoResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+        (status = 409, description = "The change conflicts with the todo's current state", body = GenericResponse),
+    ),
+)]
+pub async fn skip_occurrence_handler(
     Path(id): Path<Uuid>,
     State(db): State<DB>,
-    Json(body): Json<UpdateTodoSchema>,
+    user: CurrentUser,
+    audit: Auditor,
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
     let id = id.to_string();
-    let mut vec = db.lock().await;
-
-    if let Some(todo) = vec.iter_mut().find(|todo| todo.id == Some(id.clone())) {
-        let datetime = chrono::Utc::now();
-        let title = body.title.clone().unwrap_or_else(|| todo.title.clone());
-        let content = body.content.clone().unwrap_or_else(|| todo.content.clone());
-        let completed = body.completed.unwrap_or(todo.completed.unwrap());
-
-        let payload = Todo {
-            id: todo.id.to_owned(),
-            title: if !title.is_empty() {
-                title
-            } else {
-                todo.title.to_owned()
-            },
-            content: if !content.is_empty() {
-                content
-            } else {
-                todo.content.to_owned()
-            },
-            completed: Some(completed),
-            createdAt: todo.createdAt,
-            updatedAt: Some(datetime),
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    if let Some(mut todo) = vec.iter().find(|todo| todo.id == Some(id.clone()) && user.owns_active(todo)).cloned() {
+        if todo.recurrence.is_none() {
+            let error_response = serde_json::json!({
+                "status": "fail",
+                "message": format!("Todo with ID: {} is not recurring", id)
+            });
+            return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+        }
+
+        /* Reuses the next occurrence's schedule but keeps this todo's ID, so nothing pointing at it breaks. */
+        let Some(next) = recurrence::next_occurrence(&todo) else {
+            let error_response = serde_json::json!({
+                "status": "fail",
+                "message": format!("Todo with ID: {} has no further occurrences", id)
+            });
+            return Err((StatusCode::CONFLICT, Json(error_response)));
         };
-        *todo = payload;
+        let before = todo.clone();
+        todo.dueDate = next.dueDate;
+        todo.occurrence = next.occurrence;
+        todo.seriesStart = next.seriesStart;
+        todo.updatedAt = Some(chrono::Utc::now());
+        audit.record(vec, &user, AuditAction::Edit, Some(&before), Some(&todo)).await;
 
         let json_response = SingleTodoResponse {
             status: "success".to_string(),
-            data: TodoData { todo: todo.clone() },
+            data: TodoData {
+                todo: dependency::annotate(vec, &todo),
+                next: None,
+            },
         };
-        Ok((StatusCode::OK, Json(json_response)))
-    } else {
+        return Ok((StatusCode::OK, Json(json_response)));
+    }
+
+    let error_response = serde_json::json!({
+        "status": "fail",
+        "message": format!("Todo with ID: {} not found", id)
+    });
+    Err((StatusCode::NOT_FOUND, Json(error_response)))
+}
+
+/* Lists every occurrence (completed ones included) in the same series as the todo in the URL, oldest first. */
+#[utoipa::path(
+    get,
+    path = "/api/todos/{id}/occurrences",
+    tag = "recurrence",
+    summary = "Lists every occurrence of a recurring todo",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    responses(
+        (status = 200, description = "The series, including completed occurrences", body = TodoListResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn occurrences_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    let Some(todo) = vec.iter().find(|todo| todo.id == Some(id.clone()) && user.owns_active(todo)) else {
         let error_response = serde_json::json!({
             "status": "fail",
             "message": format!("Todo with ID: {} not found", id)
         });
-        Err((StatusCode::NOT_FOUND, Json(error_response)))
-    }
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    };
+
+    /* A todo that was never recurring is a series of one. */
+    let mut todos: Vec<Todo> = match &todo.seriesId {
+        Some(series_id) => vec
+            .iter()
+            .filter(|other| other.seriesId.as_ref() == Some(series_id) && user.owns_active(other))
+            .map(|other| dependency::annotate(vec, other))
+            .collect(),
+        None => vec![dependency::annotate(vec, todo)],
+    };
+    todos.sort_by_key(|todo| todo.occurrence);
+
+    let json_response = TodoListResponse {
+        status: "success".to_string(),
+        results: todos.len(),
+        todos,
+    };
+    Ok((StatusCode::OK, Json(json_response)))
 }
 
-/* Function to delete a todo item by ID. */
-pub async fn delete_todo_handler(
+/* Adds a reminder to a todo. The background scheduler picks it up and sends it to every configured notifier once it is due. */
+#[utoipa::path(
+    post,
+    path = "/api/todos/{id}/reminders",
+    tag = "reminders",
+    summary = "Adds a reminder to a todo",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    request_body = CreateReminderSchema,
+    responses(
+        (status = 201, description = "The todo with its new reminder", body = SingleTodoResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn add_reminder_handler(
     Path(id): Path<Uuid>,
     State(db): State<DB>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(body): Json<CreateReminderSchema>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
     let id = id.to_string();
-    let mut vec = db.lock().await;
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    let Some(mut todo) = vec.iter().find(|todo| todo.id == Some(id.clone()) && user.owns_active(todo)).cloned() else {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Todo with ID: {} not found", id)
+        });
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    };
 
-    if let Some(pos) = vec.iter().position(|todo| todo.id == Some(id.clone())) {
-        vec.remove(pos);
-        return Ok((StatusCode::NO_CONTENT, Json("")));
+    /* A reminder needs exactly one way of knowing when to fire, and offsets only make sense with a due date. */
+    let problem = match (body.remindAt, body.offsetMinutes) {
+        (Some(_), Some(_)) | (None, None) => Some("Send exactly one of 'remindAt' or 'offsetMinutes'"),
+        (None, Some(_)) if todo.dueDate.is_none() => Some("An offset reminder needs the todo to have a dueDate"),
+        _ => None,
+    };
+    if let Some(message) = problem {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": message
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    let before = todo.clone();
+    todo.reminders.push(Reminder {
+        id: Uuid::new_v4().to_string(),
+        remindAt: body.remindAt,
+        offsetMinutes: body.offsetMinutes,
+        sentAt: None,
+    });
+    todo.updatedAt = Some(chrono::Utc::now());
+    audit.record(vec, &user, AuditAction::Edit, Some(&before), Some(&todo)).await;
+
+    let json_response = SingleTodoResponse {
+        status: "success".to_string(),
+        data: TodoData {
+            todo: dependency::annotate(vec, &todo),
+            next: None,
+        },
+    };
+    Ok((StatusCode::CREATED, Json(json_response)))
+}
+
+/* Removes a reminder from a todo. */
+#[utoipa::path(
+    delete,
+    path = "/api/todos/{id}/reminders/{reminder_id}",
+    tag = "reminders",
+    summary = "Removes a reminder from a todo",
+    params(("id" = Uuid, Path, description = "The todo's ID"), ("reminder_id" = Uuid, Path, description = "The reminder's ID")),
+    responses(
+        (status = 204, description = "The reminder was removed"),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No such todo or reminder", body = GenericResponse),
+    ),
+)]
+pub async fn delete_reminder_handler(
+    Path((id, reminder_id)): Path<(Uuid, Uuid)>,
+    State(db): State<DB>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let reminder_id = reminder_id.to_string();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    if let Some(mut todo) = vec.iter().find(|todo| todo.id == Some(id.clone()) && user.owns_active(todo)).cloned() {
+        if let Some(pos) = todo.reminders.iter().position(|reminder| reminder.id == reminder_id) {
+            let before = todo.clone();
+            todo.reminders.remove(pos);
+            todo.updatedAt = Some(chrono::Utc::now());
+            audit.record(vec, &user, AuditAction::Edit, Some(&before), Some(&todo)).await;
+            return Ok((StatusCode::NO_CONTENT, Json("")));
+        }
     }
 
     let error_response = serde_json::json!({
         "status": "fail",
-        "message": format!("Todo with ID: {} not found", id)
+        "message": format!("Reminder with ID: {} not found on todo {}", reminder_id, id)
     });
-
     Err((StatusCode::NOT_FOUND, Json(error_response)))
 }
+
+/* Groups every todo by its workflow column, in the order the columns are configured, for the Kanban board. */
+#[utoipa::path(
+    get,
+    path = "/api/board",
+    tag = "board",
+    summary = "Lists todos grouped by workflow column",
+    responses(
+        (status = 200, description = "One column per workflow status", body = BoardResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn board_handler(
+    State(db): State<DB>,
+    State(workflow): State<Arc<Workflow>>,
+    user: CurrentUser,
+) -> impl IntoResponse {
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    let columns = workflow
+        .columns
+        .iter()
+        .map(|column| {
+            let todos: Vec<Todo> = vec
+                .iter()
+                .filter(|todo| todo.status.as_deref() == Some(column.as_str()) && user.owns_active(todo))
+                .map(|todo| dependency::annotate(vec, todo))
+                .collect();
+            BoardColumn {
+                status: column.clone(),
+                results: todos.len(),
+                todos,
+            }
+        })
+        .collect();
+
+    let json_response = BoardResponse {
+        status: "success".to_string(),
+        columns,
+    };
+
+    Json(json_response)
+}
+
+/* Moves a todo for drag-and-drop ordering. It gets a new position key between its new neighbours, so no other todo has to change (unless its list needs rebalancing). */
+#[utoipa::path(
+    post,
+    path = "/api/todos/{id}/move",
+    tag = "todos",
+    summary = "Moves a todo before or after other todos in its list",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    request_body = MoveTodoSchema,
+    responses(
+        (status = 200, description = "The moved todo", body = SingleTodoResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn move_todo_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(body): Json<MoveTodoSchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    let Some(todo) = vec.iter().find(|todo| todo.id == Some(id.clone()) && user.owns_active(todo)) else {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Todo with ID: {} not found", id)
+        });
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    };
+    let list = todo.list.clone();
+
+    if body.before.is_none() && body.after.is_none() {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "Send 'before' and/or 'after' to say where the todo should go"
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    /* Normally this runs once. If the new key would be too long the list is rebalanced and we look the neighbours up again. */
+    let key = loop {
+        /* The rest of the list (without the todo being moved), in order, as (ID, position) pairs. */
+        let mut others: Vec<(String, String)> = vec
+            .iter()
+            .filter(|other| other.list == list && other.id != Some(id.clone()) && user.owns_active(other))
+            .filter_map(|other| Some((other.id.clone()?, other.position.clone()?)))
+            .collect();
+        others.sort_by(|a, b| a.1.cmp(&b.1));
+
+        /* Finds an anchor todo; it has to be in the same list. */
+        let find = |anchor: Uuid| {
+            let anchor = anchor.to_string();
+            others.iter().position(|(other_id, _)| *other_id == anchor).ok_or_else(|| {
+                let error_response = serde_json::json!({
+                    "status": "fail",
+                    "message": format!("Todo with ID: {} is not in the same list as {}", anchor, id)
+                });
+                (StatusCode::BAD_REQUEST, Json(error_response))
+            })
+        };
+
+        /* 'low' and 'high' are the positions the todo should land between. */
+        let (low, high) = match (body.after, body.before) {
+            (Some(after), Some(before)) => {
+                let (after, before) = (find(after)?, find(before)?);
+                if after + 1 != before {
+                    let error_response = serde_json::json!({
+                        "status": "fail",
+                        "message": "'after' and 'before' have to be next to each other in the list"
+                    });
+                    return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+                }
+                (Some(others[after].1.clone()), Some(others[before].1.clone()))
+            }
+            (Some(after), None) => {
+                let after = find(after)?;
+                (Some(others[after].1.clone()), others.get(after + 1).map(|o| o.1.clone()))
+            }
+            (None, Some(before)) => {
+                let before = find(before)?;
+                let low = before.checked_sub(1).map(|i| others[i].1.clone());
+                (low, Some(others[before].1.clone()))
+            }
+            (None, None) => unreachable!(),
+        };
+
+        if let Some(key) = position::between(low.as_deref(), high.as_deref()) {
+            break key;
+        }
+        position::rebalance(vec, &user.id, list.as_deref(), &user, &audit).await;
+    };
+
+    let before = vec.iter().find(|todo| todo.id == Some(id.clone()) && user.owns_active(todo)).unwrap().clone();
+    let todo = Todo {
+        position: Some(key),
+        updatedAt: Some(chrono::Utc::now()),
+        ..before.clone()
+    };
+    audit.record(vec, &user, AuditAction::Edit, Some(&before), Some(&todo)).await;
+
+    let json_response = SingleTodoResponse {
+        status: "success".to_string(),
+        data: TodoData {
+            todo: dependency::annotate(vec, &todo),
+            next: None,
+        },
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Registers a new user. The password is hashed with Argon2 before it is stored. */
+#[utoipa::path(
+    post,
+    path = "/api/auth/register",
+    tag = "auth",
+    summary = "Creates a user account",
+    request_body = AuthSchema,
+    responses(
+        (status = 201, description = "The new user", body = UserResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 409, description = "The username is taken", body = GenericResponse),
+    ),
+    security(),
+)]
+pub async fn register_user_handler(
+    State(users): State<UserDB>,
+    Tenant(tenant): Tenant,
+    Json(body): Json<AuthSchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let username = body.username.trim().to_string();
+    if username.is_empty() || body.password.len() < 8 {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "A username and a password of at least 8 characters are required",
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    /* Hashing is deliberately slow, so it runs on a blocking thread instead of holding up the async runtime. */
+    let password = body.password;
+    let password_hash = tokio::task::spawn_blocking(move || auth::hash_password(&password))
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|hash| hash)
+        .map_err(|message| {
+            let error_response = serde_json::json!({
+                "status": "error",
+                "message": format!("Couldn't hash password: {}", message),
+            });
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })?;
+
+    let mut users = users.lock().await;
+    if users.iter().any(|user| user.username == username && user.tenantId == tenant) {
+        // Usernames only have to be unique within a workspace.
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("User with username: '{}' already exists", username),
+        });
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+
+    let user = User {
+        id: Uuid::new_v4().to_string(),
+        username,
+        passwordHash: password_hash,
+        tenantId: tenant,
+        createdAt: chrono::Utc::now(),
+    };
+    users.push(user.clone());
+
+    let json_response = UserResponse {
+        status: "success".to_string(),
+        data: UserData {
+            user: FilteredUser {
+                id: user.id,
+                username: user.username,
+                tenantId: user.tenantId,
+                createdAt: user.createdAt,
+            },
+        },
+    };
+    Ok((StatusCode::CREATED, Json(json_response)))
+}
+
+/* Logs a user in and hands back a token to send as "Authorization: Bearer <token>". Wrong usernames and wrong passwords get the same answer so usernames can't be discovered. Users log in to the workspace they registered in. */
+#[utoipa::path(
+    post,
+    path = "/api/auth/login",
+    tag = "auth",
+    summary = "Logs in and returns a token",
+    request_body = AuthSchema,
+    responses(
+        (status = 200, description = "A token to send as \"Authorization: Bearer <token>\"", body = LoginResponse),
+        (status = 401, description = "Wrong username or password", body = GenericResponse),
+    ),
+    security(),
+)]
+pub async fn login_user_handler(
+    State(users): State<UserDB>,
+    State(sessions): State<SessionDB>,
+    State(jwt): State<Option<Arc<JwtVerifier>>>,
+    Tenant(tenant): Tenant,
+    Json(body): Json<AuthSchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let user = users
+        .lock()
+        .await
+        .iter()
+        .find(|user| user.username == body.username.trim() && user.tenantId == tenant)
+        .cloned();
+
+    /* Unknown usernames are checked against a dummy hash, so they take as long as a wrong password (see auth.rs). */
+    let password_hash = user.as_ref().map(|user| user.passwordHash.clone());
+    let password = body.password;
+    let valid = tokio::task::spawn_blocking(move || match password_hash {
+        Some(hash) => auth::verify_password(&password, &hash),
+        None => {
+            auth::verify_password(&password, auth::dummy_hash());
+            false
+        }
+    })
+    .await
+    .unwrap_or(false);
+
+    let Some(user) = user.filter(|_| valid) else {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "Invalid username or password",
+        });
+        return Err((StatusCode::UNAUTHORIZED, Json(error_response)));
+    };
+
+    /* Hands out a signed JWT when JWT_SECRET is set, otherwise a session token kept in memory. */
+    let token = match jwt.as_ref().and_then(|jwt| jwt.issue(&user.id, &user.tenantId)) {
+        Some(token) => token,
+        None => sessions.create(&user.id).await,
+    };
+
+    let json_response = LoginResponse {
+        status: "success".to_string(),
+        token,
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Tells the caller who they are logged in as. When they used a JWT the verified claims are included too. */
+#[utoipa::path(
+    get,
+    path = "/api/auth/me",
+    tag = "auth",
+    summary = "Shows who the token belongs to",
+    responses(
+        (status = 200, description = "The caller, and their JWT claims if they sent a JWT", body = serde_json::Value),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn me_handler(user: CurrentUser, claims: Option<Claims>) -> impl IntoResponse {
+    let json_response = serde_json::json!({
+        "status": "success",
+        "data": {
+            "userId": user.id,
+            "tenant": user.tenant,
+            "claims": claims,
+        }
+    });
+
+    Json(json_response)
+}
+
+/* API keys can't be used to manage API keys, so a leaked key can't mint new ones or hide its tracks. */
+fn reject_api_key_caller(user: &CurrentUser) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    if user.api_key_id.is_some() {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "API keys can only be managed by a logged-in user",
+        });
+        return Err((StatusCode::FORBIDDEN, Json(error_response)));
+    }
+    Ok(())
+}
+
+/* Issues a new API key for the caller. The plain key is in this response only; afterwards only its hash is kept. */
+#[utoipa::path(
+    post,
+    path = "/api/keys",
+    tag = "api keys",
+    summary = "Issues a new API key",
+    request_body = CreateApiKeySchema,
+    responses(
+        (status = 201, description = "The new key; 'key' is only ever shown here", body = ApiKeyResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn create_api_key_handler(
+    State(api_keys): State<ApiKeyDB>,
+    user: CurrentUser,
+    Json(body): Json<CreateApiKeySchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    reject_api_key_caller(&user)?;
+
+    let (record, key) = api_key::generate(&user.id, body.name, body.scope);
+    api_keys.lock().await.push(record.clone());
+
+    let json_response = ApiKeyResponse {
+        status: "success".to_string(),
+        data: ApiKeyData {
+            apiKey: record,
+            key: Some(key),
+        },
+    };
+    Ok((StatusCode::CREATED, Json(json_response)))
+}
+
+/* Lists the caller's API keys (revoked ones included), with when each was last used. */
+#[utoipa::path(
+    get,
+    path = "/api/keys",
+    tag = "api keys",
+    summary = "Lists the caller's API keys",
+    responses(
+        (status = 200, description = "The caller's keys", body = ApiKeyListResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn list_api_keys_handler(
+    State(api_keys): State<ApiKeyDB>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    reject_api_key_caller(&user)?;
+
+    let keys: Vec<_> = api_keys
+        .lock()
+        .await
+        .iter()
+        .filter(|record| record.ownerId == user.id)
+        .cloned()
+        .collect();
+
+    let json_response = ApiKeyListResponse {
+        status: "success".to_string(),
+        results: keys.len(),
+        apiKeys: keys,
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Revokes one of the caller's API keys. It stops working straight away but stays in the list for reference. */
+#[utoipa::path(
+    delete,
+    path = "/api/keys/{id}",
+    tag = "api keys",
+    summary = "Revokes an API key",
+    params(("id" = Uuid, Path, description = "The key's ID")),
+    responses(
+        (status = 200, description = "The revoked key", body = ApiKeyResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No key with this ID", body = GenericResponse),
+    ),
+)]
+pub async fn revoke_api_key_handler(
+    Path(id): Path<Uuid>,
+    State(api_keys): State<ApiKeyDB>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    reject_api_key_caller(&user)?;
+    let id = id.to_string();
+
+    let mut keys = api_keys.lock().await;
+    let Some(record) = keys.iter_mut().find(|record| record.id == id && record.ownerId == user.id) else {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("API key with ID: {} not found", id)
+        });
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    };
+
+    if record.revokedAt.is_none() {
+        record.revokedAt = Some(chrono::Utc::now());
+    }
+
+    let json_response = ApiKeyResponse {
+        status: "success".to_string(),
+        data: ApiKeyData {
+            apiKey: record.clone(),
+            key: None,
+        },
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Reads the audit log. Callers see entries for todos they can access (judged by the todo as it was at the time) and entries for their own actions, in their own workspace only. Filters: 'todoId', 'actorId', and a 'from'/'to' time range. With 'format=ndjson' (or "Accept: application/x-ndjson") every match is downloaded as one JSON object per line; otherwise the newest 'limit' entries (default 100) are returned as JSON. */
+#[utoipa::path(
+    get,
+    path = "/api/audit",
+    tag = "audit",
+    summary = "Lists who changed which todo and when",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "The matching entries", content((AuditListResponse = "application/json"), (AuditEntry = "application/x-ndjson"))),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn audit_handler(
+    opts: Option<Query<AuditQuery>>,
+    headers: HeaderMap,
+    State(audit): State<AuditLog>,
+    State(list_shares): State<ListShareDB>,
+    user: CurrentUser,
+) -> Response {
+    let Query(opts) = opts.unwrap_or_default();
+    let list_shares = list_shares.lock().await.clone();
+
+    let entries: Vec<_> = audit
+        .lock()
+        .await
+        .get(&user.tenant)
+        .into_iter()
+        .flatten()
+        .filter(|entry| {
+            entry.actorId == user.id
+                || entry.targetUserId.as_ref() == Some(&user.id)
+                || entry
+                    .after
+                    .iter()
+                    .chain(entry.before.iter())
+                    .any(|todo| policy::role_for(&user.id, todo, &list_shares).is_some())
+        })
+        .filter(|entry| opts.todoId.as_ref().is_none_or(|todo_id| entry.todoId == *todo_id))
+        .filter(|entry| opts.actorId.as_ref().is_none_or(|actor_id| entry.actorId == *actor_id))
+        .filter(|entry| opts.from.is_none_or(|from| entry.at >= from))
+        .filter(|entry| opts.to.is_none_or(|to| entry.at <= to))
+        .cloned()
+        .collect();
+        // The log is append-only, so 'entries' is already oldest first.
+
+    let wants_ndjson = opts.format.as_deref() == Some("ndjson")
+        || headers
+            .get(ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/x-ndjson"));
+
+    if wants_ndjson {
+        let body: String = entries
+            .iter()
+            .filter_map(|entry| serde_json::to_string(entry).ok())
+            .map(|line| line + "\n")
+            .collect();
+        return (
+            StatusCode::OK,
+            [
+                (CONTENT_TYPE, "application/x-ndjson"),
+                (CONTENT_DISPOSITION, "attachment; filename=\"audit.ndjson\""),
+            ],
+            body,
+        )
+            .into_response();
+    }
+
+    let limit = opts.limit.unwrap_or(100);
+    let newest: Vec<_> = entries.into_iter().rev().take(limit).collect();
+    let json_response = AuditListResponse {
+        status: "success".to_string(),
+        results: newest.len(),
+        entries: newest,
+    };
+    Json(json_response).into_response()
+}
+
+/* Lists a todo's saved revisions, oldest first, each with the fields that changed since the one before. Anyone who can see the todo can see its history. */
+#[utoipa::path(
+    get,
+    path = "/api/todos/{id}/revisions",
+    tag = "revisions",
+    summary = "Lists a todo's earlier versions",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    responses(
+        (status = 200, description = "Every saved version, oldest first", body = RevisionListResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn revisions_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    State(list_shares): State<ListShareDB>,
+    State(revisions): State<Arc<RevisionStore>>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let list_shares = list_shares.lock().await.clone();
+    {
+        let mut store = db.lock().await;
+        let vec = store.entry(user.tenant.clone()).or_default();
+        authorize(vec, &id, &user, &list_shares, Action::View)?;
+    }
+
+    let revisions = revisions.history(&user.tenant, &id).await;
+    let json_response = RevisionListResponse {
+        status: "success".to_string(),
+        results: revisions.len(),
+        revisions,
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Rolls a todo back to an earlier revision. The title, content, status, due date and recurrence come back; its ID, owner, sharing, list position, dependencies and reminders stay as they are now, so a rollback can't undo someone's access or break links to other todos. The rollback itself is saved as a new revision, so it can be undone too. */
+#[utoipa::path(
+    post,
+    path = "/api/todos/{id}/revisions/{rev}/restore",
+    tag = "revisions",
+    summary = "Rolls a todo back to an earlier version",
+    params(("id" = Uuid, Path, description = "The todo's ID"), ("rev" = u32, Path, description = "The revision number")),
+    responses(
+        (status = 200, description = "The restored todo", body = SingleTodoResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No such todo or revision", body = GenericResponse),
+        (status = 409, description = "The change conflicts with the todo's current state", body = GenericResponse),
+    ),
+)]
+pub async fn restore_revision_handler(
+    Path((id, rev)): Path<(Uuid, u32)>,
+    State(db): State<DB>,
+    State(workflow): State<Arc<Workflow>>,
+    State(list_shares): State<ListShareDB>,
+    State(revisions): State<Arc<RevisionStore>>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let list_shares = list_shares.lock().await.clone();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+    let pos = authorize(vec, &id, &user, &list_shares, Action::Edit)?;
+
+    let Some(revision) = revisions.history(&user.tenant, &id).await.into_iter().find(|revision| revision.rev == rev) else {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Revision {} of todo with ID: {} not found", rev, id)
+        });
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    };
+    let old = revision.todo;
+
+    /* Titles have to stay unique in the workspace, and going back to the old status has to be a move the workflow allows. */
+    if title_taken(vec, &old.title, old.id.as_deref(), old.seriesId.as_deref()) {
+        return Err(title_conflict(&old.title));
+    }
+    let status = workflow.status_of(&old);
+    if let Err(message) = workflow.check_transition(&workflow.status_of(&vec[pos]), &status) {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Can't restore revision {}: {}", rev, message),
+        });
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+
+    let before = vec[pos].clone();
+    let todo = Todo {
+        title: old.title,
+        content: old.content,
+        completed: Some(workflow.is_done(&status)),
+        status: Some(status),
+        dueDate: old.dueDate,
+        recurrence: old.recurrence,
+        seriesId: old.seriesId,
+        occurrence: old.occurrence,
+        seriesStart: old.seriesStart,
+        updatedAt: Some(chrono::Utc::now()),
+        ..before.clone()
+    };
+    audit.record(vec, &user, AuditAction::Edit, Some(&before), Some(&todo)).await;
+
+    let json_response = SingleTodoResponse {
+        status: "success".to_string(),
+        data: TodoData {
+            todo: dependency::annotate(vec, &todo),
+            next: None,
+        },
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Upgrades the request to a WebSocket that receives todo changes as they happen (see live.rs). The query string sets the starting filter. */
+#[utoipa::path(
+    get,
+    path = "/api/ws",
+    tag = "live",
+    summary = "Pushes todo changes over a WebSocket",
+    params(LiveFilter),
+    responses(
+        (status = 101, description = "Switches to a WebSocket that sends one JSON message per change"),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Query(filter): Query<LiveFilter>,
+    State(events): State<Arc<EventBus>>,
+    State(list_shares): State<ListShareDB>,
+    user: CurrentUser,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| live::serve(socket, events, list_shares, user, filter))
+}
+
+/* Streams todo changes as Server-Sent Events, for clients that can't use the WebSocket (see live.rs). A reconnecting client's "Last-Event-ID" header picks up where it left off. */
+#[utoipa::path(
+    get,
+    path = "/api/todos/events",
+    tag = "live",
+    summary = "Streams todo changes as Server-Sent Events",
+    params(LiveFilter),
+    responses(
+        (status = 200, description = "An event stream; send 'Last-Event-ID' to resume", content_type = "text/event-stream"),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn todo_events_handler(
+    headers: HeaderMap,
+    Query(filter): Query<LiveFilter>,
+    State(events): State<Arc<EventBus>>,
+    State(list_shares): State<ListShareDB>,
+    user: CurrentUser,
+) -> impl IntoResponse {
+    let last_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+    live::sse_stream(&events, list_shares, user, filter, last_id)
+}
+
+/* Throws away the caller's workspace todo list and rebuilds it from the event log (see event_store.rs). Only the workspace's admins (TENANT_ADMINS, see tenant.rs) may do this. The result says how many todos the rebuild changed, which is 0 unless the list had drifted from the log. */
+#[utoipa::path(
+    post,
+    path = "/api/events/rebuild",
+    tag = "events",
+    summary = "Rebuilds the caller's workspace from the event log",
+    responses(
+        (status = 200, description = "What the rebuild did", body = RebuildResponse),
+        (status = 400, description = "The event-sourced mode is off", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller isn't an admin of the workspace", body = GenericResponse),
+    ),
+)]
+pub async fn rebuild_projection_handler(
+    State(db): State<DB>,
+    State(event_store): State<Arc<EventStore>>,
+    State(tenants): State<Arc<TenantConfig>>,
+    State(users): State<UserDB>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let is_admin = users
+        .lock()
+        .await
+        .iter()
+        .find(|account| account.id == user.id)
+        .is_some_and(|account| tenants.is_admin(&user.tenant, &account.username));
+    if !is_admin {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "Only a workspace admin can rebuild it from the event log",
+        });
+        return Err((StatusCode::FORBIDDEN, Json(error_response)));
+    }
+
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    let Some(rebuild) = event_store.rebuild(&user.tenant, vec).await else {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "Rebuilding needs the event log (STORAGE_MODE=events)",
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    };
+
+    let json_response = RebuildResponse {
+        status: "success".to_string(),
+        data: rebuild,
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Runs a GraphQL query or mutation (see graphql.rs). The caller and their auditor go along with the request, so resolvers act as them. Errors come back inside the GraphQL response, which is always a 200. */
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    summary = "Runs a GraphQL query or mutation",
+    request_body(content = serde_json::Value, example = json!({"query": "{ todos { total todos { id title completed } } }"})),
+    responses(
+        (status = 200, description = "A GraphQL response; errors are reported inside it", body = serde_json::Value),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn graphql_handler(
+    Extension(schema): Extension<TodoSchema>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(request): Json<async_graphql::Request>,
+) -> impl IntoResponse {
+    Json(schema.execute(request.data(user).data(audit)).await)
+}
+
+/* Opens a WebSocket for GraphQL subscriptions (see graphql.rs). The client picks the protocol in the "Sec-WebSocket-Protocol" header; one we don't speak gets a 400. */
+#[utoipa::path(
+    get,
+    path = "/graphql/ws",
+    tag = "graphql",
+    summary = "Runs GraphQL subscriptions over a WebSocket",
+    responses(
+        (status = 101, description = "Switches to a WebSocket speaking graphql-transport-ws or graphql-ws"),
+        (status = 400, description = "No supported subprotocol was asked for", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn graphql_ws_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Extension(schema): Extension<TodoSchema>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let protocol = headers
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|protocols| protocols.split(',').find_map(|protocol| protocol.trim().parse::<WebSocketProtocols>().ok()));
+    let Some(protocol) = protocol else {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "Use the graphql-transport-ws or graphql-ws WebSocket protocol",
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    };
+
+    Ok(ws
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| graphql::serve(socket, schema, protocol, user)))
+}
+
+/* Serves GraphiQL, an in-browser editor for trying out GraphQL queries. Only routed when it is switched on (see graphql.rs). */
+pub async fn graphiql_handler() -> impl IntoResponse {
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/graphql")
+            .subscription_endpoint("/graphql/ws")
+            .title("Todo API")
+            .finish(),
+    )
+}
+
+/* Downloads a Postman collection with a request for every route, built from the OpenAPI document (see postman.rs). */
+#[utoipa::path(
+    get,
+    path = "/api/postman.json",
+    tag = "docs",
+    summary = "Downloads a Postman collection for this API",
+    responses(
+        (status = 200, description = "A Postman v2.1 collection", body = serde_json::Value),
+    ),
+    security(),
+)]
+pub async fn postman_collection_handler() -> impl IntoResponse {
+    let collection = postman::collection(&ApiDoc::openapi());
+    (
+        [(CONTENT_TYPE, "application/json")],
+        serde_json::to_string_pretty(&collection).unwrap_or_default(),
+    )
+        // Pretty-printed, so the copy saved in the repository by "make postman-collection" diffs line by line.
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::testing::{app, create_todo, login, send};
+
+    #[tokio::test]
+    async fn edits_keep_titles_unique() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        create_todo(&router, &alice, json!({ "title": "Taken", "content": "a" })).await;
+        let todo = create_todo(&router, &alice, json!({ "title": "Free", "content": "b" })).await;
+        let id = todo["id"].as_str().unwrap();
+        let auth = [("authorization", alice.as_str())];
+
+        for uri in [format!("/api/todos/{}", id), format!("/api/v2/todos/{}", id)] {
+            let (status, _) = send(&router, Method::PATCH, &uri, &auth, json!({ "title": "Taken" })).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+        }
+        let uri = format!("/api/todos/{}", id);
+        let (status, _) = send(&router, Method::PATCH, &uri, &auth, json!({ "title": "Free", "content": "c" })).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
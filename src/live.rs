@@ -0,0 +1,456 @@
+/* This file pushes todo changes to connected clients as they happen, over a WebSocket ("/api/ws") or Server-Sent Events ("/api/todos/events"). Each connection only gets the changes its user may see, narrowed by an optional filter on list, tag, owner or status. */
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Query, Request,
+    },
+    http::{header::AUTHORIZATION, HeaderName, HeaderValue},
+    middleware::Next,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use chrono::prelude::*;
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    auth::CurrentUser,
+    model::{ListShare, ListShareDB, Todo},
+    policy,
+};
+
+/* A single send that takes longer than this means the client has stopped reading, so the connection is dropped. */
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/* One change to a todo, as published on the bus. 'id' counts up from 1 in each workspace, so clients can tell whether they missed anything without learning how busy other workspaces are. 'before' is empty for a create and 'after' is empty for a purge. */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone)]
+pub struct TodoChange {
+    pub id: u64,
+    pub tenantId: String,
+    pub actorId: String,
+    pub at: DateTime<Utc>,
+    pub before: Option<Todo>,
+    pub after: Option<Todo>,
+}
+
+/* What a change looks like from one connection's point of view. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, async_graphql::Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/* The message sent to a client for one change. For "deleted" the todo is the last version the client could see. */
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct LiveEvent<'a> {
+    #[serde(rename = "type")]
+    pub kind: ChangeKind,
+    pub id: u64,
+    pub at: DateTime<Utc>,
+    pub actorId: &'a str,
+    pub todo: &'a Todo,
+}
+
+/* Narrows which todos a connection hears about. Every field that is set has to match. */
+#[derive(Debug, Clone, Default, Deserialize, Serialize, async_graphql::InputObject, utoipa::IntoParams)]
+#[graphql(name = "TodoChangeFilter")]
+#[into_params(parameter_in = Query)]
+pub struct LiveFilter {
+    pub list: Option<String>,
+    pub tag: Option<String>,
+    pub status: Option<String>,
+    pub owner: Option<String>,
+}
+
+impl LiveFilter {
+    fn matches(&self, todo: &Todo) -> bool {
+        self.list.as_ref().is_none_or(|list| todo.list.as_ref() == Some(list))
+            && self.tag.as_ref().is_none_or(|tag| todo.tags.contains(tag))
+            && self.status.as_ref().is_none_or(|status| todo.status.as_ref() == Some(status))
+            && self.owner.as_ref().is_none_or(|owner| todo.ownerId.as_ref() == Some(owner))
+    }
+}
+
+/* One workspace's newest changes, oldest first, and the number of the last one. */
+#[derive(Default)]
+struct Recent {
+    latest_id: u64,
+    changes: VecDeque<Arc<TodoChange>>,
+}
+
+/* The broadcast channel every change is published on. Publishing never waits: each listener has its own place in a ring buffer of the last LIVE_BUFFER changes, and one that falls further behind than that skips ahead (see 'serve'). */
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<TodoChange>>,
+    recent: Mutex<HashMap<String, Recent>>,
+        // Keyed by workspace. A plain (not tokio) mutex: it is only held for a moment and never across an 'await', so 'publish' doesn't have to be async.
+    replay: usize,
+    heartbeat: Duration,
+}
+
+/* What a stream resuming after 'last_id' starts with: the changes it missed (or 'gap' if some are gone), then whatever 'receiver' brings. */
+pub struct Resume {
+    pub missed: Vec<Arc<TodoChange>>,
+    pub gap: bool,
+    pub latest_id: u64,
+    pub receiver: broadcast::Receiver<Arc<TodoChange>>,
+}
+
+impl EventBus {
+    /* Reads LIVE_BUFFER (how far a connection may fall behind before it has to resync, default 256), LIVE_REPLAY (changes kept for reconnecting SSE clients, default 1000) and LIVE_HEARTBEAT_SECONDS (default 30). */
+    pub fn from_env() -> Self {
+        let env_number = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+
+        let (sender, _) = broadcast::channel(env_number("LIVE_BUFFER", 256) as usize);
+        EventBus {
+            sender,
+            recent: Mutex::new(HashMap::new()),
+            replay: env_number("LIVE_REPLAY", 1000) as usize,
+            heartbeat: Duration::from_secs(env_number("LIVE_HEARTBEAT_SECONDS", 30)),
+        }
+    }
+
+    /* Publishes a change to every listener, and keeps it for replay. Nobody listening is fine. Numbering, keeping and sending all happen under one lock, so changes go out in the order of their IDs and 'resume' can't miss one in between. */
+    pub fn publish(&self, tenant: &str, actor_id: &str, before: Option<&Todo>, after: Option<&Todo>) {
+        let mut workspaces = self.recent.lock().unwrap();
+        let recent = workspaces.entry(tenant.to_string()).or_default();
+        recent.latest_id += 1;
+        let id = recent.latest_id;
+
+        let change = Arc::new(TodoChange {
+            id,
+            tenantId: tenant.to_string(),
+            actorId: actor_id.to_string(),
+            at: Utc::now(),
+            before: before.cloned(),
+            after: after.cloned(),
+        });
+        recent.changes.push_back(change.clone());
+        if recent.changes.len() > self.replay {
+            recent.changes.pop_front();
+        }
+        let _ = self.sender.send(change);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<TodoChange>> {
+        self.sender.subscribe()
+    }
+
+    /* Subscribes a stream that last saw change 'last_id' of the workspace (None for a fresh one). IDs higher than any handed out yet mean the server restarted since, which counts as a gap too. */
+    pub fn resume(&self, tenant: &str, last_id: Option<u64>) -> Resume {
+        let mut workspaces = self.recent.lock().unwrap();
+        let recent = workspaces.entry(tenant.to_string()).or_default();
+        let latest_id = recent.latest_id;
+        let oldest_id = recent.changes.front().map(|change| change.id).unwrap_or(latest_id + 1);
+
+        let (missed, gap) = match last_id {
+            None => (Vec::new(), false),
+            Some(last_id) if last_id > latest_id || last_id + 1 < oldest_id => (Vec::new(), true),
+            Some(last_id) => (
+                recent.changes.iter().filter(|change| change.id > last_id).cloned().collect(),
+                false,
+            ),
+        };
+        Resume {
+            missed,
+            gap,
+            latest_id,
+            receiver: self.sender.subscribe(),
+        }
+    }
+}
+
+/* Decides what a change means to one user with one filter: a todo counts as visible if it is live (not in the trash), in the user's workspace, the user has a role on it, and it matches the filter. Comparing before and after gives the kind; None means the user doesn't hear about it at all. */
+pub fn event_for<'a>(
+    change: &'a TodoChange,
+    user: &CurrentUser,
+    filter: &LiveFilter,
+    list_shares: &[ListShare],
+) -> Option<LiveEvent<'a>> {
+    if change.tenantId != user.tenant {
+        return None;
+    }
+    let visible = |todo: &Option<Todo>| {
+        todo.as_ref().is_some_and(|todo| {
+            todo.deletedAt.is_none()
+                && filter.matches(todo)
+                && policy::role_for(&user.id, todo, list_shares).is_some()
+        })
+    };
+
+    let (kind, todo) = match (visible(&change.before), visible(&change.after)) {
+        (false, true) => (ChangeKind::Created, change.after.as_ref()?),
+        (true, true) => (ChangeKind::Updated, change.after.as_ref()?),
+        (true, false) => (ChangeKind::Deleted, change.before.as_ref()?),
+        (false, false) => return None,
+    };
+    Some(LiveEvent {
+        kind,
+        id: change.id,
+        at: change.at,
+        actorId: &change.actorId,
+        todo,
+    })
+}
+
+/* Browsers can't add an Authorization header to a WebSocket (or EventSource) request, so for those the token may be passed as "?access_token=<token>" instead. This copies it into the header before the JWT middleware and the 'CurrentUser' extractor look for it. Ordinary requests are left alone, so tokens don't end up in the URLs of normal API calls. */
+pub async fn query_token_middleware(mut request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let streaming = headers
+        .get("upgrade")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+        || headers
+            .get("accept")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("text/event-stream"));
+
+    if streaming && !headers.contains_key(AUTHORIZATION) {
+        let token = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+            .ok()
+            .and_then(|Query(mut params)| params.remove("access_token"));
+        if let Some(value) = token.and_then(|token| HeaderValue::from_str(&format!("Bearer {}", token)).ok()) {
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+    }
+
+    next.run(request).await
+}
+
+/* Sends one JSON text frame, giving up if the client takes longer than SEND_TIMEOUT to accept it. */
+async fn send_json(socket: &mut WebSocket, value: &impl Serialize) -> Result<(), ()> {
+    let text = serde_json::to_string(value).map_err(|_| ())?;
+    match tokio::time::timeout(SEND_TIMEOUT, socket.send(Message::Text(text))).await {
+        Ok(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+}
+
+/* Runs one WebSocket connection until the client goes away. Three things can happen at any moment: the client sends something (a new filter, a pong, or a close), a change is published, or it's time for a heartbeat. */
+pub async fn serve(mut socket: WebSocket, bus: Arc<EventBus>, list_shares: ListShareDB, user: CurrentUser, mut filter: LiveFilter) {
+    let mut changes = bus.subscribe();
+    let mut heartbeat = tokio::time::interval(bus.heartbeat);
+    heartbeat.tick().await;
+        // The first tick fires straight away; skip it so the first ping goes out after one interval.
+    let mut alive = true;
+        // Set whenever the client sends anything; a heartbeat that finds it still unset closes the connection.
+
+    if send_json(&mut socket, &serde_json::json!({"type": "subscribed", "filter": filter})).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => {
+                alive = true;
+                match incoming {
+                    Some(Ok(Message::Text(text))) => {
+                        let reply = match serde_json::from_str::<LiveFilter>(&text) {
+                            Ok(new_filter) => {
+                                filter = new_filter;
+                                serde_json::json!({"type": "subscribed", "filter": filter})
+                            }
+                            Err(err) => serde_json::json!({"type": "error", "message": format!("Invalid filter: {}", err)}),
+                        };
+                        if send_json(&mut socket, &reply).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                        // Pings are answered by Axum itself; pongs and binary frames only count as signs of life.
+                }
+            }
+            change = changes.recv() => match change {
+                Ok(change) => {
+                    let event = {
+                        let list_shares = list_shares.lock().await;
+                        event_for(&change, &user, &filter, &list_shares).map(|event| serde_json::to_value(event).ok())
+                    };
+                    if let Some(Some(event)) = event {
+                        if send_json(&mut socket, &event).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    /* This client is reading more slowly than changes come in, and the oldest ones it hadn't read yet were overwritten. Rather than buffer without limit, it skips ahead and is told to reload. */
+                    if send_json(&mut socket, &serde_json::json!({"type": "resync", "missed": missed})).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = heartbeat.tick() => {
+                if !alive {
+                    break;
+                }
+                alive = false;
+                if !matches!(tokio::time::timeout(SEND_TIMEOUT, socket.send(Message::Ping(Vec::new()))).await, Ok(Ok(()))) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/* Turns a change into an SSE event for one user, if they get to hear about it. */
+fn sse_event(change: &TodoChange, user: &CurrentUser, filter: &LiveFilter, list_shares: &[ListShare]) -> Option<Event> {
+    let event = event_for(change, user, filter, list_shares)?;
+    let kind = match event.kind {
+        ChangeKind::Created => "created",
+        ChangeKind::Updated => "updated",
+        ChangeKind::Deleted => "deleted",
+    };
+    Event::default().id(change.id.to_string()).event(kind).json_data(&event).ok()
+}
+
+/* The "resync" event. It carries the newest ID when there is one, so a client that reconnects after reloading doesn't ask for the same gap again. */
+fn resync_event(missed: Option<u64>, latest_id: Option<u64>) -> Event {
+    let event = Event::default()
+        .event("resync")
+        .data(serde_json::json!({"type": "resync", "missed": missed}).to_string());
+    match latest_id {
+        Some(id) => event.id(id.to_string()),
+        None => event,
+    }
+}
+
+/* Where an SSE stream is up to: changes still to replay, then the live channel. */
+struct SseState {
+    pending: VecDeque<Event>,
+    missed: VecDeque<Arc<TodoChange>>,
+    receiver: broadcast::Receiver<Arc<TodoChange>>,
+    list_shares: ListShareDB,
+    user: CurrentUser,
+    filter: LiveFilter,
+}
+
+/* Builds the SSE response for "/api/todos/events", resuming after 'last_id' when the client sent one. */
+pub fn sse_stream(bus: &EventBus, list_shares: ListShareDB, user: CurrentUser, filter: LiveFilter, last_id: Option<u64>) -> impl IntoResponse {
+    let resume = bus.resume(&user.tenant, last_id);
+    let mut pending = VecDeque::new();
+    if resume.gap {
+        pending.push_back(resync_event(None, Some(resume.latest_id).filter(|id| *id > 0)));
+    }
+
+    let state = SseState {
+        pending,
+        missed: resume.missed.into(),
+        receiver: resume.receiver,
+        list_shares,
+        user,
+        filter,
+    };
+    let events = stream::unfold(state, next_sse_event);
+
+    (
+        [(HeaderName::from_static("x-accel-buffering"), HeaderValue::from_static("no"))],
+            // Asks nginx-style proxies not to hold events back in a buffer.
+        Sse::new(events).keep_alive(KeepAlive::new().interval(bus.heartbeat)),
+    )
+}
+
+/* Produces the stream's next event: anything queued first, then replayed changes, then live ones. Changes this user doesn't get to see are skipped. Ends when the bus goes away. */
+async fn next_sse_event(mut state: SseState) -> Option<(Result<Event, Infallible>, SseState)> {
+    loop {
+        if let Some(event) = state.pending.pop_front() {
+            return Some((Ok(event), state));
+        }
+
+        let change = match state.missed.pop_front() {
+            Some(change) => change,
+            None => match state.receiver.recv().await {
+                Ok(change) => change,
+                Err(RecvError::Lagged(missed)) => {
+                    /* Too slow to keep up, like a lagging WebSocket (see 'serve'). There is no single ID to resume from, so the resync has none. */
+                    state.pending.push_back(resync_event(Some(missed), None));
+                    continue;
+                }
+                Err(RecvError::Closed) => return None,
+            },
+        };
+
+        let event = {
+            let list_shares = state.list_shares.lock().await;
+            sse_event(&change, &state.user, &state.filter, &list_shares)
+        };
+        if let Some(event) = event {
+            return Some((Ok(event), state));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::json;
+
+    use super::{event_for, ChangeKind, EventBus, LiveFilter};
+    use crate::{
+        auth::CurrentUser,
+        testing::{app, create_todo, login, send},
+    };
+
+    #[tokio::test]
+    async fn connections_can_filter_on_tags() {
+        let (router, state) = app();
+        let alice = login(&router, "default", "alice").await;
+        let todo = create_todo(&router, &alice, json!({ "title": "Tagged", "content": "a", "tags": [" home ", "home", ""] })).await;
+        assert_eq!(todo["tags"], json!(["home"]));
+
+        let mut changes = state.events.subscribe();
+        let uri = format!("/api/todos/{}", todo["id"].as_str().unwrap());
+        send(&router, Method::PATCH, &uri, &[("authorization", alice.as_str())], json!({ "tags": ["urgent"] })).await;
+        let change = changes.recv().await.unwrap();
+
+        let user = CurrentUser {
+            id: todo["ownerId"].as_str().unwrap().to_string(),
+            api_key_id: None,
+            tenant: "default".to_string(),
+        };
+        let filter = |tag: &str| LiveFilter {
+            tag: Some(tag.to_string()),
+            ..Default::default()
+        };
+        /* Gaining the tag looks like the todo appearing, losing it like the todo going away. */
+        assert_eq!(event_for(&change, &user, &filter("urgent"), &[]).unwrap().kind, ChangeKind::Created);
+        assert_eq!(event_for(&change, &user, &filter("home"), &[]).unwrap().kind, ChangeKind::Deleted);
+        assert!(event_for(&change, &user, &filter("work"), &[]).is_none());
+    }
+
+    #[test]
+    fn each_workspace_numbers_its_own_changes() {
+        let bus = EventBus::from_env();
+        bus.publish("acme", "alice", None, None);
+        bus.publish("acme", "alice", None, None);
+        bus.publish("globex", "bob", None, None);
+
+        let resume = bus.resume("globex", Some(0));
+        assert_eq!(resume.latest_id, 1);
+        assert_eq!(resume.missed.iter().map(|change| change.id).collect::<Vec<_>>(), vec![1]);
+        assert!(!bus.resume("acme", Some(1)).gap);
+        assert!(bus.resume("globex", Some(2)).gap);
+            // Globex never got to 2, so a client claiming it did is from before a restart.
+    }
+}
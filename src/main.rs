@@ -1,21 +1,98 @@
 /* This file is used to start our server and register all necessary routes for our todo API. This implements all logic from our handler/model/response modules. This is the entry piont for our API. */
 
-/* These lines tell Rust to include code from other files (modules) named `handler.rs`, `model.rs`, `response.rs`, and `route.rs`. This keeps your code organized by separating different responsibilities (like handling requests, defining data, formatting responses, and setting up routes). */
+/* These lines tell Rust to include code from other files (modules) named `api_key.rs`, `audit.rs`, `auth.rs`, `dependency.rs`, `event_store.rs`, `graphql.rs`, `grpc.rs`, `handler.rs`, `jwt.rs`, `live.rs`, `model.rs`, `notifier.rs`, `openapi.rs`, `policy.rs`, `position.rs`, `postman.rs`, `rate_limit.rs`, `recurrence.rs`, `response.rs`, `revision.rs`, `route.rs`, `scheduler.rs`, `share_handler.rs`, `sync.rs`, `sync_handler.rs`, `tenant.rs`, `testing.rs` (only built for tests), `trash.rs`, `trash_handler.rs`, `undo.rs`, `undo_handler.rs`, `v2.rs`, `v2_handler.rs`, `version.rs`, `webhook.rs`, `webhook_handler.rs`, and `workflow.rs`. This keeps your code organized by separating different responsibilities (like handling requests, defining data, formatting responses, and setting up routes). */
+mod api_key;
+mod audit;
+mod auth;
+mod dependency;
+mod event_store;
+mod graphql;
+mod grpc;
 mod handler;
+mod jwt;
+mod live;
+#[allow(non_snake_case)]
+    // The GraphQL derives on our camelCase structs (see graphql.rs) generate methods with the same camelCase names.
 mod model;
+mod notifier;
+mod openapi;
+mod policy;
+mod position;
+mod postman;
+mod rate_limit;
+mod recurrence;
 mod response;
+mod revision;
 mod route;
+mod scheduler;
+mod share_handler;
+mod sync;
+mod sync_handler;
+mod tenant;
+#[cfg(test)]
+mod testing;
+    // Helpers shared by the tests.
+mod trash;
+mod trash_handler;
+mod undo;
+mod undo_handler;
+mod v2;
+mod v2_handler;
+mod version;
+mod webhook;
+mod webhook_handler;
+mod workflow;
 
 /* Imports types and constants from the Axum web framework */
+use std::net::SocketAddr;
+    // The type of a client's network address (IP and port).
 use axum::http::{
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-    HeaderValue, Method,
+    header::{
+        ACCEPT, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, AUTHORIZATION, CONTENT_TYPE, LINK,
+        ORIGIN, RETRY_AFTER,
+    },
+    HeaderName, HeaderValue, Method,
         // header: HTTP header names used for controlling what kind of requests our server will accept
+        // HeaderName: Represents the name of a custom HTTP header
         // HeaderValue: Represents the value of an HTTP header
         // Method: Represents HTTP methods like GET, POST, PATCH, DELETE.
 };
+use api_key::API_KEY_HEADER;
+    // Imports the name of the header API keys are sent in, so the CORS policy can allow it.
+use audit::REQUEST_ID_HEADER;
+    // Imports the header each request's ID travels in (see audit.rs).
+use event_store::EventStore;
+    // Imports the event log that backs the event-sourced storage mode.
+use grpc::spawn_grpc_server;
+    // Imports the function that starts the gRPC server (see grpc.rs).
+use auth::SessionStore;
+    // Imports the store of logged-in session tokens.
+use jwt::JwtVerifier;
+    // Imports the JWT checker used to authenticate "Authorization: Bearer <jwt>" requests.
+use live::EventBus;
+    // Imports the channel that pushes todo changes to connected WebSocket clients.
+use model::{todo_db, AppState};
+    // Imports the function that creates our shared, in-memory todo list, and the struct that bundles everything handlers share.
+use notifier::notifiers_from_env;
+    // Imports the function that decides where reminders get sent (console, webhook, email).
+use rate_limit::RateLimiter;
+    // Imports the per-client request limits that keep one busy script from slowing everyone else down.
+use revision::RevisionStore;
+    // Imports the store that keeps old versions of each todo so edits can be rolled back.
 use route::create_router;
     // Imports the create_router function from our 'route' module. Sets up all routes and URLS (and what they do) for our API.
+use scheduler::{spawn_reminder_scheduler, SchedulerConfig};
+    // Imports the background task that sends reminders when they are due.
+use tenant::{TenantConfig, TENANT_HEADER};
+    // Imports the workspace settings (quotas, subdomains) and the header a workspace can be named in.
+use trash::{spawn_trash_purger, TrashConfig};
+    // Imports the background task that empties old todos out of the trash.
+use undo::UndoStore;
+    // Imports each user's undo/redo stacks.
+use webhook::{spawn_webhook_dispatcher, WebhookStore};
+    // Imports the webhook subscriptions and the background task that delivers them.
+use workflow::Workflow;
+    // Imports the Kanban workflow (columns and allowed moves between them).
 use tower_http::cors::CorsLayer;
     // Imports the 'CorsLayer' type, controls what websites are allowed to talk to our API (not important now, but would be if we had frontend AND a backend.)
 
@@ -30,11 +107,77 @@ async fn main() {
             // Only allows certain HTTP methods. GET POST PATCH DELETE, which are tested in Postman.
         .allow_credentials(true)
             // Allows cookies or authentication info to be sent. - Not imporant at this phase of our API.
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
-            // Only allows certain headers in our requests.
+        .allow_headers([
+            AUTHORIZATION,
+            ACCEPT,
+            CONTENT_TYPE,
+            HeaderName::from_static(API_KEY_HEADER),
+            HeaderName::from_static(TENANT_HEADER),
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static("last-event-id"),
+        ])
+            // Only allows certain headers in our requests. 'X-API-Key' is how bots and scripts authenticate, 'X-Tenant-ID' picks the workspace, and 'X-Request-ID' lets clients tag requests for the audit log, and 'Last-Event-ID' resumes a change stream.
+        .expose_headers([
+            RETRY_AFTER,
+            HeaderName::from_static("ratelimit-limit"),
+            HeaderName::from_static("ratelimit-remaining"),
+            HeaderName::from_static("ratelimit-reset"),
+            HeaderName::from_static("ratelimit-policy"),
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static("deprecation"),
+            HeaderName::from_static("sunset"),
+            LINK,
+        ])
+            // Lets the frontend read the rate limit headers (so it can slow down before it gets a 429), the request ID, and the headers that say version 1 of the todo routes is going away.
+        .vary([
+            ORIGIN,
+            ACCESS_CONTROL_REQUEST_METHOD,
+            ACCESS_CONTROL_REQUEST_HEADERS,
+            ACCEPT,
+        ]);
+            // The first three are what CORS always sends; 'Accept' is added because "/api/todos" answers with version 1 or 2 depending on it (see version.rs), so caches must keep the two apart.
+
+    /* Works out the dummy password hash now, so the first failed login doesn't take longer than the rest (see auth.rs). */
+    tokio::task::spawn_blocking(auth::dummy_hash);
+
+    /* Creates the shared todo list. */
+    let db = todo_db();
+
+    /* Bundles everything the handlers share. The workflow comes from WORKFLOW_FILE, or the built-in backlog → done flow. */
+    /* Session tokens expire after SESSION_TTL_SECONDS (see auth.rs). */
+    /* JWT verification is switched on by JWT_SECRET and/or JWT_JWKS_FILE (see jwt.rs). */
+    /* Workspace quotas and the subdomain they can be picked by come from TENANT_* variables (see tenant.rs). */
+    /* Request limits come from RATE_LIMIT* variables (see rate_limit.rs), TODO_MAX_REVISIONS caps each todo's history (see revision.rs), and UNDO_DEPTH how many operations can be undone (see undo.rs). */
+    /* STORAGE_MODE=events keeps every change in an event log, which point-in-time queries and rebuilds read from (see event_store.rs). */
+    /* LIVE_BUFFER, LIVE_REPLAY and LIVE_HEARTBEAT_SECONDS tune the live updates sent over "/api/ws" and "/api/todos/events" (see live.rs). */
+    let state = AppState::new(
+        db,
+        Workflow::from_env(),
+        SessionStore::from_env(),
+        JwtVerifier::from_env(),
+        TenantConfig::from_env(),
+        RateLimiter::from_env(),
+        RevisionStore::from_env(),
+        UndoStore::from_env(),
+        EventBus::from_env(),
+        WebhookStore::from_env(),
+        EventStore::from_env(),
+    );
+
+    /* Starts the reminder scheduler, which marks sent reminders through the same audit log as everything else. */
+    spawn_reminder_scheduler(state.clone(), notifiers_from_env(), SchedulerConfig::from_env());
+
+    /* Starts the task that purges todos once they've been in the trash for TRASH_RETENTION_DAYS (see trash.rs). */
+    spawn_trash_purger(state.clone(), TrashConfig::from_env());
+
+    /* Starts the task that sends webhooks for todo changes, retrying failed ones according to WEBHOOK_* variables (see webhook.rs). */
+    spawn_webhook_dispatcher(state.clone());
+
+    /* Starts the gRPC server on its own port, GRPC_PORT (default 50051), working on the same todos (see grpc.rs and proto/todo.proto). */
+    spawn_grpc_server(state.clone());
 
     /* Creates our main application by calling: */
-    let app = create_router().layer(cors);
+    let app = create_router(state).layer(cors);
         // create_router(): sets up all of our API routes.
         // Adds a CORS policy as a "layer" to control who can access our API.
 
@@ -43,7 +186,10 @@ async fn main() {
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
         // Sets up our server to listen for incoming connections on port 8000 on all of our network interfaces ('0.0.0.0')
         // This is how our server is able to receive requests from Postman or other browsers instead of just at a frontend which isn't built yet.
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
         // Starts the Axum server, using the listener (network socket) and our app (routes + CORS). The server now handles incoming requests.
+        // 'with_connect_info' records each client's IP address, which the rate limiter uses for callers that aren't logged in.
         // This is the line that aactually allows our API to be available to clients.
 }
\ No newline at end of file
@@ -5,9 +5,33 @@ This file defines the data structures (models) for your todo app, including the
 use chrono::prelude::*;
 /* Imports traits for converting Rust data to/from JSON or other formats. */
 use serde::{Deserialize, Serialize};
+/* Imports the derives that let the same structs be used in the GraphQL schema (see graphql.rs). */
+use async_graphql::{InputObject, SimpleObject};
+/* Imports the derives that describe the same structs in the OpenAPI document (see openapi.rs). */
+use utoipa::{IntoParams, ToSchema};
 /* Imports `Arc` (a thread-safe reference-counted pointer, lets you share data safely across threads) and `Mutex` (a lock to safely allow only one thread to access data at a time, but this one is async-friendly from `tokio`). */
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Mutex;
+/* Imports FromRef, which lets handlers take a single piece of our shared AppState. */
+use axum::extract::FromRef;
+/* Imports the Kanban workflow, API keys, sharing roles and the JWT verifier so they can be shared with the handlers. */
+use crate::{
+    api_key::{ApiKey, ApiKeyScope},
+    auth::SessionStore,
+    audit::AuditLog,
+    event_store::EventStore,
+    jwt::JwtVerifier,
+    live::EventBus,
+    policy::Role,
+    rate_limit::RateLimiter,
+    revision::RevisionStore,
+    tenant::TenantConfig,
+    undo::UndoStore,
+    webhook::{WebhookEvent, WebhookStore},
+    workflow::Workflow,
+};
+/* Imports Uuid so request bodies that reference another todo are validated as real IDs. */
+use uuid::Uuid;
 
 /* Allows us to write 'non-snakecase' field names. */
 #[allow(non_snake_case)]
@@ -15,42 +39,427 @@ use tokio::sync::Mutex;
     'Debug': Print it for debugging
     Deserialize, Serialize: Convert to/from JSON'
     'Clone': Make copies of it. */
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, SimpleObject, ToSchema)]
 /* Defines a public structure (like a class or a record) named 'Todo' */
 pub struct Todo {
     pub id: Option<String>, // Option: could be missing, not needed.
     pub title: String, // Required string
     pub content: String, // Required string
-    pub completed: Option<bool>, // Optional true/false
+    pub completed: Option<bool>, // Optional true/false. Kept in sync with 'status': true only in the workflow's done column.
+    pub status: Option<String>, // Workflow column the todo is in, e.g. "backlog" or "review"
     pub createdAt: Option<DateTime<Utc>>, // Option date/time
     pub updatedAt: Option<DateTime<Utc>>, // Option date/time
+    #[serde(default)]
+    pub blockedBy: Vec<String>, // IDs of the todos that have to be completed before this one can start.
+    #[serde(default)]
+    pub blocks: Vec<String>, // Computed: IDs of the todos waiting on this one. Filled in before a response is sent.
+    #[serde(default)]
+    pub blocked: bool, // Computed: true while any todo in 'blockedBy' is still incomplete.
+    pub dueDate: Option<DateTime<Utc>>, // Optional date/time the todo is due
+    pub recurrence: Option<String>, // Optional RFC 5545 rule, e.g. "FREQ=WEEKLY;BYDAY=MO"
+    pub seriesId: Option<String>, // Shared by every occurrence of a recurring todo
+    pub occurrence: Option<u32>, // 1-based number of this occurrence within its series
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub seriesStart: Option<DateTime<Utc>>, // Not sent to clients: the due date the series counts from, so a monthly todo on the 31st stays on the 31st (see recurrence.rs)
+    #[serde(default)]
+    pub reminders: Vec<Reminder>, // Nudges sent by the reminder scheduler before the todo is due
+    pub list: Option<String>, // Optional name of the list the todo belongs to; None is the default list
+    #[serde(default)]
+    pub tags: Vec<String>, // Free-form labels, e.g. "urgent"; live connections can filter on them (see live.rs)
+    pub position: Option<String>, // Sort key for manual (drag-and-drop) ordering within the todo's list
+    pub ownerId: Option<String>, // ID of the user who created the todo; they always have the owner role on it
+    #[serde(default)]
+    pub sharedWith: Vec<Share>, // Other users this todo was shared with, and their role (see policy.rs)
+    pub deletedAt: Option<DateTime<Utc>>, // Set when the todo is moved to the trash; trashed todos are hidden everywhere else (see trash.rs)
 }
-#[derive(Debug, Deserialize)]
+
+/* One user a todo is shared with. */
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Serialize, Clone, SimpleObject, ToSchema)]
+pub struct Share {
+    pub userId: String,
+    pub role: Role,
+}
+
+/* A whole list shared by its owner with another user. The role applies to every todo the owner has in that list. */
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+pub struct ListShare {
+    pub ownerId: String,
+    pub list: String,
+    pub userId: String,
+    pub role: Role,
+}
+
+/* A registered user. The password itself is never stored, only its Argon2 hash. */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    pub passwordHash: String,
+    pub tenantId: String,
+        // The workspace the user registered in; they can't use any other.
+    pub createdAt: DateTime<Utc>,
+}
+
+/* A single reminder on a todo. It fires either at a fixed time ('remindAt') or a number of minutes before the todo's 'dueDate' ('offsetMinutes'). */
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, SimpleObject, ToSchema)]
+pub struct Reminder {
+    pub id: String,
+    pub remindAt: Option<DateTime<Utc>>,
+    pub offsetMinutes: Option<i64>,
+    pub sentAt: Option<DateTime<Utc>>, // Set by the scheduler once the reminder has been dispatched
+}
+
+impl Reminder {
+    /* Works out when this reminder should go off for the given todo. Offset reminders follow the due date, so they move when the due date moves. */
+    pub fn fire_at(&self, todo: &Todo) -> Option<DateTime<Utc>> {
+        match (self.remindAt, self.offsetMinutes) {
+            (Some(remind_at), _) => Some(remind_at),
+            (None, Some(offset)) => todo.dueDate.map(|due| due - chrono::Duration::minutes(offset)),
+            (None, None) => None,
+        }
+    }
+}
+
+/* True if a todo other than the one with ID 'id' already uses this title. Titles are unique within a workspace ('todos' is one workspace's list), with two exceptions: a todo in the trash gives its title up, and the occurrences of a recurring todo (those with the same 'series_id') all share their series' title. */
+pub fn title_taken(todos: &[Todo], title: &str, id: Option<&str>, series_id: Option<&str>) -> bool {
+    todos.iter().any(|todo| {
+        todo.title == title
+            && todo.deletedAt.is_none()
+            && todo.id.as_deref() != id
+            && (series_id.is_none() || todo.seriesId.as_deref() != series_id)
+    })
+}
+
+/* Tidies the tags a client sent: surrounding spaces are trimmed, and empty tags and repeats are dropped. */
+pub fn clean_tags(tags: Vec<String>) -> Vec<String> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !cleaned.iter().any(|seen| seen == tag) {
+            cleaned.push(tag.to_string());
+        }
+    }
+    cleaned
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, InputObject, ToSchema)]
+#[graphql(name = "CreateTodoInput")]
 pub struct CreateTodoSchema {
     pub title: String,
     pub content: String,
+    pub status: Option<String>,
+    pub dueDate: Option<DateTime<Utc>>,
+    pub recurrence: Option<String>,
+    pub list: Option<String>,
+    pub tags: Option<Vec<String>>,
 }
 
-/* Defines a type alias 'DB' for a thread-safe, shareable, lockable list of 'Todo' items. */
-pub type DB = Arc<Mutex<Vec<Todo>>>;
+/* Defines a type alias 'DB' for a thread-safe, shareable, lockable store of 'Todo' items. Each workspace (see tenant.rs) has its own list, keyed by the workspace name, so one workspace's todos are never mixed in with another's. */
+pub type DB = Arc<Mutex<HashMap<String, Vec<Todo>>>>;
 
-/* Defines a function that creates and returns a new, empty, thread-safe todo list. */
+/* Defines a function that creates and returns a new, empty, thread-safe todo store. */
 pub fn todo_db() -> DB {
-    Arc::new(Mutex::new(Vec::new()))
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+/* Registered users, and logged-in sessions (see auth.rs). */
+pub type UserDB = Arc<Mutex<Vec<User>>>;
+pub type SessionDB = Arc<SessionStore>;
+/* Issued API keys (only their hashes, see api_key.rs). */
+pub type ApiKeyDB = Arc<Mutex<Vec<ApiKey>>>;
+/* Lists that have been shared with other users. */
+pub type ListShareDB = Arc<Mutex<Vec<ListShare>>>;
+
+/* Everything our handlers can share. Handlers ask for just the part they need (for example 'State<DB>'); the 'FromRef' impls below tell Axum how to pull that part out of the whole state. */
+#[derive(Clone)]
+pub struct AppState {
+    pub db: DB,
+    pub workflow: Arc<Workflow>,
+    pub users: UserDB,
+    pub sessions: SessionDB,
+    pub api_keys: ApiKeyDB,
+    pub list_shares: ListShareDB,
+    pub jwt: Option<Arc<JwtVerifier>>,
+        // None when JWT support isn't configured.
+    pub tenants: Arc<TenantConfig>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub audit: AuditLog,
+    pub revisions: Arc<RevisionStore>,
+    pub undo: Arc<UndoStore>,
+    pub events: Arc<EventBus>,
+    pub webhooks: Arc<WebhookStore>,
+    pub event_store: Arc<EventStore>,
+}
+
+impl AppState {
+    /* Creates the shared state with an empty todo list and no users. Each setting-driven part is built from the environment in main.rs and handed in here. */
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        db: DB,
+        workflow: Workflow,
+        sessions: SessionStore,
+        jwt: Option<JwtVerifier>,
+        tenants: TenantConfig,
+        rate_limiter: RateLimiter,
+        revisions: RevisionStore,
+        undo: UndoStore,
+        events: EventBus,
+        webhooks: WebhookStore,
+        event_store: EventStore,
+    ) -> Self {
+        AppState {
+            db,
+            workflow: Arc::new(workflow),
+            users: Arc::new(Mutex::new(Vec::new())),
+            sessions: Arc::new(sessions),
+            api_keys: Arc::new(Mutex::new(Vec::new())),
+            list_shares: Arc::new(Mutex::new(Vec::new())),
+            jwt: jwt.map(Arc::new),
+            tenants: Arc::new(tenants),
+            rate_limiter: Arc::new(rate_limiter),
+            audit: Arc::new(Mutex::new(HashMap::new())),
+            revisions: Arc::new(revisions),
+            undo: Arc::new(undo),
+            events: Arc::new(events),
+            webhooks: Arc::new(webhooks),
+            event_store: Arc::new(event_store),
+        }
+    }
+}
+
+/* The state tests run against: every setting at its default, and no rate limits. */
+#[cfg(test)]
+impl AppState {
+    pub fn for_tests() -> Self {
+        AppState::new(
+            todo_db(),
+            Workflow::from_env(),
+            SessionStore::from_env(),
+            None,
+            TenantConfig::from_env(),
+            RateLimiter::unlimited(),
+            RevisionStore::from_env(),
+            UndoStore::from_env(),
+            EventBus::from_env(),
+            WebhookStore::from_env(),
+            EventStore::from_env(),
+        )
+    }
+}
+
+impl FromRef<AppState> for DB {
+    fn from_ref(state: &AppState) -> Self {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for UserDB {
+    fn from_ref(state: &AppState) -> Self {
+        state.users.clone()
+    }
+}
+
+impl FromRef<AppState> for SessionDB {
+    fn from_ref(state: &AppState) -> Self {
+        state.sessions.clone()
+    }
+}
+
+impl FromRef<AppState> for ApiKeyDB {
+    fn from_ref(state: &AppState) -> Self {
+        state.api_keys.clone()
+    }
+}
+
+impl FromRef<AppState> for ListShareDB {
+    fn from_ref(state: &AppState) -> Self {
+        state.list_shares.clone()
+    }
+}
+
+impl FromRef<AppState> for Option<Arc<JwtVerifier>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.jwt.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<WebhookStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.webhooks.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<EventStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.event_store.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<EventBus> {
+    fn from_ref(state: &AppState) -> Self {
+        state.events.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<UndoStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.undo.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<RevisionStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.revisions.clone()
+    }
+}
+
+impl FromRef<AppState> for AuditLog {
+    fn from_ref(state: &AppState) -> Self {
+        state.audit.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<TenantConfig> {
+    fn from_ref(state: &AppState) -> Self {
+        state.tenants.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Workflow> {
+    fn from_ref(state: &AppState) -> Self {
+        state.workflow.clone()
+    }
 }
 
 /* Same as above but adds 'default': allows the struct to be created with default values. */
-#[derive(Debug, Deserialize, Default)]
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct QueryOptions {
     pub page: Option<usize>,
     pub limit: Option<usize>,
+    pub actionable: Option<bool>,
+        // When true, only todos that are incomplete and not blocked by another todo are listed.
+    pub list: Option<String>,
+        // Only lists todos in this list.
+    pub includeDeleted: Option<bool>,
+        // When true, todos in the trash are listed too.
+    pub as_of: Option<DateTime<Utc>>,
+        // Lists the todos as they were at this time (needs the event log, see event_store.rs).
+}
+
+/* Query options for a sync pull: the token from the previous pull, if any. */
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SyncQuery {
+    pub since: Option<String>,
+}
+
+/* Query options for fetching a single todo. */
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetTodoQuery {
+    pub includeDeleted: Option<bool>,
+        // When true, a todo in the trash can still be fetched.
+    pub as_of: Option<DateTime<Utc>>,
+        // Fetches the todo as it was at this time (needs the event log, see event_store.rs).
+}
+
+/* Filters for the audit log. 'from'/'to' limit the time range, and 'format=ndjson' downloads every match as one JSON object per line. */
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    pub todoId: Option<String>,
+    pub actorId: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+    pub format: Option<String>,
 }
 
 #[allow(non_snake_case)]
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, InputObject, ToSchema)]
+#[graphql(name = "UpdateTodoInput")]
 /* Defines a struct for updated a todo item, note that each field is option, so you can update the todo only what you want and everything else will stay the same. */
 pub struct UpdateTodoSchema {
     pub title: Option<String>,
     pub content: Option<String>,
     pub completed: Option<bool>,
-}
\ No newline at end of file
+    pub status: Option<String>,
+        // Moves the todo to another workflow column. Only moves allowed by the workflow are accepted.
+    pub dueDate: Option<DateTime<Utc>>,
+    pub recurrence: Option<String>,
+        // Sending an empty string stops the todo from recurring.
+    pub list: Option<String>,
+        // Moves the todo to another list (at the end of it). An empty string moves it back to the default list.
+    pub tags: Option<Vec<String>>,
+        // Replaces the todo's tags. An empty array removes them all.
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, ToSchema)]
+/* Body for adding a dependency: the todo in the URL will be blocked by the todo with this ID. */
+pub struct AddDependencySchema {
+    pub blockedBy: Uuid,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, ToSchema)]
+/* Body for adding a reminder. Send exactly one of 'remindAt' (an absolute time) or 'offsetMinutes' (minutes before the due date). */
+pub struct CreateReminderSchema {
+    pub remindAt: Option<DateTime<Utc>>,
+    pub offsetMinutes: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+/* Body for moving a todo. 'before' puts it right in front of that todo, 'after' right behind it; sending both puts it between two neighbours. */
+pub struct MoveTodoSchema {
+    pub before: Option<Uuid>,
+    pub after: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+/* Body for registering and for logging in. */
+pub struct AuthSchema {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+/* Body for issuing an API key. */
+pub struct CreateApiKeySchema {
+    pub name: String,
+    pub scope: ApiKeyScope,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+/* Body for subscribing a URL to webhooks. Leaving out 'events' subscribes to every event, and leaving out 'secret' generates one. */
+pub struct CreateWebhookSchema {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+/* Body for changing a webhook subscription. Only the fields that are sent change. */
+pub struct UpdateWebhookSchema {
+    pub url: Option<String>,
+    pub events: Option<Vec<WebhookEvent>>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+/* Body for sharing a todo or a list with another user. Only 'editor' and 'viewer' can be handed out. */
+pub struct ShareSchema {
+    pub username: String,
+    pub role: Role,
+}
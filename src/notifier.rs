@@ -0,0 +1,163 @@
+/* This file defines the places a reminder can be sent ("notifier sinks"). Every sink implements the 'Notifier' trait, so the scheduler doesn't care whether a reminder ends up in the console, on a webhook, or in someone's inbox. Sinks are switched on with environment variables in 'notifiers_from_env'. */
+
+use std::{future::Future, pin::Pin, time::Duration};
+
+use chrono::prelude::*;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+
+/* What gets sent when a reminder fires. */
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Clone)]
+pub struct Notification {
+    pub todoId: String,
+    pub reminderId: String,
+    pub title: String,
+    pub content: String,
+    pub dueDate: Option<DateTime<Utc>>,
+    pub remindAt: DateTime<Utc>,
+}
+
+/* The future a notifier returns. Boxing it lets us keep different notifiers together in one list. */
+pub type SendFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/* Anything that can deliver a notification. Add a new sink by implementing this trait and pushing it in 'notifiers_from_env'. */
+pub trait Notifier: Send + Sync {
+    /* Short name used in log messages, e.g. "webhook". */
+    fn name(&self) -> &'static str;
+    fn send<'a>(&'a self, notification: &'a Notification) -> SendFuture<'a>;
+}
+
+/* Prints the reminder to the server console. Always enabled. */
+pub struct StdoutNotifier;
+
+impl Notifier for StdoutNotifier {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> SendFuture<'a> {
+        Box::pin(async move {
+            println!(
+                "⏰ Reminder: '{}' (todo {}) is due {}",
+                notification.title,
+                notification.todoId,
+                notification
+                    .dueDate
+                    .map(|due| due.to_rfc3339())
+                    .unwrap_or_else(|| "soon".to_string())
+            );
+            Ok(())
+        })
+    }
+}
+
+/* POSTs the notification as JSON to a URL. Any non-2xx response counts as a failure so it gets retried. */
+pub struct WebhookNotifier {
+    pub url: String,
+    pub client: reqwest::Client,
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> SendFuture<'a> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .post(&self.url)
+                .json(notification)
+                .send()
+                .await
+                .map_err(|err| err.to_string())?;
+
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("webhook responded with {}", response.status()))
+            }
+        })
+    }
+}
+
+/* Emails the reminder through an SMTP server. There is no TLS or login here, which is what local mail catchers (like MailHog or Mailpit on port 1025) expect. */
+pub struct SmtpNotifier {
+    pub from: String,
+    pub to: String,
+    pub transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl Notifier for SmtpNotifier {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> SendFuture<'a> {
+        Box::pin(async move {
+            let email = Message::builder()
+                .from(self.from.parse().map_err(|err| format!("invalid from address: {}", err))?)
+                .to(self.to.parse().map_err(|err| format!("invalid to address: {}", err))?)
+                .subject(format!("Reminder: {}", notification.title))
+                .body(format!(
+                    "{}\n\nDue: {}\nTodo ID: {}",
+                    notification.content,
+                    notification
+                        .dueDate
+                        .map(|due| due.to_rfc3339())
+                        .unwrap_or_else(|| "no due date".to_string()),
+                    notification.todoId
+                ))
+                .map_err(|err| err.to_string())?;
+
+            self.transport
+                .send(email)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        })
+    }
+}
+
+/* Builds the list of notifiers from environment variables:
+    REMINDER_WEBHOOK_URL: turns on the webhook notifier (REMINDER_WEBHOOK_TIMEOUT_SECONDS, default 10, is how long it waits for an answer).
+    SMTP_HOST (+ optional SMTP_PORT, default 1025), REMINDER_EMAIL_FROM and REMINDER_EMAIL_TO: turn on the email notifier.
+The console notifier is always included. */
+pub fn notifiers_from_env() -> Vec<Box<dyn Notifier>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(StdoutNotifier)];
+
+    if let Ok(url) = std::env::var("REMINDER_WEBHOOK_URL") {
+        let timeout = std::env::var("REMINDER_WEBHOOK_TIMEOUT_SECONDS")
+            .ok()
+            .and_then(|seconds| seconds.parse().ok())
+            .filter(|seconds| *seconds > 0)
+            .unwrap_or(10);
+        notifiers.push(Box::new(WebhookNotifier {
+            url,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(timeout))
+                .build()
+                .unwrap_or_default(),
+                // Reminders are sent one after another, so a receiver that never answers would hold up every reminder behind it.
+        }));
+    }
+
+    if let (Ok(host), Ok(from), Ok(to)) = (
+        std::env::var("SMTP_HOST"),
+        std::env::var("REMINDER_EMAIL_FROM"),
+        std::env::var("REMINDER_EMAIL_TO"),
+    ) {
+        let port = std::env::var("SMTP_PORT")
+            .ok()
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(1025);
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .build();
+            // 'builder_dangerous' means plain SMTP without TLS, fine for a local mail catcher.
+        notifiers.push(Box::new(SmtpNotifier { from, to, transport }));
+    }
+
+    notifiers
+}
@@ -0,0 +1,207 @@
+/* This file builds the OpenAPI 3.1 document for the REST API from the '#[utoipa::path]' attribute on each handler, served at "/api/openapi.json" with Swagger UI at "/api/docs". The test at the bottom checks it against the routes in route.rs. */
+
+use utoipa::{
+    openapi::{
+        security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+        Deprecated,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    api_key::API_KEY_HEADER, handler, model::ListShare, share_handler, sync_handler, trash_handler, undo_handler, v2_handler,
+    webhook_handler,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Todo API",
+        description = "A todo API written in Rust with Axum. Every route except registering, logging in and the health check needs a login: \"Authorization: Bearer <token>\" (a login token or a JWT) or \"X-API-Key: <key>\". Send \"X-Tenant-ID\" to pick a workspace. Errors come back as {\"status\": \"fail\", \"message\": ...}."
+    ),
+    paths(
+        handler::health_checker_handler,
+        handler::register_user_handler,
+        handler::login_user_handler,
+        handler::me_handler,
+        handler::list_api_keys_handler,
+        handler::create_api_key_handler,
+        handler::revoke_api_key_handler,
+        handler::todos_list_handler,
+        handler::create_todo_handler,
+        handler::get_todo_handler,
+        handler::edit_todo_handler,
+        handler::delete_todo_handler,
+        v2_handler::v2_todos_list_handler,
+        v2_handler::v2_create_todo_handler,
+        v2_handler::v2_get_todo_handler,
+        v2_handler::v2_edit_todo_handler,
+        v2_handler::v2_delete_todo_handler,
+        handler::todos_order_handler,
+        handler::add_dependency_handler,
+        handler::remove_dependency_handler,
+        share_handler::share_todo_handler,
+        share_handler::unshare_todo_handler,
+        share_handler::share_list_handler,
+        share_handler::unshare_list_handler,
+        handler::revisions_handler,
+        handler::restore_revision_handler,
+        handler::move_todo_handler,
+        handler::skip_occurrence_handler,
+        handler::occurrences_handler,
+        handler::add_reminder_handler,
+        handler::delete_reminder_handler,
+        handler::board_handler,
+        trash_handler::trash_list_handler,
+        trash_handler::trash_empty_handler,
+        trash_handler::trash_restore_handler,
+        trash_handler::trash_purge_handler,
+        undo_handler::undo_history_handler,
+        undo_handler::undo_handler,
+        undo_handler::redo_handler,
+        handler::ws_handler,
+        handler::todo_events_handler,
+        webhook_handler::list_webhooks_handler,
+        webhook_handler::create_webhook_handler,
+        webhook_handler::get_webhook_handler,
+        webhook_handler::edit_webhook_handler,
+        webhook_handler::delete_webhook_handler,
+        webhook_handler::webhook_deliveries_handler,
+        webhook_handler::test_webhook_handler,
+        sync_handler::sync_pull_handler,
+        sync_handler::sync_push_handler,
+        handler::rebuild_projection_handler,
+        handler::audit_handler,
+        handler::graphql_handler,
+        handler::graphql_ws_handler,
+        handler::postman_collection_handler,
+    ),
+    components(schemas(ListShare)),
+        // Schemas used by the routes above are added automatically; ListShare is only mentioned in a description.
+    modifiers(&SecurityAddon, &DeprecateV1),
+    security(("bearer" = []), ("api_key" = [])),
+        // Every route needs one of the two, unless its attribute says otherwise.
+)]
+pub struct ApiDoc;
+
+/* Describes the two ways to log in. They can't be written in the attribute above. */
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+    }
+}
+
+/* Marks each version 1 route that has a version 2 replacement (see version.rs) as deprecated, so generated clients warn about it. */
+struct DeprecateV1;
+
+impl Modify for DeprecateV1 {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let successors: Vec<String> = openapi
+            .paths
+            .paths
+            .keys()
+            .filter_map(|path| path.strip_prefix("/api/v2/"))
+            .map(|rest| format!("/api/{}", rest))
+            .collect();
+        for path in successors {
+            let Some(item) = openapi.paths.paths.get_mut(&path) else {
+                continue;
+            };
+            for operation in [&mut item.get, &mut item.post, &mut item.put, &mut item.patch, &mut item.delete]
+                .into_iter()
+                .flatten()
+            {
+                operation.deprecated = Some(Deprecated::True);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+        Router,
+    };
+    use tower::ServiceExt;
+    use utoipa::OpenApi;
+
+    use super::ApiDoc;
+    use crate::{model::AppState, route::create_router};
+
+    const METHODS: [Method; 5] = [Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE];
+
+    /* Every path the document describes, written the Axum way ("/api/todos/:id"), with the methods it documents. */
+    fn documented_routes(doc: &utoipa::openapi::OpenApi) -> BTreeMap<String, BTreeSet<Method>> {
+        let mut routes = BTreeMap::new();
+        for (path, item) in &doc.paths.paths {
+            let path = path
+                .split('/')
+                .map(|segment| match segment.strip_prefix('{').and_then(|name| name.strip_suffix('}')) {
+                    Some(name) => format!(":{}", name),
+                    None => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let operations = [&item.get, &item.post, &item.put, &item.patch, &item.delete];
+            let methods = METHODS
+                .into_iter()
+                .zip(operations)
+                .filter(|(_, operation)| operation.is_some())
+                .map(|(method, _)| method)
+                .collect();
+            routes.insert(path, methods);
+        }
+        routes
+    }
+
+    /* The methods the router answers on a path. A method it doesn't route gets a 405 (or a 404 for an unknown path) before any handler or login check runs. */
+    async fn routed_methods(router: &Router, path: &str) -> BTreeSet<Method> {
+        let uri = path
+            .split('/')
+            .map(|segment| match segment {
+                ":rev" => "1",
+                segment if segment.starts_with(':') => "00000000-0000-0000-0000-000000000000",
+                segment => segment,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+            // A value every path parameter accepts.
+        let mut methods = BTreeSet::new();
+        for method in METHODS {
+            let request = Request::builder().method(method.clone()).uri(&uri).body(Body::empty()).unwrap();
+            let status = router.clone().oneshot(request).await.unwrap().status();
+            if status != StatusCode::METHOD_NOT_ALLOWED && status != StatusCode::NOT_FOUND {
+                methods.insert(method);
+            }
+        }
+        methods
+    }
+
+    /* Every path the utoipa handlers register in the document is routed, for exactly the methods documented. */
+    #[tokio::test]
+    async fn document_matches_router() {
+        let router = create_router(AppState::for_tests());
+        let mut mismatches = Vec::new();
+        for (path, documented) in documented_routes(&ApiDoc::openapi()) {
+            let routed = routed_methods(&router, &path).await;
+            if routed != documented {
+                mismatches.push(format!("{}: documented {:?}, routed {:?}", path, documented, routed));
+            }
+        }
+        assert!(mismatches.is_empty(), "the OpenAPI document and the router disagree:\n{}", mismatches.join("\n"));
+    }
+}
@@ -0,0 +1,159 @@
+/* This file decides who may do what with a todo. A user gets a role on a todo directly or through a shared list, and the higher one wins; the rules know nothing about HTTP, so they can be tested on their own. */
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::{ListShare, Todo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, async_graphql::Enum, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+        // Can see the todo.
+    Editor,
+        // Can also change it.
+    Owner,
+        // Can also delete it and share it. Whoever created a todo is always its owner.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    View,
+    Edit,
+    Delete,
+    Share,
+}
+
+/* Why a check failed. 'NotFound' is used when the user can't even see the todo, so handlers answer 404 and don't reveal that the ID exists. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denied {
+    NotFound,
+    Forbidden,
+}
+
+impl Role {
+    /* The lowest role that is allowed to perform each action. */
+    pub fn allows(self, action: Action) -> bool {
+        let needed = match action {
+            Action::View => Role::Viewer,
+            Action::Edit => Role::Editor,
+            Action::Delete | Action::Share => Role::Owner,
+        };
+        self >= needed
+    }
+}
+
+/* The best role a user has on a todo, or None if they have no access at all. */
+pub fn role_for(user_id: &str, todo: &Todo, list_shares: &[ListShare]) -> Option<Role> {
+    if todo.ownerId.as_deref() == Some(user_id) {
+        return Some(Role::Owner);
+    }
+
+    let direct = todo
+        .sharedWith
+        .iter()
+        .filter(|share| share.userId == user_id)
+        .map(|share| share.role);
+    let through_list = list_shares
+        .iter()
+        .filter(|share| {
+            share.userId == user_id
+                && todo.ownerId.as_deref() == Some(share.ownerId.as_str())
+                && todo.list.as_deref() == Some(share.list.as_str())
+        })
+        .map(|share| share.role);
+
+    direct.chain(through_list).max()
+}
+
+/* The one check every protected handler goes through. */
+pub fn check(user_id: &str, todo: &Todo, list_shares: &[ListShare], action: Action) -> Result<Role, Denied> {
+    match role_for(user_id, todo, list_shares) {
+        None => Err(Denied::NotFound),
+        Some(role) if role.allows(action) => Ok(role),
+        Some(_) => Err(Denied::Forbidden),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, role_for, Action, Denied, Role};
+    use crate::model::{ListShare, Share, Todo};
+
+    const ACTIONS: [Action; 4] = [Action::View, Action::Edit, Action::Delete, Action::Share];
+
+    /* A todo owned by "owner" in the "work" list, shared directly with "viewer" and "editor". */
+    fn todo() -> Todo {
+        Todo {
+            ownerId: Some("owner".to_string()),
+            list: Some("work".to_string()),
+            sharedWith: vec![
+                Share { userId: "viewer".to_string(), role: Role::Viewer },
+                Share { userId: "editor".to_string(), role: Role::Editor },
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn list_share(user_id: &str, list: &str, role: Role) -> ListShare {
+        ListShare {
+            ownerId: "owner".to_string(),
+            list: list.to_string(),
+            userId: user_id.to_string(),
+            role,
+        }
+    }
+
+    /* What each action gives for a user: Ok(role), or why it was denied. */
+    fn outcomes(user_id: &str, list_shares: &[ListShare]) -> Vec<Result<Role, Denied>> {
+        ACTIONS.iter().map(|action| check(user_id, &todo(), list_shares, *action)).collect()
+    }
+
+    #[test]
+    fn roles_come_from_ownership_and_shares() {
+        assert_eq!(role_for("owner", &todo(), &[]), Some(Role::Owner));
+        assert_eq!(role_for("editor", &todo(), &[]), Some(Role::Editor));
+        assert_eq!(role_for("viewer", &todo(), &[]), Some(Role::Viewer));
+        assert_eq!(role_for("stranger", &todo(), &[]), None);
+    }
+
+    #[test]
+    fn each_role_allows_its_actions() {
+        use Denied::*;
+        assert_eq!(outcomes("owner", &[]), vec![Ok(Role::Owner); 4]);
+        assert_eq!(
+            outcomes("editor", &[]),
+            vec![Ok(Role::Editor), Ok(Role::Editor), Err(Forbidden), Err(Forbidden)]
+        );
+        assert_eq!(
+            outcomes("viewer", &[]),
+            vec![Ok(Role::Viewer), Err(Forbidden), Err(Forbidden), Err(Forbidden)]
+        );
+        assert_eq!(outcomes("stranger", &[]), vec![Err(NotFound); 4]);
+    }
+
+    #[test]
+    fn list_shares_grant_a_role() {
+        use Denied::*;
+        let shares = [list_share("stranger", "work", Role::Editor)];
+        assert_eq!(role_for("stranger", &todo(), &shares), Some(Role::Editor));
+        assert_eq!(
+            outcomes("stranger", &shares),
+            vec![Ok(Role::Editor), Ok(Role::Editor), Err(Forbidden), Err(Forbidden)]
+        );
+
+        /* The higher of the direct and the list role wins. */
+        let shares = [list_share("viewer", "work", Role::Editor)];
+        assert_eq!(role_for("viewer", &todo(), &shares), Some(Role::Editor));
+        let shares = [list_share("editor", "work", Role::Viewer)];
+        assert_eq!(role_for("editor", &todo(), &shares), Some(Role::Editor));
+
+        /* A share of another list, or of the same list name by another owner, gives nothing. */
+        let shares = [list_share("stranger", "home", Role::Editor)];
+        assert_eq!(outcomes("stranger", &shares), vec![Err(NotFound); 4]);
+        let shares = [ListShare {
+            ownerId: "someone-else".to_string(),
+            ..list_share("stranger", "work", Role::Editor)
+        }];
+        assert_eq!(outcomes("stranger", &shares), vec![Err(NotFound); 4]);
+    }
+}
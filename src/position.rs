@@ -0,0 +1,197 @@
+/* This file gives todos a manual order for drag-and-drop. Every todo has a 'position' key, a short string like "V" or "Vk", and todos are shown sorted by it. To put a todo between two others we make up a key that sorts between their keys ("fractional indexing"), so nothing else has to be renumbered. Keys can only get longer over time, so when one gets too long we rebalance the whole list back to short, evenly spaced keys. A list belongs to one owner: other users' todos with the same list name are never renumbered. */
+
+use crate::{
+    audit::{AuditAction, Auditor},
+    auth::CurrentUser,
+    model::Todo,
+};
+
+/* The characters a key is made of, in sorting order (base 62). */
+const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/* Once a key gets longer than this, the list is rebalanced. */
+const MAX_KEY_LEN: usize = 10;
+
+fn digit_value(digit: u8) -> usize {
+    DIGITS.iter().position(|d| *d == digit).unwrap_or(0)
+}
+
+/* Returns a key that sorts strictly between 'before' and 'after'. An empty 'before' means "the start of the list", and None for 'after' means "the end of the list". Keys never end in '0', which keeps every gap open. */
+pub fn key_between(before: &str, after: Option<&str>) -> String {
+    let before = before.as_bytes();
+    let after = after.map(str::as_bytes);
+
+    /* Keeps any prefix both keys share, then works on the part where they differ. */
+    if let Some(after) = after {
+        let shared = (0..after.len())
+            .take_while(|&i| before.get(i).copied().unwrap_or(b'0') == after[i])
+            .count();
+        if shared > 0 {
+            let rest_before = &before[shared.min(before.len())..];
+            let rest_after = &after[shared..];
+            return format!(
+                "{}{}",
+                String::from_utf8_lossy(&after[..shared]),
+                key_between(
+                    &String::from_utf8_lossy(rest_before),
+                    Some(&String::from_utf8_lossy(rest_after))
+                )
+            );
+        }
+    }
+
+    let digit_before = before.first().map(|d| digit_value(*d)).unwrap_or(0);
+    let digit_after = after
+        .and_then(|after| after.first())
+        .map(|d| digit_value(*d))
+        .unwrap_or(DIGITS.len());
+
+    /* If there's room between the first digits, one digit is enough. */
+    if digit_after.saturating_sub(digit_before) > 1 {
+        return (DIGITS[(digit_before + digit_after) / 2] as char).to_string();
+    }
+
+    /* Otherwise keep the first digit and look for room further along. */
+    match after {
+        Some(after) if after.len() > 1 => (after[0] as char).to_string(),
+        _ => format!(
+            "{}{}",
+            DIGITS[digit_before] as char,
+            key_between(&String::from_utf8_lossy(before.get(1..).unwrap_or_default()), None)
+        ),
+    }
+}
+
+/* Sorts todos by position. The sort is stable, so todos with the same (or no) position keep insertion order. */
+pub fn sort(todos: &mut [Todo]) {
+    todos.sort_by(|a, b| a.position.cmp(&b.position));
+}
+
+/* True if the todo is in the owner's list. Trashed todos aren't in any list. */
+fn in_list(todo: &Todo, owner: &str, list: Option<&str>) -> bool {
+    todo.ownerId.as_deref() == Some(owner) && todo.list.as_deref() == list && todo.deletedAt.is_none()
+}
+
+/* The positions in one list, smallest first. */
+fn list_positions<'a>(todos: &'a [Todo], owner: &str, list: Option<&str>) -> Vec<&'a str> {
+    let mut positions: Vec<&str> = todos
+        .iter()
+        .filter(|todo| in_list(todo, owner, list))
+        .filter_map(|todo| todo.position.as_deref())
+        .collect();
+    positions.sort();
+    positions
+}
+
+/* A key that puts a todo at the end of the owner's list. If the key would get too long the list is rebalanced first, but only when the caller is the owner; anyone else (like an editor moving a shared todo) just gets the longer key. */
+pub async fn append(todos: &mut Vec<Todo>, owner: &str, list: Option<&str>, user: &CurrentUser, audit: &Auditor) -> String {
+    let last_key = |todos: &[Todo]| {
+        let last = list_positions(todos, owner, list).last().map(|key| key.to_string());
+        key_between(last.as_deref().unwrap_or(""), None)
+    };
+    let key = last_key(todos);
+    if key.len() <= MAX_KEY_LEN || user.id != owner {
+        return key;
+    }
+    rebalance(todos, owner, list, user, audit).await;
+    last_key(todos)
+}
+
+/* A key that puts a todo right between two neighbours (either can be missing at the start/end of the list). None if the key would get too long; the caller should then rebalance and look its neighbours up again. */
+pub fn between(before: Option<&str>, after: Option<&str>) -> Option<String> {
+    let key = key_between(before.unwrap_or(""), after);
+    (key.len() <= MAX_KEY_LEN).then_some(key)
+}
+
+/* Gives every todo in the owner's list a fresh, short key, evenly spread out and in the same order as before. Each todo that changed is recorded like any other edit, so undo, live updates, webhooks and the event log all see it. */
+pub async fn rebalance(todos: &mut Vec<Todo>, owner: &str, list: Option<&str>, user: &CurrentUser, audit: &Auditor) {
+    let mut members: Vec<Todo> = todos
+        .iter()
+        .filter(|todo| in_list(todo, owner, list))
+        .cloned()
+        .collect();
+    members.sort_by(|a, b| a.position.cmp(&b.position));
+
+    /* Picks a key width big enough to leave gaps between every todo. */
+    let base = DIGITS.len();
+    let mut width = 1;
+    while base.pow(width as u32) <= members.len() * 2 + 1 {
+        width += 1;
+    }
+    let step = base.pow(width as u32) / (members.len() + 1);
+
+    let now = chrono::Utc::now();
+    for (index, before) in members.into_iter().enumerate() {
+        let mut value = (index + 1) * step;
+        let mut digits = vec![b'0'; width];
+        for slot in digits.iter_mut().rev() {
+            *slot = DIGITS[value % base];
+            value /= base;
+        }
+        let key = String::from_utf8_lossy(&digits).trim_end_matches('0').to_string();
+            // Trailing zeros don't change the order, and dropping them keeps the "never ends in 0" rule.
+        if before.position.as_deref() == Some(key.as_str()) {
+            continue;
+        }
+        let after = Todo {
+            position: Some(key),
+            updatedAt: Some(now),
+            ..before.clone()
+        };
+        audit.record(todos, user, AuditAction::Edit, Some(&before), Some(&after)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::{
+        audit::AuditAction,
+        testing::{app, create_todo, login, send},
+    };
+
+    #[tokio::test]
+    async fn rebalancing_stays_in_the_owners_list() {
+        let (router, state) = app();
+        let alice = login(&router, "default", "alice").await;
+        let bob = login(&router, "default", "bob").await;
+        let mut ids = Vec::new();
+        for title in ["a", "b", "c"] {
+            let todo = create_todo(&router, &alice, json!({ "title": title, "content": "x" })).await;
+            ids.push(todo["id"].as_str().unwrap().to_string());
+        }
+        let bobs = create_todo(&router, &bob, json!({ "title": "bob's", "content": "x" })).await;
+        let auth = [("authorization", alice.as_str())];
+
+        /* Squeezing 'b' and 'c' in right after 'a' over and over makes the keys longer until the list is rebalanced. */
+        for round in 0..100 {
+            let (moved, anchor) = if round % 2 == 0 { (&ids[2], &ids[1]) } else { (&ids[1], &ids[2]) };
+            let uri = format!("/api/todos/{}/move", moved);
+            let (status, body) = send(&router, Method::POST, &uri, &auth, json!({ "before": anchor })).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+
+        let store = state.db.lock().await;
+        let todos = &store["default"];
+        let position = |id: &str| {
+            let todo = todos.iter().find(|todo| todo.id.as_deref() == Some(id)).unwrap();
+            todo.position.clone().unwrap()
+        };
+        assert!(position(&ids[0]) < position(&ids[1]) && position(&ids[1]) < position(&ids[2]));
+        assert!(ids.iter().all(|id| position(id).len() <= super::MAX_KEY_LEN));
+
+        /* 'a' was only ever moved by the rebalance, which went through the audit log; Bob's todo wasn't touched. */
+        let audit = state.audit.lock().await;
+        let edits = |id: &str| {
+            audit["default"]
+                .iter()
+                .filter(|entry| entry.todoId == id && matches!(entry.action, AuditAction::Edit))
+                .count()
+        };
+        assert!(edits(&ids[0]) > 0);
+        assert_eq!(edits(bobs["id"].as_str().unwrap()), 0);
+        assert_eq!(position(bobs["id"].as_str().unwrap()), bobs["position"].as_str().unwrap());
+    }
+}
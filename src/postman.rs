@@ -0,0 +1,489 @@
+/* This file turns the OpenAPI document (see openapi.rs) into a Postman v2.1 collection, served at "/api/postman.json". Run "make postman-collection" with the server running to refresh Todo.postman_collection.json. */
+
+use serde_json::{json, Map, Value};
+use utoipa::openapi::{path::Operation, OpenApi};
+
+/* Where the server listens by default (see main.rs), and the login the collection registers and logs in with. */
+const DEFAULT_VARIABLES: &[(&str, &str)] = &[
+    ("baseUrl", "http://localhost:8000"),
+    ("username", "postman"),
+    ("password", "postman-password"),
+    ("friendUsername", "postman-friend"),
+    ("token", ""),
+];
+
+/* Defaults for path variables that can't be captured from an earlier response. */
+const PATH_VARIABLE_DEFAULTS: &[(&str, &str)] = &[("list", "work"), ("rev", "1")];
+
+/* Example bodies for requests that need more than the required fields. The first todo recurs, so it can be skipped; todos and lists are shared with a second user, registered by the request after the login; the webhook is left switched on so it can be tested. */
+const EXAMPLE_BODIES: &[(&str, &str, &str)] = &[
+    (
+        "POST",
+        "/api/todos",
+        r#"{ "title": "{{$randomLoremWords}}", "content": "Water the plants", "dueDate": "2030-01-01T09:00:00Z", "recurrence": "FREQ=WEEKLY" }"#,
+    ),
+    ("POST", "/api/todos/{id}/share", r#"{ "username": "{{friendUsername}}", "role": "viewer" }"#),
+    ("POST", "/api/lists/{list}/share", r#"{ "username": "{{friendUsername}}", "role": "viewer" }"#),
+    ("PATCH", "/api/webhooks/{id}", r#"{ "active": true }"#),
+];
+
+/* Routes whose todo isn't 'todoId'. The todo created through v2 is a second todo, 'blockerId': the first todo is made to depend on it and moved next to it. The trash routes work on whichever todo was deleted last, 'trashedId'. */
+const TODO_VARIABLES: &[(&str, &str)] = &[("/api/v2/todos", "blockerId"), ("/api/trash", "trashedId")];
+
+/* Requests that have to run after everything else, in this order, because each needs the one before it: the second todo is deleted and restored from the trash, then the first todo is deleted and purged from it. */
+const RUN_LAST: &[(&str, &str)] = &[
+    ("DELETE", "/api/v2/todos/{id}"),
+    ("POST", "/api/trash/{id}/restore"),
+    ("DELETE", "/api/todos/{id}"),
+    ("DELETE", "/api/trash/{id}"),
+    ("DELETE", "/api/trash"),
+];
+
+/* Builds the whole collection. */
+pub fn collection(doc: &OpenApi) -> Value {
+    let components = serde_json::to_value(&doc.components).unwrap_or_default();
+    let schemas = components["schemas"].as_object().cloned().unwrap_or_default();
+
+    let mut items = Vec::new();
+    let mut deletes = Vec::new();
+    let mut last = Vec::new();
+    let mut path_variables: Vec<String> = Vec::new();
+    for (path, item) in &doc.paths.paths {
+        let methods = [
+            ("POST", &item.post),
+            ("GET", &item.get),
+            ("PATCH", &item.patch),
+            ("PUT", &item.put),
+            ("DELETE", &item.delete),
+        ];
+        for (method, operation) in methods {
+            let Some(operation) = operation else {
+                continue;
+            };
+            let Some(request) = request_item(method, path, operation, &schemas, &mut path_variables) else {
+                continue;
+            };
+            if let Some(position) = RUN_LAST.iter().position(|run_last| *run_last == (method, path.as_str())) {
+                last.push((position, request));
+            } else if method == "DELETE" {
+                deletes.push(request);
+            } else {
+                items.push(request);
+            }
+        }
+    }
+    items.extend(deletes.into_iter().rev());
+        // Deletes go after the other requests, the most deeply nested first (a todo's reminders before the share of its list).
+    last.sort_by_key(|(position, _)| *position);
+    items.extend(last.into_iter().map(|(_, request)| request));
+
+    /* A second user to share with: a copy of the register request, right after the login. Its 'userId' is the one the share requests use. */
+    let register = items.iter().find(|item| item["request"]["url"]["raw"] == "{{baseUrl}}/api/auth/register").cloned();
+    let login = items.iter().position(|item| item["request"]["url"]["raw"] == "{{baseUrl}}/api/auth/login");
+    if let (Some(mut friend), Some(login)) = (register, login) {
+        friend["name"] = json!("Registers a second user to share with");
+        friend["request"]["body"]["raw"] = json!(serde_json::to_string_pretty(
+            &json!({ "username": "{{friendUsername}}", "password": "{{password}}" })
+        )
+        .unwrap_or_default());
+        items.insert(login + 1, friend);
+    }
+
+    let mut variables: Vec<Value> = DEFAULT_VARIABLES
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": value }))
+        .collect();
+    for name in path_variables {
+        let value = PATH_VARIABLE_DEFAULTS
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map_or("", |(_, value)| value);
+        variables.push(json!({ "key": name, "value": value }));
+    }
+
+    json!({
+        "info": {
+            "name": "Todo",
+            "description": doc.info.description.clone().unwrap_or_default(),
+            "schema": "https://schema.getpostman.com/json/collection/v2.1.0/collection.json",
+        },
+        "auth": {
+            "type": "bearer",
+            "bearer": [{ "key": "token", "value": "{{token}}", "type": "string" }],
+        },
+        "variable": variables,
+        "item": items,
+    })
+}
+
+/* One request in the collection, or None for a route Postman can't call. */
+fn request_item(
+    method: &str,
+    path: &str,
+    operation: &Operation,
+    schemas: &Map<String, Value>,
+    path_variables: &mut Vec<String>,
+) -> Option<Value> {
+    let operation = serde_json::to_value(operation).ok()?;
+    let responses = operation["responses"].as_object().cloned().unwrap_or_default();
+    if responses.contains_key("101")
+        || responses.values().any(|response| response["content"].get("text/event-stream").is_some())
+    {
+        return None;
+    }
+    let public = operation["security"].as_array().is_some_and(|security| security.is_empty());
+
+    /* The URL, with each "{param}" swapped for a variable. */
+    let segments: Vec<String> = path
+        .trim_start_matches('/')
+        .split('/')
+        .map(|segment| match segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')) {
+            Some(param) => {
+                let variable = path_variable(path, param);
+                if !path_variables.contains(&variable) {
+                    path_variables.push(variable.clone());
+                }
+                format!("{{{{{}}}}}", variable)
+            }
+            None => segment.to_string(),
+        })
+        .collect();
+    let query: Vec<Value> = operation["parameters"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|param| param["in"] == "query")
+        .map(|param| {
+            let name = param["name"].as_str().unwrap_or_default();
+            let value = match example(&param["schema"], name, schemas, 0) {
+                Value::String(value) => value,
+                value => value.to_string(),
+            };
+            json!({ "key": name, "value": value, "disabled": true })
+        })
+        .collect();
+        // Query parameters are all optional, so they're listed but switched off.
+    let mut request = json!({
+        "method": method,
+        "header": [],
+        "url": {
+            "raw": format!("{{{{baseUrl}}}}/{}", segments.join("/")),
+            "host": ["{{baseUrl}}"],
+            "path": segments,
+            "query": query,
+        },
+    });
+    if public {
+        request["auth"] = json!({ "type": "noauth" });
+    }
+
+    /* The example body. One from EXAMPLE_BODIES, or written in the handler's attribute, wins over one built from the schema. */
+    let content = &operation["requestBody"]["content"]["application/json"];
+    if !content.is_null() {
+        let written = EXAMPLE_BODIES
+            .iter()
+            .find(|(example_method, example_path, _)| (*example_method, *example_path) == (method, path))
+            .and_then(|(_, _, body)| serde_json::from_str(body).ok())
+            .or_else(|| content.get("example").cloned());
+        let body = match written {
+            Some(example) => example,
+            None => example(&content["schema"], "", schemas, 0),
+        };
+        request["body"] = json!({
+            "mode": "raw",
+            "raw": serde_json::to_string_pretty(&body).unwrap_or_default(),
+            "options": { "raw": { "language": "json" } },
+        });
+    }
+
+    let mut item = json!({
+        "name": operation["summary"].as_str().unwrap_or(path),
+        "request": request,
+        "response": [],
+    });
+    let script = test_script(method, path, &responses, public, schemas);
+    if !script.is_empty() {
+        item["event"] = json!([{ "listen": "test", "script": { "type": "text/javascript", "exec": script } }]);
+    }
+    Some(item)
+}
+
+/* The variable a path parameter is filled from. ':id' is named after the resource it identifies; the others keep their own name in camelCase ("blocker_id" is 'blockerId'). */
+fn path_variable(path: &str, param: &str) -> String {
+    if param == "id" {
+        return match path.split('/').nth(2) {
+            Some("keys") => "apiKeyId".to_string(),
+            Some("webhooks") => "webhookId".to_string(),
+            _ => todo_variable(path).to_string(),
+        };
+    }
+    let mut parts = param.split('_');
+    let first = parts.next().unwrap_or_default().to_string();
+    parts.fold(first, |mut name, part| {
+        let mut chars = part.chars();
+        if let Some(c) = chars.next() {
+            name.extend(c.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+        name
+    })
+}
+
+/* The variable holding the todo a path works on (see TODO_VARIABLES). */
+fn todo_variable(path: &str) -> &'static str {
+    TODO_VARIABLES
+        .iter()
+        .find(|(prefix, _)| path.starts_with(prefix))
+        .map_or("todoId", |(_, variable)| variable)
+}
+
+/* Follows a "$ref" to the schema it points at. */
+fn resolve<'a>(schema: &'a Value, schemas: &'a Map<String, Value>) -> &'a Value {
+    match schema["$ref"].as_str().and_then(|reference| reference.rsplit('/').next()) {
+        Some(name) => schemas.get(name).unwrap_or(schema),
+        None => schema,
+    }
+}
+
+/* An example value for a schema. 'name' is the field it is for, which picks better values for fields like 'username' or 'url'. */
+fn example(schema: &Value, name: &str, schemas: &Map<String, Value>, depth: usize) -> Value {
+    if depth > 8 {
+        return Value::Null;
+            // Schemas that contain themselves stop here.
+    }
+    let schema = resolve(schema, schemas);
+    if let Some(example) = schema.get("example") {
+        return example.clone();
+    }
+    if let Some(first) = schema["enum"].as_array().and_then(|values| values.first()) {
+        return first.clone();
+    }
+    if let Some(parts) = schema["allOf"].as_array() {
+        let mut merged = Map::new();
+        for part in parts {
+            if let Value::Object(fields) = example(part, name, schemas, depth + 1) {
+                merged.extend(fields);
+            }
+        }
+        return Value::Object(merged);
+    }
+    if let Some(first) = schema["oneOf"].as_array().or(schema["anyOf"].as_array()).and_then(|options| {
+        options.iter().find(|option| resolve(option, schemas)["type"] != "null")
+    }) {
+        return example(first, name, schemas, depth + 1);
+    }
+
+    let kind = match &schema["type"] {
+        Value::Array(kinds) => kinds.iter().find(|kind| *kind != "null").and_then(Value::as_str),
+        kind => kind.as_str(),
+    };
+        // An optional field's type is ["string", "null"]; the example uses the first type that isn't null.
+    match kind {
+        Some("object") => {
+            let properties = schema["properties"].as_object().cloned().unwrap_or_default();
+            let required: Vec<&str> = schema["required"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .collect();
+            let fields = properties.iter().filter(|(field, _)| required.contains(&field.as_str()));
+            let fields: Vec<_> = if required.is_empty() {
+                properties.iter().take(1).collect()
+            } else {
+                fields.collect()
+            };
+            Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(field, property)| (field.clone(), example(property, field, schemas, depth + 1)))
+                    .collect(),
+            )
+        }
+        Some("array") => json!([example(&schema["items"], name, schemas, depth + 1)]),
+        Some("integer") | Some("number") => json!(1),
+        Some("boolean") => json!(false),
+        Some("string") => json!(match (schema["format"].as_str(), name) {
+            (Some("date-time"), _) => "2030-01-01T09:00:00Z",
+            (Some("uuid"), "blockedBy" | "before" | "after") => "{{blockerId}}",
+                // A todo can't depend on, or be moved next to, itself.
+            (Some("uuid"), _) => "{{todoId}}",
+            (_, "username") => "{{username}}",
+            (_, "password") => "{{password}}",
+            (_, "url") => "https://example.com/webhook",
+            _ => "{{$randomLoremWords}}",
+                // Random words, so titles don't clash when the collection is run again.
+        }),
+        _ => json!({}),
+    }
+}
+
+/* The test script for a request: it checks the success status, and stores IDs the requests after it need. Anything a route creates comes back as "data.<thing>.id", and is stored as '<thing>Id' ('todoId', 'userId', 'apiKeyId', 'webhookId'); a route that adds to one of a todo's lists (like ".../reminders") stores the new last entry instead ('reminderId'). A login's "token" is stored as 'token', and deleting a todo stores its ID as 'trashedId'. */
+fn test_script(
+    method: &str,
+    path: &str,
+    responses: &Map<String, Value>,
+    public: bool,
+    schemas: &Map<String, Value>,
+) -> Vec<String> {
+    let Some((status, response)) = responses.iter().find(|(status, _)| status.starts_with('2')) else {
+        return Vec::new();
+    };
+    let mut script = vec![
+        format!("pm.test(\"Status code is {}\", function () {{", status),
+        format!("    pm.response.to.have.status({});", status),
+        "});".to_string(),
+    ];
+
+    let schema = resolve(&response["content"]["application/json"]["schema"], schemas);
+    let mut captures = Vec::new();
+        // (variable, JavaScript expression for its new value)
+    if status == "201" {
+        let data = resolve(&schema["properties"]["data"], schemas);
+        let list = path.rsplit('/').next().unwrap_or_default();
+        for (field, property) in data["properties"].as_object().into_iter().flatten() {
+            let thing = resolve(property, schemas);
+            let entries = resolve(&thing["properties"][list]["items"], schemas);
+            if thing["properties"][list]["type"] == "array" && entries["properties"].get("id").is_some() {
+                captures.push((
+                    format!("{}Id", list.trim_end_matches('s')),
+                    format!("body.data.{}.{}.at(-1).id", field, list),
+                ));
+            } else if thing["properties"].get("id").is_some() {
+                let variable = match field.as_str() {
+                    "todo" => todo_variable(path).to_string(),
+                    _ => format!("{}Id", field),
+                };
+                captures.push((variable, format!("body.data.{}.id", field)));
+            }
+        }
+    }
+    if public && schema["properties"].get("token").is_some() {
+        captures.push(("token".to_string(), "body.token".to_string()));
+    }
+    if method == "DELETE" && path.ends_with("todos/{id}") {
+        captures.push((
+            "trashedId".to_string(),
+            format!("pm.collectionVariables.get(\"{}\")", todo_variable(path)),
+        ));
+    }
+    if !captures.is_empty() {
+        script.push(format!("if (pm.response.code === {}) {{", status));
+        if captures.iter().any(|(_, expression)| expression.starts_with("body.")) {
+            script.push("    const body = pm.response.json();".to_string());
+        }
+        for (variable, expression) in captures {
+            script.push(format!("    pm.collectionVariables.set(\"{}\", {});", variable, expression));
+        }
+        script.push("}".to_string());
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use axum::http::Method;
+    use serde_json::Value;
+    use utoipa::OpenApi;
+
+    use super::collection;
+    use crate::{
+        event_store::EventStore,
+        model::AppState,
+        openapi::ApiDoc,
+        tenant::TenantConfig,
+        testing::{app_with, send},
+    };
+
+    /* Fills in "{{variable}}"s the way Postman would. Random words get a fresh value each time. */
+    fn fill(text: &str, variables: &HashMap<String, String>) -> String {
+        let mut text = text.to_string();
+        while let Some(start) = text.find("{{$random") {
+            let end = start + text[start..].find("}}").unwrap() + 2;
+            text.replace_range(start..end, &uuid::Uuid::new_v4().to_string());
+        }
+        for (name, value) in variables {
+            text = text.replace(&format!("{{{{{}}}}}", name), value);
+        }
+        text
+    }
+
+    /* Follows the part of an expression like "body.data.todo.reminders.at(-1).id" after "body.". */
+    fn lookup(body: &Value, path: &str) -> Value {
+        path.split('.').fold(body.clone(), |value, key| match key {
+            "at(-1)" => value.as_array().and_then(|items| items.last()).cloned().unwrap_or_default(),
+            key => value[key].clone(),
+        })
+    }
+
+    #[test]
+    fn the_saved_collection_is_up_to_date() {
+        let saved = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/Todo.postman_collection.json")).unwrap();
+        let generated = serde_json::to_string_pretty(&collection(&ApiDoc::openapi())).unwrap();
+        assert!(saved.trim_end() == generated, "run \"make postman-collection\" to refresh Todo.postman_collection.json");
+    }
+
+    /* Runs the whole collection in order, like Postman's collection runner: every request should get the status its test expects. The event log is on, and the collection's user is an admin, so the rebuild request can run. */
+    #[tokio::test]
+    async fn every_request_passes_in_order() {
+        let mut state = AppState::for_tests();
+        state.event_store = Arc::new(EventStore::recording());
+        state.tenants = Arc::new(TenantConfig::with_admin("default", "postman"));
+        let (router, _) = app_with(state);
+        let collection = collection(&ApiDoc::openapi());
+        let mut variables: HashMap<String, String> = collection["variable"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variable| (variable["key"].as_str().unwrap().to_string(), variable["value"].as_str().unwrap().to_string()))
+            .collect();
+
+        for item in collection["item"].as_array().unwrap() {
+            let request = &item["request"];
+            let script: Vec<&str> = item["event"][0]["script"]["exec"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .collect();
+            let Some(expected) = script.iter().find_map(|line| {
+                line.trim().strip_prefix("pm.response.to.have.status(")?.strip_suffix(");")?.parse::<u16>().ok()
+            }) else {
+                continue;
+            };
+
+            let method = Method::from_bytes(request["method"].as_str().unwrap().as_bytes()).unwrap();
+            let uri = fill(&request["url"]["raw"].as_str().unwrap().replace("{{baseUrl}}", ""), &variables);
+            let body = match request["body"]["raw"].as_str() {
+                Some(raw) => serde_json::from_str(&fill(raw, &variables)).unwrap(),
+                None => Value::Null,
+            };
+            let auth = format!("Bearer {}", variables["token"]);
+            let headers: &[(&str, &str)] = match request["auth"]["type"].as_str() {
+                Some("noauth") => &[],
+                _ => &[("authorization", auth.as_str())],
+            };
+
+            let (status, response) = send(&router, method.clone(), &uri, headers, body).await;
+            assert_eq!(status.as_u16(), expected, "{} {}: {}", method, uri, response);
+
+            /* The captures, e.g. 'pm.collectionVariables.set("todoId", body.data.todo.id);'. */
+            for line in &script {
+                let Some(capture) = line.trim().strip_prefix("pm.collectionVariables.set(\"").and_then(|line| line.strip_suffix(");")) else {
+                    continue;
+                };
+                let (name, expression) = capture.split_once("\", ").unwrap();
+                let value = match expression.strip_prefix("body.") {
+                    Some(path) => lookup(&response, path).as_str().unwrap().to_string(),
+                    None => {
+                        let from = expression.trim_start_matches("pm.collectionVariables.get(\"").trim_end_matches("\")");
+                        variables[from].clone()
+                    }
+                };
+                variables.insert(name.to_string(), value);
+            }
+        }
+    }
+}
@@ -0,0 +1,355 @@
+/* This file stops one client from hogging the server. Every client gets a "token bucket" per route: each request takes a token, tokens drip back in at a steady rate, and a client with an empty bucket gets a 429. */
+
+use std::{collections::HashMap, net::SocketAddr, time::Instant};
+
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::{header::AUTHORIZATION, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    api_key::{self, API_KEY_HEADER},
+    jwt::Claims,
+    model::AppState,
+};
+
+/* A limit: up to 'capacity' requests at once, refilled completely every 'period_secs'. */
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    capacity: u32,
+    period_secs: u64,
+}
+
+impl Limit {
+    /* Reads "<requests>/<seconds>". */
+    fn parse(text: &str) -> Option<Self> {
+        let (capacity, period) = text.trim().split_once('/')?;
+        let limit = Limit {
+            capacity: capacity.trim().parse().ok()?,
+            period_secs: period.trim().parse().ok()?,
+        };
+        (limit.capacity > 0 && limit.period_secs > 0).then_some(limit)
+    }
+
+    /* Tokens added back per second. */
+    fn refill_rate(&self) -> f64 {
+        self.capacity as f64 / self.period_secs as f64
+    }
+}
+
+/* A limit for one route. 'method' None means any method. */
+#[derive(Debug, Clone)]
+struct RouteRule {
+    method: Option<Method>,
+    path: String,
+    limit: Limit,
+}
+
+/* One client's bucket for one rule. */
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_seen: Instant,
+}
+
+/* The outcome of taking a token, used for the RateLimit-* headers. */
+struct Decision {
+    allowed: bool,
+    limit: Limit,
+    remaining: u32,
+    reset_secs: u64,
+        // Seconds until the bucket is full again.
+    retry_after_secs: u64,
+        // Seconds until the next token, when the request was refused.
+}
+
+/* The limits and every client's buckets. Built once in main.rs. */
+pub struct RateLimiter {
+    default_limit: Option<Limit>,
+    routes: Vec<RouteRule>,
+    max_clients: usize,
+    buckets: Mutex<HashMap<(String, usize), Bucket>>,
+        // Keyed by (client, rule). Rule index 'routes.len()' is the default limit.
+}
+
+impl RateLimiter {
+    /* Reads RATE_LIMIT (the rule for every other route, default "120/60"; "0" turns limiting off), RATE_LIMIT_ROUTES (per-route rules like "POST /api/todos=30/60") and RATE_LIMIT_MAX_CLIENTS (buckets kept, default 10000). Rules that can't be read are skipped with a warning. */
+    pub fn from_env() -> Self {
+        let default_limit = match std::env::var("RATE_LIMIT") {
+            Ok(text) if text.trim() == "0" => None,
+            Ok(text) => Limit::parse(&text).or_else(|| {
+                eprintln!("Ignoring bad RATE_LIMIT '{}', using 120/60", text);
+                Limit::parse("120/60")
+            }),
+            Err(_) => Limit::parse("120/60"),
+        };
+
+        let routes = std::env::var("RATE_LIMIT_ROUTES")
+            .unwrap_or_else(|_| "POST /api/todos=30/60".to_string())
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .filter_map(|entry| {
+                let rule = parse_route_rule(entry);
+                if rule.is_none() {
+                    eprintln!("Ignoring bad RATE_LIMIT_ROUTES entry: '{}'", entry);
+                }
+                rule
+            })
+            .collect();
+
+        RateLimiter {
+            default_limit,
+            routes,
+            max_clients: std::env::var("RATE_LIMIT_MAX_CLIENTS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(10_000),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /* No limits at all, for tests that send many requests. */
+    #[cfg(test)]
+    pub fn unlimited() -> Self {
+        RateLimiter {
+            default_limit: None,
+            routes: Vec::new(),
+            max_clients: 0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /* Only the given "METHOD /path=<requests>/<seconds>" rules, for tests. */
+    #[cfg(test)]
+    pub fn with_routes(rules: &[&str]) -> Self {
+        RateLimiter {
+            default_limit: None,
+            routes: rules.iter().filter_map(|rule| parse_route_rule(rule)).collect(),
+            max_clients: 100,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /* Picks the rule for a request: the first matching route rule, otherwise the default limit. Returns the rule's index and limit. */
+    fn rule_for(&self, method: &Method, path: &str) -> Option<(usize, Limit)> {
+        self.routes
+            .iter()
+            .position(|rule| rule.path == path && rule.method.as_ref().is_none_or(|m| m == method))
+            .map(|index| (index, self.routes[index].limit))
+            .or_else(|| self.default_limit.map(|limit| (self.routes.len(), limit)))
+    }
+
+    /* Refills the client's bucket for the time that has passed and takes a token if there is one. */
+    async fn take(&self, client: String, rule: usize, limit: Limit) -> Decision {
+        let now = Instant::now();
+        let rate = limit.refill_rate();
+        let capacity = limit.capacity as f64;
+        let mut buckets = self.buckets.lock().await;
+
+        let key = (client, rule);
+        if !buckets.contains_key(&key) && buckets.len() >= self.max_clients {
+            self.evict(&mut buckets, now);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            last_seen: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_seen).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.last_seen = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        Decision {
+            allowed,
+            limit,
+            remaining: bucket.tokens.floor() as u32,
+            reset_secs: ((capacity - bucket.tokens) / rate).ceil() as u64,
+            retry_after_secs: ((1.0 - bucket.tokens).max(0.0) / rate).ceil().max(1.0) as u64,
+        }
+    }
+
+    /* Makes room for a new client. A bucket that would have refilled by now belongs to a client that has gone quiet, so dropping it changes nothing; those go first. If every client is still busy, the one idle the longest is dropped. */
+    fn evict(&self, buckets: &mut HashMap<(String, usize), Bucket>, now: Instant) {
+        buckets.retain(|(_, rule), bucket| {
+            let limit = self.routes.get(*rule).map(|rule| rule.limit).or(self.default_limit);
+            limit.is_some_and(|limit| {
+                bucket.tokens + now.duration_since(bucket.last_seen).as_secs_f64() * limit.refill_rate()
+                    < limit.capacity as f64
+            })
+        });
+
+        while buckets.len() >= self.max_clients {
+            let Some(oldest) = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.last_seen)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            buckets.remove(&oldest);
+        }
+    }
+}
+
+/* Reads one "METHOD /path=<requests>/<seconds>" rule. */
+fn parse_route_rule(entry: &str) -> Option<RouteRule> {
+    let (route, limit) = entry.trim().split_once('=')?;
+    let (method, path) = route.trim().split_once(' ')?;
+    let method = match method.trim() {
+        "*" => None,
+        method => Some(Method::from_bytes(method.to_uppercase().as_bytes()).ok()?),
+    };
+    Some(RouteRule {
+        method,
+        path: path.trim().to_string(),
+        limit: Limit::parse(limit)?,
+    })
+}
+
+/* What the request says about who is calling, read before anything has to wait (the request itself can't be held on to across an 'await'). */
+struct Caller {
+    api_key: Option<String>,
+    jwt_user: Option<String>,
+    token: Option<String>,
+    ip: String,
+}
+
+fn caller(request: &Request) -> Caller {
+    let headers: &HeaderMap = request.headers();
+    let api_key = headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(api_key::hash_key);
+    let jwt_user = request.extensions().get::<Claims>().map(|claims| claims.sub.clone());
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| format!("ip:{}", addr.ip()))
+        .unwrap_or_else(|| "ip:unknown".to_string());
+
+    Caller {
+        api_key,
+        jwt_user,
+        token,
+        ip,
+    }
+}
+
+/* Works out who is calling. API keys are hashed so the raw key isn't kept in memory; a session token is only used once it has been looked up to a user, so made-up tokens fall back to the IP. */
+async fn client_key(state: &AppState, caller: Caller) -> String {
+    let Caller {
+        api_key,
+        jwt_user,
+        token,
+        ip,
+    } = caller;
+
+    if let Some(hash) = api_key {
+        return format!("key:{}", hash);
+    }
+    if let Some(user_id) = jwt_user {
+        return format!("user:{}", user_id);
+    }
+    if let Some(token) = token {
+        if let Some(user_id) = state.sessions.user(&token).await {
+            return format!("user:{}", user_id);
+        }
+    }
+    ip
+}
+
+/* Adds a header, skipping values that can't be headers (which numbers always can). */
+fn set_header(response: &mut Response, name: &'static str, value: String) {
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        response.headers_mut().insert(HeaderName::from_static(name), value);
+    }
+}
+
+/* Runs before every route. Takes a token from the caller's bucket and either lets the request through or answers 429. Both carry RateLimit-* headers so clients can slow down before they hit the limit. */
+pub async fn rate_limit_middleware(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let Some((rule, limit)) = state.rate_limiter.rule_for(request.method(), &path) else {
+        return next.run(request).await;
+    };
+
+    let client = client_key(&state, caller(&request)).await;
+    let decision = state.rate_limiter.take(client, rule, limit).await;
+
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Too many requests, try again in {} seconds", decision.retry_after_secs),
+        });
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, Json(error_response)).into_response();
+        set_header(&mut response, "retry-after", decision.retry_after_secs.to_string());
+        response
+    };
+
+    set_header(&mut response, "ratelimit-limit", decision.limit.capacity.to_string());
+    set_header(&mut response, "ratelimit-remaining", decision.remaining.to_string());
+    set_header(&mut response, "ratelimit-reset", decision.reset_secs.to_string());
+    set_header(
+        &mut response,
+        "ratelimit-policy",
+        format!("{};w={}", decision.limit.capacity, decision.limit.period_secs),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use super::RateLimiter;
+    use crate::{
+        model::AppState,
+        testing::{app_with, login, send},
+    };
+
+    #[tokio::test]
+    async fn a_client_over_its_limit_gets_a_429() {
+        let mut state = AppState::for_tests();
+        state.rate_limiter = Arc::new(RateLimiter::with_routes(&["POST /api/todos=2/3600"]));
+        let (router, _) = app_with(state);
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+
+        for title in ["a", "b"] {
+            let (status, _) = send(&router, Method::POST, "/api/todos", &auth, json!({ "title": title, "content": "x" })).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        let (status, body) = send(&router, Method::POST, "/api/todos", &auth, json!({ "title": "c", "content": "x" })).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["status"], "fail");
+
+        /* Other routes, and other clients, have their own buckets. */
+        let (status, _) = send(&router, Method::GET, "/api/todos", &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let bob = login(&router, "default", "bob").await;
+        let (status, _) = send(&router, Method::POST, "/api/todos", &[("authorization", bob.as_str())], json!({ "title": "d", "content": "x" })).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+}
@@ -0,0 +1,370 @@
+/* This file understands a small part of the RFC 5545 recurrence rule format (the "RRULE" lines calendars use), e.g. "FREQ=WEEKLY;BYDAY=MO,TH;COUNT=10". A recurring todo stores its rule as a string, and when an occurrence is completed or skipped we use this file to work out when the next one is due. Supported parts: FREQ (DAILY, WEEKLY, MONTHLY, YEARLY), INTERVAL, COUNT, UNTIL and BYDAY. */
+
+use std::str::FromStr;
+
+use chrono::{prelude::*, Days, Duration, Months};
+use uuid::Uuid;
+
+use crate::model::{Reminder, Todo};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/* A parsed recurrence rule. Build one with "rule".parse::<RecurrenceRule>(). */
+#[derive(Debug, Clone)]
+pub struct RecurrenceRule {
+    pub freq: Frequency,
+    pub interval: u32,
+        // Repeat every N days/weeks/months/years. Defaults to 1.
+    pub count: Option<u32>,
+        // Total number of occurrences in the series, including the first one.
+    pub until: Option<DateTime<Utc>>,
+        // No occurrence may be due after this moment.
+    pub by_day: Vec<Weekday>,
+        // Days of the week the todo falls on, e.g. BYDAY=MO,WE,FR.
+}
+
+impl FromStr for RecurrenceRule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+            // Calendar exports put "RRULE:" in front of the rule; accept it either way.
+
+        let mut freq = None;
+        let mut interval = 1;
+        let mut count = None;
+        let mut until = None;
+        let mut by_day = Vec::new();
+
+        /* Each part looks like KEY=VALUE and parts are separated by ';'. */
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid recurrence rule part: '{}'", part))?;
+
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        other => return Err(format!("Unsupported recurrence frequency: '{}'", other)),
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(|| format!("Invalid recurrence interval: '{}'", value))?
+                }
+                "COUNT" => {
+                    count = Some(
+                        value
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|count| *count > 0)
+                            .ok_or_else(|| format!("Invalid recurrence count: '{}'", value))?,
+                    )
+                }
+                "UNTIL" => until = Some(parse_until(value)?),
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        by_day.push(parse_weekday(day)?);
+                    }
+                }
+                other => return Err(format!("Unsupported recurrence rule part: '{}'", other)),
+            }
+        }
+
+        if count.is_some() && until.is_some() {
+            return Err("A recurrence rule can't have both COUNT and UNTIL".to_string());
+        }
+
+        Ok(RecurrenceRule {
+            freq: freq.ok_or_else(|| "A recurrence rule needs a FREQ".to_string())?,
+            interval,
+            count,
+            until,
+            by_day,
+        })
+    }
+}
+
+impl RecurrenceRule {
+    /* Works out when the occurrence after 'current' is due. 'start' is the due date the series counts from and 'occurrence' the 1-based number of the current occurrence, so COUNT can be enforced. Returns None once the series is over. */
+    pub fn next_after(&self, start: DateTime<Utc>, current: DateTime<Utc>, occurrence: u32) -> Option<DateTime<Utc>> {
+        if let Some(count) = self.count {
+            if occurrence >= count {
+                return None;
+            }
+        }
+
+        let next = match self.freq {
+            Frequency::Daily if !self.by_day.is_empty() => {
+                // only every 'interval'-th day counts, and of those only the BYDAY weekdays; the weekdays repeat after 7 steps
+                (1..=7)
+                    .filter_map(|step| current.checked_add_signed(Duration::try_days(self.interval as i64 * step)?))
+                    .find(|candidate| self.by_day.contains(&candidate.weekday()))
+            }
+            Frequency::Daily => current.checked_add_signed(Duration::try_days(self.interval as i64)?),
+            Frequency::Weekly if !self.by_day.is_empty() => self.next_matching_day(current),
+            Frequency::Weekly => current.checked_add_signed(Duration::try_weeks(self.interval as i64)?),
+            Frequency::Monthly if !self.by_day.is_empty() => self.next_matching_day(current),
+            Frequency::Monthly => on_start_day(current.checked_add_months(Months::new(self.interval))?, start),
+            Frequency::Yearly if !self.by_day.is_empty() => self.next_matching_day(current),
+            Frequency::Yearly => on_start_day(current.checked_add_months(Months::new(self.interval.checked_mul(12)?))?, start),
+        }?;
+
+        /* Anything past UNTIL is outside the series. */
+        match self.until {
+            Some(until) if next > until => None,
+            _ => Some(next),
+        }
+    }
+
+    /* Finds the next BYDAY weekday for a WEEKLY, MONTHLY or YEARLY rule. Later days in the current week/month/year count; otherwise we look in the week/month/year 'interval' periods on. E.g. "FREQ=MONTHLY;INTERVAL=2;BYDAY=MO" is every Monday of every other month. */
+    fn next_matching_day(&self, current: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let today = current.date_naive();
+        let this_period = self.period_start(today);
+        let next_period = match self.freq {
+            Frequency::Weekly => this_period.checked_add_days(Days::new(7 * self.interval as u64)),
+            Frequency::Monthly => this_period.checked_add_months(Months::new(self.interval)),
+            Frequency::Yearly => this_period.checked_add_months(Months::new(self.interval.checked_mul(12)?)),
+            Frequency::Daily => None,
+        }?;
+
+        let later_this_period = today
+            .iter_days()
+            .skip(1)
+            .take_while(|day| self.period_start(*day) == this_period);
+        let in_next_period = next_period
+            .iter_days()
+            .take_while(|day| self.period_start(*day) == next_period);
+        let day = later_this_period
+            .chain(in_next_period)
+            .find(|day| self.by_day.contains(&day.weekday()))?;
+        Some(Utc.from_utc_datetime(&day.and_time(current.time())))
+            // same time of day as the current occurrence
+    }
+
+    /* The first day of the week, month or year that 'date' falls in, depending on FREQ. */
+    fn period_start(&self, date: NaiveDate) -> NaiveDate {
+        match self.freq {
+            Frequency::Daily => date,
+            Frequency::Weekly => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+                // weeks start on Monday (RFC 5545's default)
+            Frequency::Monthly => date.with_day(1).unwrap_or(date),
+            Frequency::Yearly => date.with_ordinal(1).unwrap_or(date),
+        }
+    }
+}
+
+/* Checks the recurrence settings a client sent. A rule has to parse, and a recurring todo needs a due date to count from. */
+pub fn validate(recurrence: Option<&str>, due_date: Option<DateTime<Utc>>) -> Result<(), String> {
+    if let Some(rule) = recurrence {
+        rule.parse::<RecurrenceRule>()?;
+        if due_date.is_none() {
+            return Err("A recurring todo needs a dueDate".to_string());
+        }
+    }
+    Ok(())
+}
+
+/* Builds the todo for the occurrence after this one, with its due date moved forward. Returns None if the todo doesn't recur or its series is over (COUNT/UNTIL reached). The current todo is left untouched so completed occurrences stay in the list as history. */
+pub fn next_occurrence(todo: &Todo) -> Option<Todo> {
+    let rule = todo.recurrence.as_deref()?.parse::<RecurrenceRule>().ok()?;
+    let occurrence = todo.occurrence.unwrap_or(1);
+    let start = todo.seriesStart.or(todo.dueDate)?;
+    let next_due = rule.next_after(start, todo.dueDate?, occurrence)?;
+    let datetime = Utc::now();
+
+    Some(Todo {
+        id: Some(Uuid::new_v4().to_string()),
+        completed: Some(false),
+        createdAt: Some(datetime),
+        updatedAt: Some(datetime),
+        dueDate: Some(next_due),
+        seriesId: todo.seriesId.clone().or_else(|| todo.id.clone()),
+        occurrence: Some(occurrence + 1),
+        seriesStart: Some(start),
+        reminders: todo
+            .reminders
+            .iter()
+            .filter(|reminder| reminder.offsetMinutes.is_some() && reminder.remindAt.is_none())
+            .map(|reminder| Reminder {
+                sentAt: None,
+                ..reminder.clone()
+            })
+            .collect(),
+            // Reminders relative to the due date carry over to the next occurrence; fixed-time ones belong to this occurrence only.
+        ..todo.clone()
+    })
+}
+
+/* Moves a monthly or yearly occurrence onto the series' day of the month. Adding a month to Jan 31 gives Feb 28, and adding another would give Mar 28; counting from the start keeps the series on the 31st whenever the month has one (and on the last day when it doesn't). */
+fn on_start_day(moment: DateTime<Utc>, start: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    (1..=start.day()).rev().find_map(|day| moment.with_day(day))
+}
+
+fn parse_weekday(day: &str) -> Result<Weekday, String> {
+    match day.trim().to_ascii_uppercase().as_str() {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        other => Err(format!("Invalid recurrence weekday: '{}'", other)),
+    }
+}
+
+/* UNTIL is either a date-time in UTC ("20251231T235959Z") or a plain date ("20251231"), which means the end of that day. */
+fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ") {
+        return Ok(Utc.from_utc_datetime(&datetime));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms_opt(23, 59, 59).unwrap()));
+    }
+    Err(format!("Invalid recurrence UNTIL: '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use chrono::prelude::*;
+    use serde_json::json;
+
+    use super::{next_occurrence, RecurrenceRule};
+    use crate::{
+        model::Todo,
+        testing::{app, create_todo, login, send},
+    };
+
+    fn at(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, 9, 0, 0).unwrap()
+    }
+
+    /* The due dates of the first 'n' occurrences of a series. */
+    fn series(rule: &str, start: DateTime<Utc>, n: usize) -> Vec<DateTime<Utc>> {
+        let mut todo = Todo {
+            id: Some("first".to_string()),
+            recurrence: Some(rule.to_string()),
+            dueDate: Some(start),
+            seriesStart: Some(start),
+            occurrence: Some(1),
+            ..Default::default()
+        };
+        let mut dates = vec![start];
+        while dates.len() < n {
+            let Some(next) = next_occurrence(&todo) else {
+                break;
+            };
+            dates.push(next.dueDate.unwrap());
+            todo = next;
+        }
+        dates
+    }
+
+    #[test]
+    fn monthly_keeps_the_day_of_the_month() {
+        assert_eq!(
+            series("FREQ=MONTHLY", at(2025, 1, 31), 4),
+            vec![at(2025, 1, 31), at(2025, 2, 28), at(2025, 3, 31), at(2025, 4, 30)]
+        );
+    }
+
+    #[test]
+    fn yearly_keeps_leap_days() {
+        assert_eq!(
+            series("FREQ=YEARLY", at(2024, 2, 29), 5),
+            vec![at(2024, 2, 29), at(2025, 2, 28), at(2026, 2, 28), at(2027, 2, 28), at(2028, 2, 29)]
+        );
+    }
+
+    #[test]
+    fn count_and_until_end_the_series() {
+        assert_eq!(series("FREQ=DAILY;COUNT=3", at(2025, 1, 1), 10).len(), 3);
+        assert_eq!(series("FREQ=WEEKLY;UNTIL=20250115", at(2025, 1, 1), 10).len(), 3);
+        let rule: RecurrenceRule = "FREQ=WEEKLY;BYDAY=MO,TH".parse().unwrap();
+        assert_eq!(rule.next_after(at(2025, 1, 6), at(2025, 1, 6), 1), Some(at(2025, 1, 9)));
+    }
+
+    #[test]
+    fn daily_byday_keeps_the_interval() {
+        // every other day, but only on Monday, Wednesday or Friday
+        assert_eq!(
+            series("FREQ=DAILY;INTERVAL=2;BYDAY=MO,WE,FR", at(2025, 1, 6), 4),
+            vec![at(2025, 1, 6), at(2025, 1, 8), at(2025, 1, 10), at(2025, 1, 20)]
+        );
+    }
+
+    #[test]
+    fn monthly_byday_is_every_matching_weekday_of_the_month() {
+        // January 27th is the last Monday of January, so the next is the first Monday two months on
+        assert_eq!(
+            series("FREQ=MONTHLY;INTERVAL=2;BYDAY=MO", at(2025, 1, 27), 3),
+            vec![at(2025, 1, 27), at(2025, 3, 3), at(2025, 3, 10)]
+        );
+    }
+
+    #[test]
+    fn yearly_byday_is_every_matching_weekday_of_the_year() {
+        assert_eq!(
+            series("FREQ=YEARLY;INTERVAL=2;BYDAY=SU", at(2025, 12, 28), 3),
+            vec![at(2025, 12, 28), at(2027, 1, 3), at(2027, 1, 10)]
+        );
+        // "the second Monday" style BYDAY values aren't supported and are refused
+        assert!("FREQ=MONTHLY;BYDAY=2MO".parse::<RecurrenceRule>().is_err());
+    }
+
+    #[tokio::test]
+    async fn occurrences_share_their_title() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let todo = create_todo(
+            &router,
+            &alice,
+            json!({ "title": "Rent", "content": "a", "dueDate": "2025-01-31T09:00:00Z", "recurrence": "FREQ=MONTHLY" }),
+        )
+        .await;
+        let uri = format!("/api/todos/{}", todo["id"].as_str().unwrap());
+        let auth = [("authorization", alice.as_str())];
+
+        /* Completing it schedules the next occurrence under the same title, on the last day of February. */
+        for column in ["in_progress", "review", "done"] {
+            let (status, body) = send(&router, Method::PATCH, &uri, &auth, json!({ "status": column })).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+        let (_, body) = send(&router, Method::GET, "/api/todos", &auth, serde_json::Value::Null).await;
+        let next = body["todos"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|todo| todo["occurrence"] == 2)
+            .cloned()
+            .unwrap();
+        assert_eq!(next["title"], "Rent");
+        assert_eq!(next["dueDate"], "2025-02-28T09:00:00Z");
+
+        /* Skipping it lands back on the 31st. */
+        let skip = format!("/api/todos/{}/skip", next["id"].as_str().unwrap());
+        let (status, body) = send(&router, Method::POST, &skip, &auth, serde_json::Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["todo"]["dueDate"], "2025-03-31T09:00:00Z");
+
+        /* Todos outside the series still can't take the title. */
+        let (status, _) = send(&router, Method::POST, "/api/todos", &auth, json!({ "title": "Rent", "content": "b" })).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+}
@@ -2,30 +2,198 @@
 This file defines the shapes of the JSON responses your API will send back. Each struct represents a different kind of response: a generic message, a single todo, or a list of todos. The `Serialize` trait makes it easy to turn these structs into JSON for your users. */
 
 /* Imports the 'Todo' struct from our model.rs file so we can use it here. */
-use crate::model::Todo;
+use crate::{
+    api_key::ApiKey,
+    audit::{AuditAction, AuditEntry},
+    model::Todo,
+    event_store::Rebuild,
+    revision::Revision,
+    sync::{SyncResult, Tombstone},
+    undo::OperationSummary,
+    webhook::{Delivery, Webhook},
+};
+/* Imports date/time types for timestamps in responses. */
+use chrono::prelude::*;
 /* Imports the 'Serialize' trait. Which lets us turn Rust data into JSON. */
 use serde::Serialize;
+/* Imports the derive that describes each response in the OpenAPI document (see openapi.rs). */
+use utoipa::ToSchema;
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct GenericResponse {
     pub status: String,
     pub message: String,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, async_graphql::SimpleObject, ToSchema)]
 pub struct TodoData {
     pub todo: Todo,
+    /* Only filled in when completing or skipping a recurring todo created its next occurrence. */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<Todo>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct SingleTodoResponse {
     pub status: String,
     pub data: TodoData,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
 pub struct TodoListResponse {
     pub status: String,
     pub results: usize,
     pub todos: Vec<Todo>,
-}
\ No newline at end of file
+}
+/* One column of the Kanban board. */
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BoardColumn {
+    pub status: String,
+    pub results: usize,
+    pub todos: Vec<Todo>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BoardResponse {
+    pub status: String,
+    pub columns: Vec<BoardColumn>,
+}
+
+/* A user as sent to clients. The password hash is left out on purpose. */
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FilteredUser {
+    pub id: String,
+    pub username: String,
+    pub tenantId: String,
+    pub createdAt: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct UserData {
+    pub user: FilteredUser,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct UserResponse {
+    pub status: String,
+    pub data: UserData,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct LoginResponse {
+    pub status: String,
+    pub token: String,
+}
+
+/* 'key' is only filled in right after the key is issued; it can't be shown again later. */
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ApiKeyData {
+    pub apiKey: ApiKey,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ApiKeyResponse {
+    pub status: String,
+    pub data: ApiKeyData,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ApiKeyListResponse {
+    pub status: String,
+    pub results: usize,
+    pub apiKeys: Vec<ApiKey>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct AuditListResponse {
+    pub status: String,
+    pub results: usize,
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RevisionListResponse {
+    pub status: String,
+    pub results: usize,
+    pub revisions: Vec<Revision>,
+}
+
+/* Sent back after an undo or redo: which operation it was, and the todos it put back (purged ones are left out). */
+#[derive(Serialize, Debug, ToSchema)]
+pub struct UndoResponse {
+    pub status: String,
+    pub action: AuditAction,
+    pub results: usize,
+    pub todos: Vec<Todo>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct UndoStackResponse {
+    pub status: String,
+    pub undo: Vec<OperationSummary>,
+    pub redo: Vec<OperationSummary>,
+}
+
+/* A webhook subscription. 'secret' is only filled in when the subscription is created. */
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WebhookData {
+    pub webhook: Webhook,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WebhookResponse {
+    pub status: String,
+    pub data: WebhookData,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WebhookListResponse {
+    pub status: String,
+    pub results: usize,
+    pub webhooks: Vec<Webhook>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DeliveryResponse {
+    pub status: String,
+    pub delivery: Delivery,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DeliveryListResponse {
+    pub status: String,
+    pub results: usize,
+    pub deliveries: Vec<Delivery>,
+}
+
+/* A sync pull: what changed since the client's token, and the token to send next time. */
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SyncResponse {
+    pub status: String,
+    pub token: String,
+    pub full: bool,
+    pub results: usize,
+    pub todos: Vec<Todo>,
+    pub deleted: Vec<Tombstone>,
+}
+
+/* A sync push: one result per change, in the order they were sent. */
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SyncPushResponse {
+    pub status: String,
+    pub results: usize,
+    pub changes: Vec<SyncResult>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RebuildResponse {
+    pub status: String,
+    pub data: Rebuild,
+}
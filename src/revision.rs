@@ -0,0 +1,151 @@
+/* This file keeps a revision history for every todo, so an accidental edit can be undone. Each time a todo is created or changed, a copy of it is saved as a new numbered revision (1, 2, 3...) along with who made the change and which fields changed since the revision before. Only the newest TODO_MAX_REVISIONS (default 50) revisions of a todo are kept; revision numbers keep counting up even after old ones are dropped. */
+
+use std::collections::HashMap;
+
+use chrono::prelude::*;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::model::Todo;
+
+/* Fields left out of diffs: 'updatedAt' changes on every edit, and 'blocks'/'blocked' are worked out when a todo is sent back rather than stored, so they would only add noise. */
+const IGNORED_FIELDS: &[&str] = &["updatedAt", "blocks", "blocked"];
+
+/* One field that differs between a revision and the one before it. */
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct FieldChange {
+    pub field: String,
+    pub from: serde_json::Value,
+    pub to: serde_json::Value,
+}
+
+/* A saved copy of a todo. 'changes' lists what changed since the previous revision (for revision 1, every field that was set). */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Revision {
+    pub rev: u32,
+    pub at: DateTime<Utc>,
+    pub authorId: String,
+    pub changes: Vec<FieldChange>,
+    pub todo: Todo,
+}
+
+/* Every todo's revisions, by workspace and todo ID, oldest first. The workspace is part of the key so one workspace's history can never show up in another's, even under the same ID. */
+pub struct RevisionStore {
+    max_revisions: usize,
+    todos: Mutex<HashMap<(String, String), Vec<Revision>>>,
+}
+
+impl RevisionStore {
+    /* Reads TODO_MAX_REVISIONS. */
+    pub fn from_env() -> Self {
+        RevisionStore {
+            max_revisions: std::env::var("TODO_MAX_REVISIONS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|max| *max > 0)
+                .unwrap_or(50),
+            todos: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /* Saves the todo as its next revision. Nothing is saved if no field changed. */
+    pub async fn record(&self, tenant: &str, todo: &Todo, author_id: &str) {
+        let Some(todo_id) = todo.id.clone() else {
+            return;
+        };
+        let mut todos = self.todos.lock().await;
+        let history = todos.entry((tenant.to_string(), todo_id)).or_default();
+
+        let changes = diff(history.last().map(|previous| &previous.todo), todo);
+        if changes.is_empty() && !history.is_empty() {
+            return;
+        }
+
+        history.push(Revision {
+            rev: history.last().map(|previous| previous.rev + 1).unwrap_or(1),
+            at: Utc::now(),
+            authorId: author_id.to_string(),
+            changes,
+            todo: todo.clone(),
+        });
+        if history.len() > self.max_revisions {
+            let extra = history.len() - self.max_revisions;
+            history.drain(..extra);
+        }
+    }
+
+    /* A todo's revisions, oldest first. */
+    pub async fn history(&self, tenant: &str, todo_id: &str) -> Vec<Revision> {
+        self.todos
+            .lock()
+            .await
+            .get(&(tenant.to_string(), todo_id.to_string()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /* Drops a deleted todo's history. */
+    pub async fn forget(&self, tenant: &str, todo_id: &str) {
+        self.todos.lock().await.remove(&(tenant.to_string(), todo_id.to_string()));
+    }
+}
+
+/* Lists the fields that differ between two versions of a todo. Both are turned into JSON objects and compared key by key, so new fields on 'Todo' are picked up without touching this function. */
+pub fn diff(before: Option<&Todo>, after: &Todo) -> Vec<FieldChange> {
+    let as_object = |todo: Option<&Todo>| match todo.map(serde_json::to_value) {
+        Some(Ok(serde_json::Value::Object(fields))) => fields,
+        _ => serde_json::Map::new(),
+    };
+    let before = as_object(before);
+    let after = as_object(Some(after));
+
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    fields
+        .into_iter()
+        .filter(|field| !IGNORED_FIELDS.contains(&field.as_str()))
+        .filter_map(|field| {
+            let from = before.get(field).cloned().unwrap_or(serde_json::Value::Null);
+            let to = after.get(field).cloned().unwrap_or(serde_json::Value::Null);
+            (from != to).then(|| FieldChange {
+                field: field.clone(),
+                from,
+                to,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::testing::{app, create_todo, login, send};
+
+    #[tokio::test]
+    async fn history_stays_in_its_workspace() {
+        let (router, state) = app();
+        let alice = login(&router, "team-a", "alice").await;
+        let mallory = login(&router, "team-b", "mallory").await;
+        let todo = create_todo(&router, &alice, json!({ "title": "Secret", "content": "a" })).await;
+        let id = todo["id"].as_str().unwrap();
+
+        /* Pushing a new todo under another workspace's ID is refused. */
+        let push = json!({ "changes": [{ "id": id, "changedAt": "2030-01-01T00:00:00Z", "title": "Mine now" }] });
+        let (status, body) = send(&router, Method::POST, "/api/sync", &[("authorization", &mallory)], push).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["changes"][0]["outcome"], "rejected");
+
+        let uri = format!("/api/todos/{}/revisions", id);
+        let (status, _) = send(&router, Method::GET, &uri, &[("authorization", &mallory)], Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        /* Even under the same ID, the store keeps each workspace's history apart. */
+        assert_eq!(state.revisions.history("team-a", id).await.len(), 1);
+        assert!(state.revisions.history("team-b", id).await.is_empty());
+    }
+}
@@ -1,42 +1,209 @@
 /* This file is used to organize all routes/urls for us to use in our website. The path's can be used as a reference when writing tests in Postman. */
 
+/* Imports Swagger UI, which shows the OpenAPI document as interactive docs, and the trait that builds the document. */
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
 /* Imports necessary portions of the Axum framework. */
 use axum::{
-    routing::{get, post},
-    Router,
+    middleware,
+    routing::{delete, get, post},
+    Extension, Router,
 };
 
-/* Imports our functions builtin the handler.rs file for use in this file. */
+/* Imports our functions builtin the handler.rs file (and the *_handler.rs files split out of it) for use in this file. */
 use crate::{
     handler::{
-        create_todo_handler, delete_todo_handler, edit_todo_handler, get_todo_handler,
-        health_checker_handler, todos_list_handler,
+        add_dependency_handler, add_reminder_handler, audit_handler, board_handler, create_api_key_handler,
+        create_todo_handler, list_api_keys_handler, revoke_api_key_handler, delete_reminder_handler,
+        delete_todo_handler, edit_todo_handler, get_todo_handler, health_checker_handler,
+        login_user_handler, me_handler, move_todo_handler, restore_revision_handler, revisions_handler,
+        occurrences_handler, register_user_handler, remove_dependency_handler, skip_occurrence_handler, todos_list_handler,
+        todos_order_handler, ws_handler, todo_events_handler, rebuild_projection_handler,
+        graphql_handler, graphql_ws_handler, graphiql_handler, postman_collection_handler,
+    },
+    share_handler::{share_list_handler, share_todo_handler, unshare_list_handler, unshare_todo_handler},
+    sync_handler::{sync_pull_handler, sync_push_handler},
+    trash_handler::{trash_empty_handler, trash_list_handler, trash_purge_handler, trash_restore_handler},
+    undo_handler::{redo_handler, undo_handler, undo_history_handler},
+    v2_handler::{
+        v2_create_todo_handler, v2_delete_todo_handler, v2_edit_todo_handler, v2_get_todo_handler,
+        v2_todos_list_handler,
+    },
+    webhook_handler::{
+        create_webhook_handler, delete_webhook_handler, edit_webhook_handler, get_webhook_handler,
+        list_webhooks_handler, test_webhook_handler, webhook_deliveries_handler,
     },
-    model,
+    graphql::{build_schema, graphiql_from_env},
+    openapi::ApiDoc,
+    audit::request_id_middleware,
+    jwt::jwt_middleware,
+    live::query_token_middleware,
+    model::AppState,
+    rate_limit::rate_limit_middleware,
+    version::{negotiate, VersionConfig},
 };
 
 /* A main router function which is called in the main.rs file to set up our routing. */
-pub fn create_router() -> Router {
-    let db = model::todo_db();
-        // Calls a function in our model.rs file to create/access our todo database/in-memory store.
-        // This 'db' is shared with our handler functions so they can read and write todos in the same place.
+pub fn create_router(state: AppState) -> Router {
+    // 'state' is created in main.rs and passed in, so background tasks like the reminder scheduler can share the same todo list.
+    // It holds our 'db' (shared with our handler functions so they can read and write todos in the same place) and the Kanban workflow.
+
+    /* GraphiQL is only served when switched on (see graphql.rs). */
+    let mut router = Router::new();
+    if graphiql_from_env() {
+        router = router.route("/graphiql", get(graphiql_handler));
+    }
+    let schema = build_schema(state.clone());
+
+    /* The OpenAPI document, generated from the handlers (see openapi.rs), served as JSON along with Swagger UI. */
+    router = router.merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi()));
+        // "/api/openapi.json" is the document itself; "/api/docs" shows it in the browser, with a "Try it out" button for every route.
 
-    /* Creates a new empty router which we can add our API routes into. */
-    Router::new()
+    /* When version 1 of the todo routes was deprecated and when it goes away (see version.rs). */
+    let versions = VersionConfig::from_env();
+
+    /* Adds our API routes to the router. */
+    router
         .route("/api/healthchecker", get(health_checker_handler))
             // Adds a route for healthchecker, then calls/ties our function to it. This is the base "check that server is running" route.
+        .route("/api/auth/register", post(register_user_handler))
+            // Creates a new user account.
+        .route("/api/auth/login", post(login_user_handler))
+            // Logs in and returns a token. Every todo route below needs "Authorization: Bearer <token>" and only shows the caller's own todos.
+        .route("/api/auth/me", get(me_handler))
+            // Shows who the token belongs to (and its JWT claims, if it is a JWT).
+        .route(
+            "/api/keys",
+            get(list_api_keys_handler) // List the caller's API keys
+                .post(create_api_key_handler), // Issue a new API key
+        )
+        .route("/api/keys/:id", delete(revoke_api_key_handler))
+            // Revokes an API key. Todo routes accept "X-API-Key: <key>" as well as a Bearer token.
         .route(
             "/api/todos",
-            get(todos_list_handler) // List all todos
-                .post(create_todo_handler), // Create a new todo
-                    // If the request is a GET, it lists all todos, if it's a POST, it creates a new todo.
+            negotiate(
+                state.clone(),
+                versions,
+                get(todos_list_handler) // List all todos
+                    .post(create_todo_handler), // Create a new todo
+                        // If the request is a GET, it lists all todos, if it's a POST, it creates a new todo.
+                get(v2_todos_list_handler).post(v2_create_todo_handler),
+                    // The same routes in version 2, for "Accept: application/vnd.todo.v2+json".
+            ),
         )
         .route(
             "/api/todos/:id",
-            get(get_todo_handler) // Get a single todo by ID
-                .patch(edit_todo_handler) // Edit a todo by ID
-                .delete(delete_todo_handler), // Delete a todo by ID
+            negotiate(
+                state.clone(),
+                versions,
+                get(get_todo_handler) // Get a single todo by ID
+                    .patch(edit_todo_handler) // Edit a todo by ID
+                    .delete(delete_todo_handler), // Delete a todo by ID
+                get(v2_get_todo_handler)
+                    .patch(v2_edit_todo_handler)
+                    .delete(v2_delete_todo_handler),
+            ),
+        )
+        .route(
+            "/api/v2/todos",
+            get(v2_todos_list_handler).post(v2_create_todo_handler),
+        )
+            // Version 2 of the todo routes: snake_case fields with real types (see v2.rs). The version 1 routes above answer with 'Deprecation' and 'Sunset' headers pointing here.
+        .route(
+            "/api/v2/todos/:id",
+            get(v2_get_todo_handler)
+                .patch(v2_edit_todo_handler)
+                .delete(v2_delete_todo_handler),
+        )
+        .route("/api/todos/order", get(todos_order_handler))
+            // Lists every todo so that each one comes after the todos blocking it. Static paths take priority over '/api/todos/:id'.
+        .route("/api/todos/:id/dependencies", post(add_dependency_handler))
+            // Makes a todo wait on another todo.
+        .route(
+            "/api/todos/:id/dependencies/:blocker_id",
+            delete(remove_dependency_handler),
+        )
+            // Removes one of the todos a todo is waiting on.
+        .route("/api/todos/:id/share", post(share_todo_handler))
+            // Shares a todo with another user as an editor or viewer.
+        .route("/api/todos/:id/share/:user_id", delete(unshare_todo_handler))
+            // Stops sharing a todo with a user.
+        .route("/api/lists/:list/share", post(share_list_handler))
+            // Shares every todo in one of the caller's lists with another user.
+        .route("/api/lists/:list/share/:user_id", delete(unshare_list_handler))
+            // Stops sharing a list with a user.
+        .route("/api/todos/:id/revisions", get(revisions_handler))
+            // Lists a todo's earlier versions and what changed between them.
+        .route(
+            "/api/todos/:id/revisions/:rev/restore",
+            post(restore_revision_handler),
+        )
+            // Rolls a todo back to one of its earlier versions.
+        .route("/api/todos/:id/move", post(move_todo_handler))
+            // Drag-and-drop: moves a todo before/after other todos in its list.
+        .route("/api/todos/:id/skip", post(skip_occurrence_handler))
+            // Moves a recurring todo on to its next occurrence without completing it.
+        .route("/api/todos/:id/occurrences", get(occurrences_handler))
+            // Lists every occurrence of a recurring todo, including completed ones.
+        .route("/api/todos/:id/reminders", post(add_reminder_handler))
+            // Adds a reminder that the background scheduler will send when it is due.
+        .route(
+            "/api/todos/:id/reminders/:reminder_id",
+            delete(delete_reminder_handler),
+        )
+            // Removes a reminder from a todo.
+        .route("/api/board", get(board_handler))
+            // Lists todos grouped by workflow column for the Kanban board.
+        .route("/api/trash", get(trash_list_handler).delete(trash_empty_handler))
+            // Lists the caller's deleted todos, or empties the trash for good.
+        .route("/api/trash/:id/restore", post(trash_restore_handler))
+            // Takes a deleted todo back out of the trash.
+        .route("/api/trash/:id", delete(trash_purge_handler))
+            // Removes one deleted todo for good.
+        .route("/api/undo", get(undo_history_handler).post(undo_handler))
+            // Shows the caller's undo/redo stacks, or undoes their most recent operation.
+        .route("/api/redo", post(redo_handler))
+            // Redoes the operation the caller most recently undid.
+        .route("/api/ws", get(ws_handler))
+            // Opens a WebSocket that pushes todo changes as they happen, instead of polling "/api/todos".
+        .route("/api/todos/events", get(todo_events_handler))
+            // The same changes as a Server-Sent Events stream, for clients whose proxies break WebSockets. Resumes from 'Last-Event-ID'.
+        .route("/api/webhooks", get(list_webhooks_handler).post(create_webhook_handler))
+            // Lists the caller's webhook subscriptions, or subscribes a URL to todo changes.
+        .route(
+            "/api/webhooks/:id",
+            get(get_webhook_handler)
+                .patch(edit_webhook_handler)
+                .delete(delete_webhook_handler),
         )
-        .with_state(db)
-            // Attaches our 'db' to the router so that all handler function can access and modify the todo list.
+            // Reads, changes or removes one subscription.
+        .route("/api/webhooks/:id/deliveries", get(webhook_deliveries_handler))
+            // Shows how the subscription's recent deliveries went.
+        .route("/api/webhooks/:id/test", post(test_webhook_handler))
+            // Sends the subscription a signed "ping".
+        .route("/api/sync", get(sync_pull_handler).post(sync_push_handler))
+            // Offline clients fetch only what changed since their last sync, or send back the changes they made offline.
+        .route("/api/events/rebuild", post(rebuild_projection_handler))
+            // Rebuilds the caller's workspace from the event log, when the event-sourced mode is on.
+        .route("/api/audit", get(audit_handler))
+            // Lists who changed which todo and when, or downloads it as NDJSON.
+        .route("/api/postman.json", get(postman_collection_handler))
+            // A Postman collection with a request for every route, built from the OpenAPI document.
+        .route("/graphql", post(graphql_handler))
+            // The same todos over GraphQL: queries and mutations.
+        .route("/graphql/ws", get(graphql_ws_handler))
+            // GraphQL subscriptions, pushing todo changes over a WebSocket.
+        .route_layer(middleware::from_fn_with_state(state.clone(), rate_limit_middleware))
+            // Counts each request against the caller's limit for that route; over the limit gets a 429. It runs after the JWT check below, so JWT callers are counted per user.
+        .layer(middleware::from_fn_with_state(state.jwt.clone(), jwt_middleware))
+            // Checks JWT bearer tokens before any handler runs; bad tokens are rejected with a 401 here.
+        .layer(middleware::from_fn(query_token_middleware))
+            // Lets WebSocket and EventSource clients, which can't set headers, send their token as '?access_token=' instead. Runs before the JWT check.
+        .layer(middleware::from_fn(request_id_middleware))
+            // Gives every request an ID (sent back as 'X-Request-ID') that the audit log records.
+        .layer(Extension(schema))
+            // Hands the GraphQL schema to the GraphQL handlers.
+        .with_state(state)
+            // Attaches our shared state (including 'db') to the router so that all handler function can access and modify the todo list.
 }
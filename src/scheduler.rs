@@ -0,0 +1,303 @@
+/* This file runs the reminder scheduler: a background task (started in main.rs) that wakes up every few seconds, finds reminders that are due, and hands them to every notifier. Sends that fail are retried with a growing wait (backoff). The keys of reminders that were already sent are saved to a small JSON file, so restarting the server doesn't send them again. */
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::prelude::*;
+
+use crate::{
+    audit::{AuditAction, Auditor},
+    auth::CurrentUser,
+    model::AppState,
+    notifier::{Notification, Notifier},
+    trash::SYSTEM_ACTOR,
+};
+
+/* How many times a single notifier is tried before we give up on it for this reminder. */
+const MAX_ATTEMPTS: u32 = 4;
+
+/* Settings for the scheduler, read from environment variables in 'from_env'. */
+pub struct SchedulerConfig {
+    pub poll_interval: Duration,
+        // REMINDER_POLL_SECONDS, default 30.
+    pub state_file: PathBuf,
+        // REMINDER_STATE_FILE, default "reminders-sent.json". Holds the keys of reminders that were already sent.
+}
+
+impl SchedulerConfig {
+    pub fn from_env() -> Self {
+        let poll_seconds = std::env::var("REMINDER_POLL_SECONDS")
+            .ok()
+            .and_then(|seconds| seconds.parse().ok())
+            .filter(|seconds| *seconds > 0)
+                // tokio can't tick every 0 seconds, so 0 falls back to the default too
+            .unwrap_or(30);
+        let state_file = std::env::var("REMINDER_STATE_FILE")
+            .unwrap_or_else(|_| "reminders-sent.json".to_string());
+
+        SchedulerConfig {
+            poll_interval: Duration::from_secs(poll_seconds),
+            state_file: PathBuf::from(state_file),
+        }
+    }
+}
+
+/* Starts the scheduler on its own tokio task and returns straight away. */
+pub fn spawn_reminder_scheduler(state: AppState, notifiers: Vec<Box<dyn Notifier>>, config: SchedulerConfig) {
+    tokio::spawn(async move {
+        let mut sent = load_sent_keys(&config.state_file).await;
+        let mut interval = tokio::time::interval(config.poll_interval);
+
+        loop {
+            interval.tick().await;
+            run_due_reminders(&state, &notifiers, &mut sent, &config.state_file).await;
+        }
+    });
+}
+
+/* A reminder is identified by its todo, its own ID and the time it fires. Including the time means an offset reminder fires again if the due date moves. */
+fn dedup_key(todo_id: &str, reminder_id: &str, fire_at: DateTime<Utc>) -> String {
+    format!("{}:{}:{}", todo_id, reminder_id, fire_at.to_rfc3339())
+}
+
+/* One pass of the scheduler: collect due reminders, send them, then remember the ones that were sent. A reminder no notifier could deliver is left unsent and tried again on the next pass. */
+async fn run_due_reminders(
+    state: &AppState,
+    notifiers: &[Box<dyn Notifier>],
+    sent: &mut HashSet<String>,
+    state_file: &Path,
+) {
+    let now = Utc::now();
+
+    /* Collects what needs sending while holding the lock, then lets go of it so handlers aren't kept waiting on slow notifiers. */
+    let due: Vec<(String, Notification)> = {
+        let store = state.db.lock().await;
+        store
+            .values()
+            .flatten()
+                // Reminders are sent for every workspace.
+            .filter(|todo| !todo.completed.unwrap_or(false) && todo.deletedAt.is_none())
+                // Completed and trashed todos don't send reminders.
+            .flat_map(|todo| {
+                todo.reminders.iter().filter_map(move |reminder| {
+                    let todo_id = todo.id.clone()?;
+                    let fire_at = reminder.fire_at(todo)?;
+                    if fire_at > now {
+                        return None;
+                    }
+                    Some((
+                        dedup_key(&todo_id, &reminder.id, fire_at),
+                        Notification {
+                            todoId: todo_id,
+                            reminderId: reminder.id.clone(),
+                            title: todo.title.clone(),
+                            content: todo.content.clone(),
+                            dueDate: todo.dueDate,
+                            remindAt: fire_at,
+                        },
+                    ))
+                })
+            })
+            .filter(|(key, _)| !sent.contains(key))
+            .collect()
+    };
+
+    if due.is_empty() {
+        return;
+    }
+
+    let mut delivered = Vec::new();
+    for (key, notification) in due {
+        let mut any_succeeded = false;
+        for notifier in notifiers {
+            any_succeeded |= send_with_retry(notifier.as_ref(), &notification).await;
+        }
+        if any_succeeded {
+            sent.insert(key);
+            delivered.push(notification);
+        }
+    }
+    if delivered.is_empty() {
+        return;
+    }
+
+    /* Marks the reminders as sent on the todos themselves so clients can see it. Like any other change it goes through the audit log, with "system" as the actor. */
+    {
+        let audit = Auditor::background(state);
+        let mut store = state.db.lock().await;
+        for (tenant, todos) in store.iter_mut() {
+            let system = CurrentUser {
+                id: SYSTEM_ACTOR.to_string(),
+                api_key_id: None,
+                tenant: tenant.clone(),
+            };
+            for notification in &delivered {
+                let Some(before) = todos
+                    .iter()
+                    .find(|todo| todo.id.as_deref() == Some(notification.todoId.as_str()))
+                    .cloned()
+                else {
+                    continue;
+                };
+                let mut after = before.clone();
+                let Some(reminder) = after.reminders.iter_mut().find(|reminder| reminder.id == notification.reminderId) else {
+                    continue;
+                };
+                reminder.sentAt = Some(Utc::now());
+                audit.record(todos, &system, AuditAction::Edit, Some(&before), Some(&after)).await;
+            }
+        }
+    }
+
+    save_sent_keys(state_file, sent).await;
+}
+
+/* Tries a notifier up to MAX_ATTEMPTS times, waiting 1s, 2s, 4s... between attempts. A notifier that keeps failing is logged and skipped so it can't hold up the others. Returns whether the notification got through. */
+async fn send_with_retry(notifier: &dyn Notifier, notification: &Notification) -> bool {
+    let mut delay = Duration::from_secs(1);
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        match notifier.send(notification).await {
+            Ok(()) => return true,
+            Err(err) if attempt < MAX_ATTEMPTS => {
+                eprintln!(
+                    "Reminder {} via {} failed (attempt {}): {}",
+                    notification.reminderId,
+                    notifier.name(),
+                    attempt,
+                    err
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(err) => eprintln!(
+                "Giving up on reminder {} via {}: {}",
+                notification.reminderId,
+                notifier.name(),
+                err
+            ),
+        }
+    }
+    false
+}
+
+/* Reads the keys of already-sent reminders. A missing or unreadable file just means nothing was sent yet. */
+async fn load_sent_keys(path: &Path) -> HashSet<String> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+        Err(_) => HashSet::new(),
+    }
+}
+
+async fn save_sent_keys(path: &Path, sent: &HashSet<String>) {
+    match serde_json::to_vec(sent) {
+        Ok(bytes) => {
+            if let Err(err) = tokio::fs::write(path, bytes).await {
+                eprintln!("Couldn't save sent reminders to {}: {}", path.display(), err);
+            }
+        }
+        Err(err) => eprintln!("Couldn't serialize sent reminders: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use super::run_due_reminders;
+    use crate::{
+        notifier::{Notification, Notifier, SendFuture},
+        testing::{app, create_todo, login, send},
+    };
+
+    /* A notifier that records what it was asked to send, or always fails. */
+    struct StubNotifier {
+        fails: bool,
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Notifier for StubNotifier {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        fn send<'a>(&'a self, notification: &'a Notification) -> SendFuture<'a> {
+            Box::pin(async move {
+                if self.fails {
+                    return Err("receiver is down".to_string());
+                }
+                self.sent.lock().unwrap().push(notification.reminderId.clone());
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn due_reminders_are_sent_once() {
+        let (router, state) = app();
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+        let todo = create_todo(&router, &alice, json!({ "title": "Call", "content": "a" })).await;
+        let uri = format!("/api/todos/{}", todo["id"].as_str().unwrap());
+        let (status, body) = send(
+            &router,
+            Method::POST,
+            &format!("{}/reminders", uri),
+            &auth,
+            json!({ "remindAt": "2020-01-01T09:00:00Z" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        let reminder_id = body["data"]["todo"]["reminders"][0]["id"].as_str().unwrap().to_string();
+
+        let state_file = std::env::temp_dir().join(format!("reminders-{}.json", uuid::Uuid::new_v4()));
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let working = || -> Box<dyn Notifier> {
+            Box::new(StubNotifier {
+                fails: false,
+                sent: delivered.clone(),
+            })
+        };
+        let failing = || -> Box<dyn Notifier> {
+            Box::new(StubNotifier {
+                fails: true,
+                sent: delivered.clone(),
+            })
+        };
+        let sent_at = || async {
+            let (_, body) = send(&router, Method::GET, &uri, &auth, Value::Null).await;
+            body["data"]["todo"]["reminders"][0]["sentAt"].clone()
+        };
+        let mut sent = HashSet::new();
+
+        // skip the real waits between retries
+        tokio::time::pause();
+
+        /* Nothing got through, so the reminder isn't marked as sent and will be tried again. */
+        run_due_reminders(&state, &[failing()], &mut sent, &state_file).await;
+        assert!(sent.is_empty());
+        assert_eq!(sent_at().await, Value::Null);
+
+        /* One notifier succeeding is enough. */
+        run_due_reminders(&state, &[failing(), working()], &mut sent, &state_file).await;
+        assert_eq!(*delivered.lock().unwrap(), vec![reminder_id.clone()]);
+        assert!(sent_at().await.is_string());
+        let saved: HashSet<String> = serde_json::from_slice(&std::fs::read(&state_file).unwrap()).unwrap();
+        assert_eq!(saved, sent);
+
+        /* It isn't sent again on the next pass. */
+        run_due_reminders(&state, &[working()], &mut sent, &state_file).await;
+        assert_eq!(delivered.lock().unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(&state_file);
+    }
+}
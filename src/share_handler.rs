@@ -0,0 +1,302 @@
+/* This file holds the handlers for sharing a todo, or a whole list, with other users (see policy.rs). */
+
+/* Imports the parts of the axum webframework the handlers below use (see handler.rs for what each one does). */
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+/* Imports Uuid, used for the user IDs in the paths. */
+use uuid::Uuid;
+
+/* Imports structures and functions from our local crate (IE our project). */
+use crate::{
+    handler::authorize,
+    audit::{AuditAction, Auditor},
+    auth::CurrentUser,
+    dependency,
+    policy::{self, Action},
+    model::{ListShare, ListShareDB, Share, ShareSchema, UserDB, DB},
+    response::{GenericResponse, SingleTodoResponse, TodoData},
+};
+
+/* Looks up the user a todo or list is being shared with, and makes sure the role can be handed out (nobody can be made an owner). */
+async fn share_target(
+    users: &UserDB,
+    user: &CurrentUser,
+    body: &ShareSchema,
+) -> Result<String, (StatusCode, Json<serde_json::Value>)> {
+    if body.role == policy::Role::Owner {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "Only the 'editor' and 'viewer' roles can be shared",
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    let target = users
+        .lock()
+        .await
+        .iter()
+        .find(|target| target.username == body.username && target.tenantId == user.tenant)
+            // Todos can only be shared inside the workspace.
+        .map(|target| target.id.clone());
+
+    match target {
+        Some(target_id) if target_id != user.id => Ok(target_id),
+        Some(_) => {
+            let error_response = serde_json::json!({
+                "status": "fail",
+                "message": "You can't share with yourself",
+            });
+            Err((StatusCode::BAD_REQUEST, Json(error_response)))
+        }
+        None => {
+            let error_response = serde_json::json!({
+                "status": "fail",
+                "message": format!("User with username: '{}' not found", body.username),
+            });
+            Err((StatusCode::NOT_FOUND, Json(error_response)))
+        }
+    }
+}
+
+/* Shares a todo with another user as an editor or viewer. Sharing again with the same user changes their role. Only the owner can share. */
+#[utoipa::path(
+    post,
+    path = "/api/todos/{id}/share",
+    tag = "sharing",
+    summary = "Shares a todo with another user",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    request_body = ShareSchema,
+    responses(
+        (status = 200, description = "The todo, with its new share", body = SingleTodoResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn share_todo_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    State(users): State<UserDB>,
+    State(list_shares): State<ListShareDB>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(body): Json<ShareSchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let target_id = share_target(&users, &user, &body).await?;
+    let list_shares = list_shares.lock().await.clone();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    let pos = authorize(vec, &id, &user, &list_shares, Action::Share)?;
+    let before = vec[pos].clone();
+    let mut todo = before.clone();
+    todo.sharedWith.retain(|share| share.userId != target_id);
+    todo.sharedWith.push(Share {
+        userId: target_id,
+        role: body.role,
+    });
+    todo.updatedAt = Some(chrono::Utc::now());
+    audit.record(vec, &user, AuditAction::Edit, Some(&before), Some(&todo)).await;
+
+    let json_response = SingleTodoResponse {
+        status: "success".to_string(),
+        data: TodoData {
+            todo: dependency::annotate(vec, &todo),
+            next: None,
+        },
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Stops sharing a todo with a user. Only the owner can unshare. */
+#[utoipa::path(
+    delete,
+    path = "/api/todos/{id}/share/{user_id}",
+    tag = "sharing",
+    summary = "Stops sharing a todo with a user",
+    params(("id" = Uuid, Path, description = "The todo's ID"), ("user_id" = Uuid, Path, description = "The user it was shared with")),
+    responses(
+        (status = 204, description = "The todo is no longer shared with the user"),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn unshare_todo_handler(
+    Path((id, user_id)): Path<(Uuid, Uuid)>,
+    State(db): State<DB>,
+    State(list_shares): State<ListShareDB>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let user_id = user_id.to_string();
+    let list_shares = list_shares.lock().await.clone();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    let pos = authorize(vec, &id, &user, &list_shares, Action::Share)?;
+    let before = vec[pos].clone();
+    let mut todo = before.clone();
+    todo.sharedWith.retain(|share| share.userId != user_id);
+    if todo.sharedWith.len() == before.sharedWith.len() {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("Todo with ID: {} is not shared with user {}", id, user_id)
+        });
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    }
+    todo.updatedAt = Some(chrono::Utc::now());
+    audit.record(vec, &user, AuditAction::Edit, Some(&before), Some(&todo)).await;
+
+    Ok((StatusCode::NO_CONTENT, Json("")))
+}
+
+/* Shares one of the caller's lists with another user. The role covers every todo the caller has (or adds later) in that list. */
+#[utoipa::path(
+    post,
+    path = "/api/lists/{list}/share",
+    tag = "sharing",
+    summary = "Shares every todo in one of the caller's lists",
+    params(("list" = String, Path, description = "The list's name")),
+    request_body = ShareSchema,
+    responses(
+        (status = 200, description = "The share, as \"data.share\" (a ListShare)", body = serde_json::Value),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn share_list_handler(
+    Path(list): Path<String>,
+    State(db): State<DB>,
+    State(users): State<UserDB>,
+    State(list_shares): State<ListShareDB>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(body): Json<ShareSchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let target_id = share_target(&users, &user, &body).await?;
+
+    let mut list_shares = list_shares.lock().await;
+    list_shares.retain(|share| !(share.ownerId == user.id && share.list == list && share.userId == target_id));
+    let share = ListShare {
+        ownerId: user.id.clone(),
+        list,
+        userId: target_id,
+        role: body.role,
+    };
+    list_shares.push(share.clone());
+    record_list_access(&db, &audit, &user, &share.list, &share.userId, AuditAction::Share).await;
+
+    let json_response = serde_json::json!({
+        "status": "success",
+        "data": { "share": share }
+    });
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Stops sharing one of the caller's lists with a user. */
+#[utoipa::path(
+    delete,
+    path = "/api/lists/{list}/share/{user_id}",
+    tag = "sharing",
+    summary = "Stops sharing a list with a user",
+    params(("list" = String, Path, description = "The list's name"), ("user_id" = Uuid, Path, description = "The user it was shared with")),
+    responses(
+        (status = 204, description = "The list is no longer shared with the user"),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "The list isn't shared with the user", body = GenericResponse),
+    ),
+)]
+pub async fn unshare_list_handler(
+    Path((list, user_id)): Path<(String, Uuid)>,
+    State(db): State<DB>,
+    State(list_shares): State<ListShareDB>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let user_id = user_id.to_string();
+
+    let mut list_shares = list_shares.lock().await;
+    let before = list_shares.len();
+    list_shares.retain(|share| !(share.ownerId == user.id && share.list == list && share.userId == user_id));
+    if list_shares.len() == before {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("List '{}' is not shared with user {}", list, user_id)
+        });
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    }
+    record_list_access(&db, &audit, &user, &list, &user_id, AuditAction::Unshare).await;
+
+    Ok((StatusCode::NO_CONTENT, Json("")))
+}
+
+/* Writes a 'Share' or 'Unshare' audit entry for each of the caller's todos in a list, so sync can tell the other user what they gained or lost. */
+async fn record_list_access(db: &DB, audit: &Auditor, user: &CurrentUser, list: &str, target_id: &str, action: AuditAction) {
+    let store = db.lock().await;
+    let Some(vec) = store.get(&user.tenant) else {
+        return;
+    };
+    let in_list = vec.iter().filter(|todo| {
+        todo.deletedAt.is_none()
+            && todo.ownerId.as_deref() == Some(user.id.as_str())
+            && todo.list.as_deref() == Some(list)
+    });
+    for todo in in_list {
+        audit.record_access(user, action, todo, target_id).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::{
+        model::AppState,
+        testing::{app_with, create_todo, login, send},
+    };
+
+    #[tokio::test]
+    async fn a_viewer_can_read_but_not_edit() {
+        let (router, _) = app_with(AppState::for_tests());
+        let alice = login(&router, "default", "alice").await;
+        let bob = login(&router, "default", "bob").await;
+        let auth = [("authorization", alice.as_str())];
+        let bob_auth = [("authorization", bob.as_str())];
+        let todo = create_todo(&router, &alice, json!({ "title": "Shared", "content": "a" })).await;
+        let todo_uri = format!("/api/todos/{}", todo["id"].as_str().unwrap());
+        let (_, body) = send(&router, Method::GET, "/api/auth/me", &bob_auth, Value::Null).await;
+        let bob_id = body["data"]["userId"].as_str().unwrap().to_string();
+
+        /* Bob can't see Alice's todo until she shares it with him. */
+        let (status, _) = send(&router, Method::GET, &todo_uri, &bob_auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let share_uri = format!("{}/share", todo_uri);
+        let (status, body) = send(&router, Method::POST, &share_uri, &auth, json!({ "username": "bob", "role": "viewer" })).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let (status, body) = send(&router, Method::GET, &todo_uri, &bob_auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["todo"]["title"], "Shared");
+
+        /* As a viewer he can't change it, or share it on. */
+        let (status, _) = send(&router, Method::PATCH, &todo_uri, &bob_auth, json!({ "title": "Mine" })).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&router, Method::POST, &share_uri, &bob_auth, json!({ "username": "alice", "role": "editor" })).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        /* Unsharing takes the todo away from him again. */
+        let (status, _) = send(&router, Method::DELETE, &format!("{}/{}", share_uri, bob_id), &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&router, Method::GET, &todo_uri, &bob_auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,336 @@
+/* This file lets offline-first clients sync without downloading every todo each time. A pull ("GET /api/sync?since=<token>") sends what changed since the token, a position in the workspace's audit log, and a push ("POST /api/sync") applies changes made offline, settling conflicts by the batch's strategy. */
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::AuditEntry,
+    auth::CurrentUser,
+    model::{ListShare, Todo, UpdateTodoSchema},
+    policy,
+};
+
+/* The most changes one push may contain. */
+pub const MAX_BATCH: usize = 500;
+
+/* The fields a client can change through sync, as named in JSON. */
+const SYNC_FIELDS: &[&str] = &["title", "content", "completed", "status", "dueDate", "recurrence", "list", "tags"];
+
+/* A todo that is gone for this client. */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Tombstone {
+    pub id: String,
+    pub deletedAt: DateTime<Utc>,
+}
+
+/* The result of a pull, before it is wrapped in a response. */
+pub struct Delta {
+    pub full: bool,
+    pub todos: Vec<Todo>,
+    pub deleted: Vec<Tombstone>,
+}
+
+/* Reads a token back into a position in the workspace's audit log. Tokens past the end of the log weren't handed out by this server. */
+pub fn parse_token(token: Option<&str>, log_len: usize) -> Option<usize> {
+    token?.parse().ok().filter(|position| *position <= log_len)
+}
+
+/* True if the user can see the todo and it isn't in the trash. */
+fn visible(todo: &Todo, user: &CurrentUser, list_shares: &[ListShare]) -> bool {
+    todo.deletedAt.is_none() && policy::role_for(&user.id, todo, list_shares).is_some()
+}
+
+/* Works out what a client that has read the log up to 'since' needs. 'todos' are the workspace's todos and 'log' its audit log. Todos are returned as stored; the caller fills in computed fields. */
+pub fn delta(todos: &[Todo], log: &[AuditEntry], since: Option<usize>, user: &CurrentUser, list_shares: &[ListShare]) -> Delta {
+    let Some(since) = since else {
+        return Delta {
+            full: true,
+            todos: todos.iter().filter(|todo| visible(todo, user, list_shares)).cloned().collect(),
+            deleted: Vec::new(),
+        };
+    };
+
+    /* One pass over the new entries: when each todo last changed, and which todos the user could see at some point (or was given or lost access to). */
+    let mut last_change: HashMap<&str, (usize, DateTime<Utc>)> = HashMap::new();
+    let mut seen: HashSet<&str> = HashSet::new();
+    for (index, entry) in log[since..].iter().enumerate() {
+        last_change.insert(&entry.todoId, (index, entry.at));
+        let visible_then = entry.targetUserId.as_ref() == Some(&user.id)
+            || [entry.before.as_ref(), entry.after.as_ref()]
+                .into_iter()
+                .flatten()
+                .any(|snapshot| policy::role_for(&user.id, snapshot, list_shares).is_some());
+        if visible_then {
+            seen.insert(&entry.todoId);
+        }
+    }
+
+    /* The todos changed since the token, in the order they last changed. */
+    let mut changed: Vec<(&str, (usize, DateTime<Utc>))> = last_change.into_iter().collect();
+    changed.sort_by_key(|(_, (index, _))| *index);
+
+    let mut delta = Delta {
+        full: false,
+        todos: Vec::new(),
+        deleted: Vec::new(),
+    };
+    for (id, (_, last_change)) in changed {
+        let current = todos.iter().find(|todo| todo.id.as_deref() == Some(id));
+        if let Some(todo) = current.filter(|todo| visible(todo, user, list_shares)) {
+            delta.todos.push(todo.clone());
+            continue;
+        }
+
+        /* Gone or hidden now. Only send a tombstone if the user could see it at some point since the token, so IDs of other people's todos don't leak. */
+        if seen.contains(id) {
+            delta.deleted.push(Tombstone {
+                id: id.to_string(),
+                deletedAt: current.and_then(|todo| todo.deletedAt).unwrap_or(last_change),
+            });
+        }
+    }
+    delta
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    #[default]
+    LastWriterWins,
+        // The newer of the client's 'changedAt' and the server's 'updatedAt' wins, for the whole todo.
+    FieldLevel,
+        // Fields only one side changed are kept; fields both changed keep the server's value.
+}
+
+/* One change made on the client. The fields are the same as for "PATCH /api/todos/:id"; only the ones sent are changed. */
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct SyncChange {
+    pub id: uuid::Uuid,
+    #[serde(default)]
+    pub deleted: bool,
+    pub baseUpdatedAt: Option<DateTime<Utc>>,
+    pub changedAt: DateTime<Utc>,
+    #[serde(flatten)]
+    pub fields: UpdateTodoSchema,
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct SyncPushSchema {
+    #[serde(default)]
+    pub strategy: ConflictStrategy,
+    pub changes: Vec<SyncChange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncOutcome {
+    Applied,
+        // Applied in full (any conflict was won by the client).
+    Merged,
+        // Applied in part: some fields kept the server's value.
+    Conflict,
+        // Not applied: the server's version won.
+    Rejected,
+        // Not applied because it was invalid or not allowed; see 'error'.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Winner {
+    Client,
+    Server,
+}
+
+/* A field both sides changed, and whose value was kept. */
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct FieldConflict {
+    pub field: String,
+    pub client: serde_json::Value,
+    pub server: serde_json::Value,
+    pub winner: Winner,
+}
+
+/* What happened to one pushed change. 'todo' is the server's version afterwards (None if it doesn't exist or the client can't see it). */
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct SyncResult {
+    pub id: String,
+    pub outcome: SyncOutcome,
+    pub conflicts: Vec<FieldConflict>,
+    pub todo: Option<Todo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/* A todo's value for one sync field, as JSON. */
+fn todo_field(todo: &Todo, field: &str) -> serde_json::Value {
+    serde_json::to_value(todo)
+        .ok()
+        .and_then(|value| value.get(field).cloned())
+        .unwrap_or(serde_json::Value::Null)
+}
+
+/* The fields the client sent, with the value each would leave on the todo. Empty strings for 'list' and 'recurrence' mean "none", as in the edit handler. */
+pub fn sent_fields(fields: &UpdateTodoSchema) -> Vec<(&'static str, serde_json::Value)> {
+    let as_json = serde_json::to_value(fields).unwrap_or_default();
+    SYNC_FIELDS
+        .iter()
+        .filter_map(|field| {
+            let value = as_json.get(*field)?;
+            match value {
+                serde_json::Value::Null => None,
+                serde_json::Value::String(text) if text.is_empty() && matches!(*field, "list" | "recurrence") => {
+                    Some((*field, serde_json::Value::Null))
+                }
+                _ => Some((*field, value.clone())),
+            }
+        })
+        .collect()
+}
+
+/* The conflicts between what the client sent and the server's current todo: every sent field whose value differs. */
+pub fn differences(fields: &UpdateTodoSchema, server: &Todo, winner: Winner) -> Vec<FieldConflict> {
+    sent_fields(fields)
+        .into_iter()
+        .filter_map(|(field, client)| {
+            let server = todo_field(server, field);
+            (client != server).then(|| FieldConflict {
+                field: field.to_string(),
+                client,
+                server,
+                winner,
+            })
+        })
+        .collect()
+}
+
+/* Field-level merge: splits the client's fields into the ones that can be applied and the ones the server keeps. A field conflicts when the server changed it since 'base' (or there is no base to compare with) and it now differs from the client's value. */
+pub fn merge_fields(fields: &UpdateTodoSchema, server: &Todo, base: Option<&Todo>) -> (UpdateTodoSchema, Vec<FieldConflict>) {
+    let mut apply = fields.clone();
+    let mut conflicts = Vec::new();
+
+    for (field, client) in sent_fields(fields) {
+        let server_value = todo_field(server, field);
+        let server_changed = base.is_none_or(|base| todo_field(base, field) != server_value);
+        if server_changed && client != server_value {
+            clear_field(&mut apply, field);
+            conflicts.push(FieldConflict {
+                field: field.to_string(),
+                client,
+                server: server_value,
+                winner: Winner::Server,
+            });
+        }
+    }
+    (apply, conflicts)
+}
+
+/* Drops one field from an edit, so it isn't applied. */
+fn clear_field(fields: &mut UpdateTodoSchema, field: &str) {
+    match field {
+        "title" => fields.title = None,
+        "content" => fields.content = None,
+        "completed" => fields.completed = None,
+        "status" => fields.status = None,
+        "dueDate" => fields.dueDate = None,
+        "recurrence" => fields.recurrence = None,
+        "list" => fields.list = None,
+        "tags" => fields.tags = None,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::testing::{app, create_todo, login, send};
+
+    /* Pulls everything that changed since 'token'. */
+    async fn pull(router: &axum::Router, auth: &str, token: &str) -> Value {
+        let uri = format!("/api/sync?since={}", token);
+        let (status, body) = send(router, Method::GET, &uri, &[("authorization", auth)], Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        body
+    }
+
+    #[tokio::test]
+    async fn a_pull_returns_only_later_changes() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+        let first = create_todo(&router, &alice, json!({ "title": "First", "content": "a" })).await;
+
+        /* A pull without a token sends everything. */
+        let (status, body) = send(&router, Method::GET, "/api/sync", &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["results"], 1);
+        let token = body["token"].as_str().unwrap().to_string();
+
+        /* Only the todo created since then comes back. */
+        let second = create_todo(&router, &alice, json!({ "title": "Second", "content": "b" })).await;
+        let body = pull(&router, &alice, &token).await;
+        assert_eq!(body["results"], 1);
+        assert_eq!(body["todos"][0]["id"], second["id"]);
+        let token = body["token"].as_str().unwrap().to_string();
+
+        /* Then only the edited one, as it is now. */
+        let uri = format!("/api/todos/{}", first["id"].as_str().unwrap());
+        let (status, _) = send(&router, Method::PATCH, &uri, &auth, json!({ "title": "First, renamed" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let body = pull(&router, &alice, &token).await;
+        assert_eq!(body["results"], 1);
+        assert_eq!(body["todos"][0]["title"], "First, renamed");
+        let token = body["token"].as_str().unwrap().to_string();
+
+        /* Nothing has changed since the last pull. */
+        let body = pull(&router, &alice, &token).await;
+        assert_eq!(body["results"], 0);
+        assert_eq!(body["token"], token.as_str());
+    }
+
+    #[tokio::test]
+    async fn list_shares_show_up_in_a_pull() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let bob = login(&router, "default", "bob").await;
+        let bob_auth = [("authorization", bob.as_str())];
+        let alice_auth = [("authorization", alice.as_str())];
+
+        let todo = create_todo(&router, &alice, json!({ "title": "Plan", "content": "a", "list": "work" })).await;
+        let id = todo["id"].as_str().unwrap();
+        let (_, body) = send(&router, Method::GET, "/api/sync", &bob_auth, Value::Null).await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        /* Another workspace's activity doesn't move Bob's token. */
+        let carol = login(&router, "other", "carol").await;
+        create_todo(&router, &carol, json!({ "title": "Elsewhere", "content": "c" })).await;
+        let body = pull(&router, &bob, &token).await;
+        assert_eq!(body["token"], token.as_str());
+        assert_eq!(body["results"], 0);
+
+        let (status, body) = send(&router, Method::GET, "/api/auth/me", &bob_auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let bob_id = body["data"]["userId"].as_str().unwrap().to_string();
+        let share = json!({ "username": "bob", "role": "viewer" });
+        let (status, _) = send(&router, Method::POST, "/api/lists/work/share", &alice_auth, share).await;
+        assert_eq!(status, StatusCode::OK);
+
+        /* Gaining access to the list sends the todo... */
+        let body = pull(&router, &bob, &token).await;
+        assert_eq!(body["todos"][0]["id"], id);
+        let token = body["token"].as_str().unwrap().to_string();
+
+        /* ...and losing it sends a tombstone. */
+        let uri = format!("/api/lists/work/share/{}", bob_id);
+        let (status, _) = send(&router, Method::DELETE, &uri, &alice_auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let body = pull(&router, &bob, &token).await;
+        assert_eq!(body["results"], 0);
+        assert_eq!(body["deleted"][0]["id"], id);
+    }
+}
@@ -0,0 +1,270 @@
+/* This file holds the handlers offline clients sync through (see sync.rs). */
+
+/* Imports the parts of the axum webframework the handlers below use (see handler.rs for what each one does). */
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+/* Imports HashSet, used to look up IDs, and Arc for shared settings. */
+use std::{collections::HashSet, sync::Arc};
+
+/* Imports structures and functions from our local crate (IE our project). */
+use crate::{
+    handler::{apply_edit, authorize_including_trash, insert_todo, trash_todo},
+    audit::{AuditLog, Auditor},
+    auth::CurrentUser,
+    dependency,
+    policy::Action,
+    model::{CreateTodoSchema, ListShare, ListShareDB, SyncQuery, Todo, DB},
+    response::{GenericResponse, SyncPushResponse, SyncResponse},
+    revision::RevisionStore,
+    sync::{self, ConflictStrategy, SyncChange, SyncOutcome, SyncPushSchema, SyncResult, Winner},
+    tenant::TenantConfig,
+    workflow::Workflow,
+};
+
+/* Sends an offline client what changed since its last sync (see sync.rs). Without a token (or with one this server doesn't know) every visible todo is sent instead. */
+#[utoipa::path(
+    get,
+    path = "/api/sync",
+    tag = "sync",
+    summary = "Fetches what changed since the last sync",
+    params(SyncQuery),
+    responses(
+        (status = 200, description = "The changes, and the token to send next time", body = SyncResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn sync_pull_handler(
+    opts: Option<Query<SyncQuery>>,
+    State(db): State<DB>,
+    State(list_shares): State<ListShareDB>,
+    State(audit_log): State<AuditLog>,
+    user: CurrentUser,
+) -> impl IntoResponse {
+    let Query(opts) = opts.unwrap_or_default();
+    let list_shares = list_shares.lock().await.clone();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+    let logs = audit_log.lock().await;
+    let log = logs.get(&user.tenant).map(Vec::as_slice).unwrap_or_default();
+        // Both stay locked while the delta is worked out, so the new token matches exactly what was sent.
+
+    let since = sync::parse_token(opts.since.as_deref(), log.len());
+    let delta = sync::delta(vec, log, since, &user, &list_shares);
+    let todos: Vec<Todo> = delta.todos.iter().map(|todo| dependency::annotate(vec, todo)).collect();
+
+    let json_response = SyncResponse {
+        status: "success".to_string(),
+        token: log.len().to_string(),
+        full: delta.full,
+        results: todos.len(),
+        todos,
+        deleted: delta.deleted,
+    };
+    Json(json_response)
+}
+
+/* Everything a pushed change needs besides the todos themselves. */
+struct SyncContext<'a> {
+    strategy: ConflictStrategy,
+    workflow: &'a Workflow,
+    tenants: &'a TenantConfig,
+    list_shares: &'a [ListShare],
+    revisions: &'a RevisionStore,
+    user: &'a CurrentUser,
+    audit: &'a Auditor,
+    foreign_ids: &'a HashSet<String>,
+        // IDs of todos in other workspaces, which a new todo can't take.
+}
+
+/* Applies a batch of changes made on an offline client, settling conflicts with the batch's strategy (see sync.rs). Each change is handled on its own, so one bad change doesn't stop the rest; the response says what happened to each. */
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    post,
+    path = "/api/sync",
+    tag = "sync",
+    summary = "Sends back changes made offline",
+    request_body = SyncPushSchema,
+    responses(
+        (status = 200, description = "One result per change", body = SyncPushResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn sync_push_handler(
+    State(db): State<DB>,
+    State(workflow): State<Arc<Workflow>>,
+    State(tenants): State<Arc<TenantConfig>>,
+    State(list_shares): State<ListShareDB>,
+    State(revisions): State<Arc<RevisionStore>>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(body): Json<SyncPushSchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    if body.changes.len() > sync::MAX_BATCH {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": format!("A sync can push at most {} changes at once", sync::MAX_BATCH),
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    let list_shares = list_shares.lock().await.clone();
+    let mut store = db.lock().await;
+    let foreign_ids: HashSet<String> = store
+        .iter()
+        .filter(|(tenant, _)| **tenant != user.tenant)
+        .flat_map(|(_, todos)| todos.iter().filter_map(|todo| todo.id.clone()))
+        .collect();
+    let vec = store.entry(user.tenant.clone()).or_default();
+    let context = SyncContext {
+        strategy: body.strategy,
+        workflow: &workflow,
+        tenants: &tenants,
+        list_shares: &list_shares,
+        revisions: &revisions,
+        user: &user,
+        audit: &audit,
+        foreign_ids: &foreign_ids,
+    };
+
+    let mut changes = Vec::with_capacity(body.changes.len());
+    for change in body.changes {
+        changes.push(sync_change(vec, change, &context).await);
+    }
+
+    let json_response = SyncPushResponse {
+        status: "success".to_string(),
+        results: changes.len(),
+        changes,
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Applies one pushed change and reports what happened. */
+async fn sync_change(vec: &mut Vec<Todo>, change: SyncChange, context: &SyncContext<'_>) -> SyncResult {
+    let id = change.id.to_string();
+    let mut result = SyncResult {
+        id: id.clone(),
+        outcome: SyncOutcome::Applied,
+        conflicts: Vec::new(),
+        todo: None,
+        error: None,
+    };
+    let rejected = |mut result: SyncResult, (_, Json(error)): (StatusCode, Json<serde_json::Value>)| {
+        result.outcome = SyncOutcome::Rejected;
+        result.error = error["message"].as_str().map(str::to_string);
+        result
+    };
+
+    /* A todo the server doesn't have yet was created on the client, under the ID the client picked. Deleting one the server never had needs no work. */
+    let Some(pos) = vec.iter().position(|todo| todo.id.as_deref() == Some(id.as_str())) else {
+        if change.deleted {
+            return result;
+        }
+        if context.foreign_ids.contains(&id) {
+            result.outcome = SyncOutcome::Rejected;
+            result.error = Some("This ID is already taken, pick another one".to_string());
+            return result;
+        }
+            // IDs are unique across the whole server, so a client can't borrow another workspace's todo ID.
+        let Some(title) = change.fields.title else {
+            result.outcome = SyncOutcome::Rejected;
+            result.error = Some("A new todo needs a title".to_string());
+            return result;
+        };
+        let body = CreateTodoSchema {
+            title,
+            content: change.fields.content.unwrap_or_default(),
+            status: change.fields.status,
+            dueDate: change.fields.dueDate,
+            recurrence: change.fields.recurrence.filter(|rule| !rule.is_empty()),
+            list: change.fields.list.filter(|list| !list.is_empty()),
+            tags: change.fields.tags,
+        };
+        return match insert_todo(vec, change.id, context.workflow, context.tenants, context.user, context.audit, body).await {
+            Ok(todo) => {
+                result.todo = Some(dependency::annotate(vec, &todo));
+                result
+            }
+            Err(error) => rejected(result, error),
+        };
+    };
+
+    let action = if change.deleted { Action::Delete } else { Action::Edit };
+    if let Err(error) = authorize_including_trash(vec, &id, context.user, context.list_shares, action, true) {
+        return rejected(result, error);
+    }
+    let server = vec[pos].clone();
+
+    /* Deleted on the server while the client was offline: a delete agrees with that, and an edit loses to it. */
+    if server.deletedAt.is_some() {
+        if !change.deleted {
+            result.outcome = SyncOutcome::Conflict;
+            result.error = Some("This todo was deleted on the server".to_string());
+        }
+        return result;
+    }
+
+    /* Settles the conflict if the server's copy changed since the client last saw it. 'apply' is what gets applied, if anything. */
+    let stale = change.baseUpdatedAt != server.updatedAt;
+    let (apply, conflicts, outcome) = match context.strategy {
+        _ if !stale => (Some(change.fields), Vec::new(), SyncOutcome::Applied),
+        ConflictStrategy::LastWriterWins => {
+            if server.updatedAt.is_none_or(|updated_at| change.changedAt > updated_at) {
+                let conflicts = sync::differences(&change.fields, &server, Winner::Client);
+                (Some(change.fields), conflicts, SyncOutcome::Applied)
+            } else {
+                let conflicts = sync::differences(&change.fields, &server, Winner::Server);
+                (None, conflicts, SyncOutcome::Conflict)
+            }
+        }
+        ConflictStrategy::FieldLevel if change.deleted => (None, Vec::new(), SyncOutcome::Conflict),
+            // Someone changed the todo since; deleting it would throw their work away.
+        ConflictStrategy::FieldLevel => {
+            let base = match change.baseUpdatedAt {
+                Some(base) => context
+                    .revisions
+                    .history(&context.user.tenant, &id)
+                    .await
+                    .into_iter()
+                    .find(|revision| revision.todo.updatedAt == Some(base))
+                    .map(|revision| revision.todo),
+                None => None,
+            };
+            let (apply, conflicts) = sync::merge_fields(&change.fields, &server, base.as_ref());
+            match (conflicts.is_empty(), sync::sent_fields(&apply).is_empty()) {
+                (true, _) => (Some(apply), conflicts, SyncOutcome::Applied),
+                (false, false) => (Some(apply), conflicts, SyncOutcome::Merged),
+                (false, true) => (None, conflicts, SyncOutcome::Conflict),
+            }
+        }
+    };
+    result.outcome = outcome;
+    result.conflicts = conflicts;
+
+    let Some(apply) = apply else {
+        result.todo = Some(dependency::annotate(vec, &server));
+        return result;
+    };
+    if change.deleted {
+        trash_todo(vec, pos, context.user, context.audit).await;
+        return result;
+    }
+    if sync::sent_fields(&apply).is_empty() {
+        result.todo = Some(dependency::annotate(vec, &server));
+            // Nothing to change, so the todo isn't touched (and its 'updatedAt' stays put).
+        return result;
+    }
+    match apply_edit(vec, pos, context.workflow, context.user, context.audit, apply).await {
+        Ok((todo, _)) => {
+            result.todo = Some(dependency::annotate(vec, &todo));
+            result
+        }
+        Err(error) => rejected(result, error),
+    }
+}
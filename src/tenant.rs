@@ -0,0 +1,203 @@
+/* This file splits the API into workspaces (tenants) so several teams can share one server without ever seeing each other's data. A request picks its workspace with a JWT "tenant" claim, an "X-Tenant-ID" header or a subdomain, and otherwise uses "default". */
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header::HOST, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/* The header a workspace can be named in. */
+pub const TENANT_HEADER: &str = "x-tenant-id";
+/* The JWT claim a workspace can be named in. */
+pub const TENANT_CLAIM: &str = "tenant";
+/* The workspace used when a request doesn't name one. */
+pub const DEFAULT_TENANT: &str = "default";
+
+/* How workspaces are found and how much each may store. Built once in main.rs from environment variables. */
+#[derive(Debug, Default)]
+pub struct TenantConfig {
+    base_domain: Option<String>,
+    max_todos: Option<usize>,
+    quotas: HashMap<String, usize>,
+    admins: HashMap<String, Vec<String>>,
+        // Workspace -> the usernames allowed to run admin operations there (like rebuilding from the event log).
+}
+
+impl TenantConfig {
+    /* Reads TENANT_BASE_DOMAIN, TENANT_MAX_TODOS, TENANT_QUOTAS ("team-a=100,team-b=500") and TENANT_ADMINS ("default=alice,team-a=bob", a workspace may be named more than once). Entries that can't be read are skipped with a warning. */
+    pub fn from_env() -> Self {
+        let quotas = env_entries("TENANT_QUOTAS", |limit| limit.parse().ok()).into_iter().collect();
+        let mut admins: HashMap<String, Vec<String>> = HashMap::new();
+        for (tenant, username) in env_entries("TENANT_ADMINS", |username| Some(username.to_string())) {
+            admins.entry(tenant).or_default().push(username);
+        }
+
+        TenantConfig {
+            base_domain: std::env::var("TENANT_BASE_DOMAIN")
+                .ok()
+                .map(|domain| domain.trim_start_matches('.').to_lowercase()),
+            max_todos: std::env::var("TENANT_MAX_TODOS").ok().and_then(|value| value.parse().ok()),
+            quotas,
+            admins,
+        }
+    }
+
+    /* Every workspace limited to the same number of todos, for tests. */
+    #[cfg(test)]
+    pub fn limited(max_todos: usize) -> Self {
+        TenantConfig {
+            max_todos: Some(max_todos),
+            ..Default::default()
+        }
+    }
+
+    /* One admin for one workspace, for tests. */
+    #[cfg(test)]
+    pub fn with_admin(tenant: &str, username: &str) -> Self {
+        TenantConfig {
+            admins: HashMap::from([(tenant.to_string(), vec![username.to_string()])]),
+            ..Default::default()
+        }
+    }
+
+    /* Whether the user with this username is an admin of the workspace. */
+    pub fn is_admin(&self, tenant: &str, username: &str) -> bool {
+        self.admins.get(tenant).is_some_and(|admins| admins.iter().any(|admin| admin == username))
+    }
+
+    /* The most todos a workspace may hold, or None for no limit. */
+    pub fn quota(&self, tenant: &str) -> Option<usize> {
+        self.quotas.get(tenant).copied().or(self.max_todos)
+    }
+
+    /* The workspace the request names through its header or subdomain, if any. A name that isn't valid is an error. */
+    pub fn requested(&self, parts: &Parts) -> Result<Option<String>, String> {
+        let from_header = parts
+            .headers
+            .get(TENANT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let from_subdomain = || {
+            let base = self.base_domain.as_ref()?;
+            let host = parts.headers.get(HOST)?.to_str().ok()?.to_lowercase();
+            let host = host.split(':').next().unwrap_or_default().to_string();
+                // Drops the port, if there is one.
+            let subdomain = host.strip_suffix(base.as_str())?.strip_suffix('.')?;
+            (!subdomain.is_empty()).then(|| subdomain.to_string())
+        };
+
+        match from_header.or_else(from_subdomain) {
+            Some(tenant) if is_valid(&tenant) => Ok(Some(tenant)),
+            Some(tenant) => Err(format!(
+                "Invalid workspace name: '{}' (use 1-63 lowercase letters, digits and dashes)",
+                tenant
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+/* Reads a "workspace=value,workspace=value" environment variable. 'parse' reads each value; entries it can't read are skipped with a warning. */
+fn env_entries<T>(name: &str, parse: impl Fn(&str) -> Option<T>) -> Vec<(String, T)> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| {
+            let parsed = entry
+                .split_once('=')
+                .and_then(|(tenant, value)| Some((tenant.trim().to_string(), parse(value.trim())?)));
+            if parsed.is_none() {
+                eprintln!("Ignoring bad {} entry: '{}'", name, entry);
+            }
+            parsed
+        })
+        .collect()
+}
+
+/* Workspace names look like DNS labels, so any of them can also be used as a subdomain. */
+pub fn is_valid(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant.len() <= 63
+        && !tenant.starts_with('-')
+        && !tenant.ends_with('-')
+        && tenant.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/* The error sent back when a request names a workspace it can't use. */
+pub fn rejection(status: StatusCode, message: String) -> Response {
+    let error_response = serde_json::json!({
+        "status": "fail",
+        "message": message,
+    });
+    (status, Json(error_response)).into_response()
+}
+
+/* The workspace named by a request that isn't logged in yet (registering and logging in). Logged-in handlers use 'CurrentUser::tenant' instead. */
+#[derive(Debug, Clone)]
+pub struct Tenant(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Tenant
+where
+    Arc<TenantConfig>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<TenantConfig>::from_ref(state);
+        match config.requested(parts) {
+            Ok(tenant) => Ok(Tenant(tenant.unwrap_or_else(|| DEFAULT_TENANT.to_string()))),
+            Err(message) => Err(rejection(StatusCode::BAD_REQUEST, message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::testing::{app, create_todo, login, send};
+
+    #[tokio::test]
+    async fn another_workspace_gets_a_404() {
+        let (router, _) = app();
+        let alice = login(&router, "team-a", "alice").await;
+        let mallory = login(&router, "team-b", "mallory").await;
+        let mallory_auth = [("authorization", mallory.as_str())];
+        let todo = create_todo(&router, &alice, json!({ "title": "Secret", "content": "a" })).await;
+        let uri = format!("/api/todos/{}", todo["id"].as_str().unwrap());
+
+        /* Knowing the ID doesn't help: reads and changes answer as if it didn't exist. */
+        for (method, body) in [
+            (Method::GET, Value::Null),
+            (Method::PATCH, json!({ "title": "Mine now" })),
+            (Method::DELETE, Value::Null),
+        ] {
+            let (status, _) = send(&router, method.clone(), &uri, &mallory_auth, body).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", method);
+        }
+        let v2_uri = format!("/api/v2/todos/{}", todo["id"].as_str().unwrap());
+        let (status, _) = send(&router, Method::GET, &v2_uri, &mallory_auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        /* Users of another workspace can't be shared with either. */
+        let share = json!({ "username": "mallory", "role": "viewer" });
+        let (status, _) = send(&router, Method::POST, &format!("{}/share", uri), &[("authorization", alice.as_str())], share).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        /* Titles only have to be unique within a workspace, and Alice's todo is untouched. */
+        create_todo(&router, &mallory, json!({ "title": "Secret", "content": "b" })).await;
+        let (status, body) = send(&router, Method::GET, &uri, &[("authorization", alice.as_str())], Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["todo"]["title"], "Secret");
+        assert!(body["data"]["todo"]["deletedAt"].is_null());
+    }
+}
@@ -0,0 +1,59 @@
+/* Helpers for the tests: they send requests through the real router, the way a client would. */
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{model::AppState, route::create_router};
+
+/* A router over a fresh test state, and the state itself so tests can look inside it. */
+pub fn app() -> (Router, AppState) {
+    app_with(AppState::for_tests())
+}
+
+/* The same, over a state the test has adjusted. */
+pub fn app_with(state: AppState) -> (Router, AppState) {
+    (create_router(state.clone()), state)
+}
+
+/* Sends one request and returns the status and the JSON body (Null when there is none). */
+pub async fn send(router: &Router, method: Method, uri: &str, headers: &[(&str, &str)], body: Value) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = if body.is_null() {
+        request.body(Body::empty()).unwrap()
+    } else {
+        request
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+/* Registers a user in a workspace, logs them in, and returns their "Bearer <token>" header value. */
+pub async fn login(router: &Router, tenant: &str, username: &str) -> String {
+    let credentials = serde_json::json!({ "username": username, "password": "password123" });
+    let headers = [("x-tenant-id", tenant)];
+    let (status, _) = send(router, Method::POST, "/api/auth/register", &headers, credentials.clone()).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, body) = send(router, Method::POST, "/api/auth/login", &headers, credentials).await;
+    assert_eq!(status, StatusCode::OK);
+    format!("Bearer {}", body["token"].as_str().unwrap())
+}
+
+/* Creates a todo and returns it as JSON. */
+pub async fn create_todo(router: &Router, auth: &str, body: Value) -> Value {
+    let (status, response) = send(router, Method::POST, "/api/todos", &[("authorization", auth)], body).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", response);
+    response["data"]["todo"].clone()
+}
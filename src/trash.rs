@@ -0,0 +1,160 @@
+/* This file handles the trash. Deleting a todo only gives it a 'deletedAt' time, so its owner can restore it or purge it for good; a background task (started in main.rs) purges todos that have been in the trash too long. */
+
+use std::time::Duration;
+
+use chrono::prelude::*;
+
+use crate::{
+    audit::{AuditAction, Auditor},
+    auth::CurrentUser,
+    dependency,
+    model::{AppState, Todo},
+};
+
+/* The actor recorded in the audit log for todos the background task purges. */
+pub const SYSTEM_ACTOR: &str = "system";
+
+/* Settings for the purge task, read from environment variables in 'from_env'. */
+pub struct TrashConfig {
+    pub retention: chrono::Duration,
+    pub purge_interval: Duration,
+}
+
+impl TrashConfig {
+    /* Reads TRASH_RETENTION_DAYS (default 30) and TRASH_PURGE_INTERVAL_SECONDS (default 3600). */
+    pub fn from_env() -> Self {
+        let env_number = |name: &str, default: i64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+
+        TrashConfig {
+            retention: chrono::Duration::days(env_number("TRASH_RETENTION_DAYS", 30)),
+            purge_interval: Duration::from_secs(env_number("TRASH_PURGE_INTERVAL_SECONDS", 3600) as u64),
+        }
+    }
+}
+
+/* Removes a todo for good and drops every dependency on it, recording both in the audit log. Returns the removed todo, or None if it isn't there. */
+pub async fn purge(todos: &mut Vec<Todo>, id: &str, user: &CurrentUser, audit: &Auditor) -> Option<Todo> {
+    let removed = todos.iter().find(|todo| todo.id.as_deref() == Some(id))?.clone();
+    audit.record(todos, user, AuditAction::Purge, Some(&removed), None).await;
+    for (before, after) in dependency::remove_references(todos, id) {
+        audit.record(todos, user, AuditAction::Edit, Some(&before), Some(&after)).await;
+    }
+    Some(removed)
+}
+
+/* Starts the purge task on its own tokio task and returns straight away. */
+pub fn spawn_trash_purger(state: AppState, config: TrashConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.purge_interval);
+
+        loop {
+            interval.tick().await;
+            purge_expired(&state, config.retention).await;
+        }
+    });
+}
+
+/* Purges every trashed todo, in every workspace, that was deleted longer ago than 'retention'. Each purge is written to the audit log with "system" as the actor. */
+async fn purge_expired(state: &AppState, retention: chrono::Duration) {
+    let cutoff = Utc::now() - retention;
+    let audit = Auditor::background(state);
+    let mut store = state.db.lock().await;
+
+    for (tenant, todos) in store.iter_mut() {
+        let expired: Vec<String> = todos
+            .iter()
+            .filter(|todo| todo.deletedAt.is_some_and(|deleted_at| deleted_at < cutoff))
+            .filter_map(|todo| todo.id.clone())
+            .collect();
+
+        let system = CurrentUser {
+            id: SYSTEM_ACTOR.to_string(),
+            api_key_id: None,
+            tenant: tenant.clone(),
+        };
+        for id in expired {
+            purge(todos, &id, &system, &audit).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::{
+        model::AppState,
+        tenant::TenantConfig,
+        testing::{app_with, create_todo, login, send},
+    };
+
+    #[tokio::test]
+    async fn trash_restore_and_purge() {
+        let (router, _) = app_with(AppState::for_tests());
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+        let todo = create_todo(&router, &alice, json!({ "title": "Old", "content": "a" })).await;
+        let id = todo["id"].as_str().unwrap();
+        let todo_uri = format!("/api/todos/{}", id);
+        let listed = |body: &Value| body["todos"].as_array().unwrap().iter().any(|todo| todo["id"] == id);
+
+        /* Deleting moves it from the list to the trash. */
+        let (status, _) = send(&router, Method::DELETE, &todo_uri, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, body) = send(&router, Method::GET, "/api/todos", &auth, Value::Null).await;
+        assert!(!listed(&body));
+        let (_, body) = send(&router, Method::GET, "/api/trash", &auth, Value::Null).await;
+        assert!(listed(&body));
+
+        /* Restoring brings it back. */
+        let restore = format!("/api/trash/{}/restore", id);
+        let (status, body) = send(&router, Method::POST, &restore, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["todo"]["id"], id);
+        let (_, body) = send(&router, Method::GET, "/api/todos", &auth, Value::Null).await;
+        assert!(listed(&body));
+
+        /* Purging only works on a todo in the trash, and then it is gone for good. */
+        let purge = format!("/api/trash/{}", id);
+        let (status, _) = send(&router, Method::DELETE, &purge, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&router, Method::DELETE, &todo_uri, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&router, Method::DELETE, &purge, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, body) = send(&router, Method::GET, "/api/trash", &auth, Value::Null).await;
+        assert!(!listed(&body));
+        let (status, _) = send(&router, Method::POST, &restore, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&router, Method::GET, &todo_uri, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn restoring_respects_the_quota() {
+        let mut state = AppState::for_tests();
+        state.tenants = Arc::new(TenantConfig::limited(1));
+        let (router, _) = app_with(state);
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+
+        let first = create_todo(&router, &alice, json!({ "title": "First", "content": "a" })).await;
+        let id = first["id"].as_str().unwrap();
+        let (status, _) = send(&router, Method::DELETE, &format!("/api/todos/{}", id), &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        create_todo(&router, &alice, json!({ "title": "Second", "content": "b" })).await;
+
+        let uri = format!("/api/trash/{}/restore", id);
+        let (status, _) = send(&router, Method::POST, &uri, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,183 @@
+/* This file holds the handlers for the trash: listing, restoring and purging deleted todos (see trash.rs). */
+
+/* Imports the parts of the axum webframework the handlers below use (see handler.rs for what each one does). */
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+/* Imports Uuid, used for the todo IDs in the paths, and Arc for shared settings. */
+use uuid::Uuid;
+use std::sync::Arc;
+
+/* Imports structures and functions from our local crate (IE our project). */
+use crate::{
+    handler::{check_quota, title_conflict},
+    audit::{AuditAction, Auditor},
+    auth::CurrentUser,
+    dependency,
+    model::{title_taken, Todo, DB},
+    response::{GenericResponse, SingleTodoResponse, TodoData, TodoListResponse},
+    tenant::TenantConfig,
+    trash,
+};
+
+/* Lists the caller's todos that are in the trash, most recently deleted first. */
+#[utoipa::path(
+    get,
+    path = "/api/trash",
+    tag = "trash",
+    summary = "Lists the caller's deleted todos",
+    responses(
+        (status = 200, description = "The todos in the trash", body = TodoListResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn trash_list_handler(State(db): State<DB>, user: CurrentUser) -> impl IntoResponse {
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    let mut todos: Vec<Todo> = vec
+        .iter()
+        .filter(|todo| user.owns(todo) && todo.deletedAt.is_some())
+        .map(|todo| dependency::annotate(vec, todo))
+        .collect();
+    todos.sort_by_key(|todo| std::cmp::Reverse(todo.deletedAt));
+
+    let json_response = TodoListResponse {
+        status: "success".to_string(),
+        results: todos.len(),
+        todos,
+    };
+    Json(json_response)
+}
+
+/* Finds one of the caller's trashed todos. Other people's todos and todos that aren't in the trash give a 404. */
+fn find_in_trash(vec: &[Todo], id: &str, user: &CurrentUser) -> Result<usize, (StatusCode, Json<serde_json::Value>)> {
+    vec.iter()
+        .position(|todo| todo.id.as_deref() == Some(id) && user.owns(todo) && todo.deletedAt.is_some())
+        .ok_or_else(|| {
+            let error_response = serde_json::json!({
+                "status": "fail",
+                "message": format!("Todo with ID: {} not found in the trash", id)
+            });
+            (StatusCode::NOT_FOUND, Json(error_response))
+        })
+}
+
+/* Takes a todo back out of the trash. Fails with 409 if another todo has taken its title in the meantime. */
+#[utoipa::path(
+    post,
+    path = "/api/trash/{id}/restore",
+    tag = "trash",
+    summary = "Takes a todo back out of the trash",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    responses(
+        (status = 200, description = "The restored todo", body = SingleTodoResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it, or the workspace is full", body = GenericResponse),
+        (status = 404, description = "No todo with this ID in the trash", body = GenericResponse),
+        (status = 409, description = "Another todo has taken its title", body = GenericResponse),
+    ),
+)]
+pub async fn trash_restore_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    State(tenants): State<Arc<TenantConfig>>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+    let pos = find_in_trash(vec, &id, &user)?;
+    check_quota(vec, &tenants, &user)?;
+        // A restored todo counts towards the quota again.
+
+    if title_taken(vec, &vec[pos].title, Some(&id), vec[pos].seriesId.as_deref()) {
+        return Err(title_conflict(&vec[pos].title));
+    }
+
+    let before = vec[pos].clone();
+    let todo = Todo {
+        deletedAt: None,
+        updatedAt: Some(chrono::Utc::now()),
+        ..before.clone()
+    };
+    audit.record(vec, &user, AuditAction::Restore, Some(&before), Some(&todo)).await;
+
+    let json_response = SingleTodoResponse {
+        status: "success".to_string(),
+        data: TodoData {
+            todo: dependency::annotate(vec, &todo),
+            next: None,
+        },
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Permanently removes one todo from the trash. This can't be undone. */
+#[utoipa::path(
+    delete,
+    path = "/api/trash/{id}",
+    tag = "trash",
+    summary = "Removes one deleted todo for good",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    responses(
+        (status = 204, description = "The todo is gone"),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID in the trash", body = GenericResponse),
+    ),
+)]
+pub async fn trash_purge_handler(
+    Path(id): Path<Uuid>,
+    State(db): State<DB>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+    find_in_trash(vec, &id, &user)?;
+
+    trash::purge(vec, &id, &user, &audit).await;
+    Ok((StatusCode::NO_CONTENT, Json("")))
+}
+
+/* Permanently removes everything in the caller's trash. */
+#[utoipa::path(
+    delete,
+    path = "/api/trash",
+    tag = "trash",
+    summary = "Empties the trash for good",
+    responses(
+        (status = 200, description = "How many todos were removed", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn trash_empty_handler(
+    State(db): State<DB>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> impl IntoResponse {
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+
+    let trashed: Vec<String> = vec
+        .iter()
+        .filter(|todo| user.owns(todo) && todo.deletedAt.is_some())
+        .filter_map(|todo| todo.id.clone())
+        .collect();
+    for id in &trashed {
+        trash::purge(vec, id, &user, &audit).await;
+    }
+
+    let json_response = GenericResponse {
+        status: "success".to_string(),
+        message: format!("Purged {} todos from the trash", trashed.len()),
+    };
+    Json(json_response)
+}
@@ -0,0 +1,321 @@
+/* This file keeps an undo (and redo) stack for every user. Changes made by the same request are undone together, and an undo is refused with a 409 if any of its todos has changed since. */
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use chrono::prelude::*;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::{
+    audit::AuditAction,
+    dependency,
+    event_store,
+    model::{title_taken, Todo},
+};
+
+/* One todo going from 'before' to 'after'. None means the todo didn't exist (before a create, after a purge). */
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub before: Option<Todo>,
+    pub after: Option<Todo>,
+}
+
+/* Everything one request changed. */
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub request_id: String,
+    pub action: AuditAction,
+    pub at: DateTime<Utc>,
+    pub changes: Vec<Change>,
+}
+
+/* A short description of an operation on the stack, sent back to the client. */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct OperationSummary {
+    pub action: AuditAction,
+    pub at: DateTime<Utc>,
+    pub todoIds: Vec<String>,
+}
+
+impl Operation {
+    pub fn summary(&self) -> OperationSummary {
+        OperationSummary {
+            action: self.action,
+            at: self.at,
+            todoIds: self
+                .changes
+                .iter()
+                .filter_map(|change| change.after.as_ref().or(change.before.as_ref())?.id.clone())
+                .collect(),
+        }
+    }
+}
+
+/* One user's stacks. The newest operation is at the back. */
+#[derive(Debug, Default)]
+struct History {
+    undo: VecDeque<Operation>,
+    redo: Vec<Operation>,
+}
+
+/* Every user's undo and redo stacks, keyed by (workspace, user ID). */
+pub struct UndoStore {
+    depth: usize,
+    users: Mutex<HashMap<(String, String), History>>,
+}
+
+/* Which stack to take an operation from. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Undo,
+    Redo,
+}
+
+impl UndoStore {
+    /* Reads UNDO_DEPTH. */
+    pub fn from_env() -> Self {
+        UndoStore {
+            depth: std::env::var("UNDO_DEPTH")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|depth| *depth > 0)
+                .unwrap_or(20),
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /* Adds a change a user just made. It joins the newest operation if it came from the same request, otherwise it starts a new one. Either way the redo stack is cleared. */
+    pub async fn push(&self, tenant: &str, user_id: &str, request_id: &str, action: AuditAction, change: Change) {
+        let mut users = self.users.lock().await;
+        let history = users.entry((tenant.to_string(), user_id.to_string())).or_default();
+        history.redo.clear();
+
+        if let Some(last) = history.undo.back_mut().filter(|last| last.request_id == request_id) {
+            last.changes.push(change);
+            return;
+        }
+
+        history.undo.push_back(Operation {
+            request_id: request_id.to_string(),
+            action,
+            at: Utc::now(),
+            changes: vec![change],
+        });
+        if history.undo.len() > self.depth {
+            history.undo.pop_front();
+        }
+    }
+
+    /* Takes the newest operation off the undo or redo stack. */
+    pub async fn pop(&self, tenant: &str, user_id: &str, direction: Direction) -> Option<Operation> {
+        let mut users = self.users.lock().await;
+        let history = users.get_mut(&(tenant.to_string(), user_id.to_string()))?;
+        match direction {
+            Direction::Undo => history.undo.pop_back(),
+            Direction::Redo => history.redo.pop(),
+        }
+    }
+
+    /* Puts an operation on the undo or redo stack without clearing anything. Used to move an operation across after it has been undone or redone. */
+    pub async fn put(&self, tenant: &str, user_id: &str, direction: Direction, operation: Operation) {
+        let mut users = self.users.lock().await;
+        let history = users.entry((tenant.to_string(), user_id.to_string())).or_default();
+        match direction {
+            Direction::Undo => {
+                history.undo.push_back(operation);
+                if history.undo.len() > self.depth {
+                    history.undo.pop_front();
+                }
+            }
+            Direction::Redo => {
+                history.redo.push(operation);
+                if history.redo.len() > self.depth {
+                    history.redo.remove(0);
+                }
+            }
+        }
+    }
+
+    /* After an undo or redo, the todos it put back carry a fresh 'updatedAt', so older operations on the stacks that expect the previous version would be refused as stale. This swaps those snapshots for the restored versions, so the user can keep undoing step by step. */
+    pub async fn refresh(&self, tenant: &str, user_id: &str, reverted: &Operation, inverse: &[Change]) {
+        let replacements: Vec<(&Todo, &Todo)> = inverse
+            .iter()
+            .filter_map(|change| change.after.as_ref())
+            .filter_map(|restored| {
+                let original = reverted
+                    .changes
+                    .iter()
+                    .filter_map(|change| change.before.as_ref())
+                    .find(|before| before.id == restored.id)?;
+                Some((original, restored))
+            })
+            .collect();
+
+        let mut users = self.users.lock().await;
+        let Some(history) = users.get_mut(&(tenant.to_string(), user_id.to_string())) else {
+            return;
+        };
+        let snapshots = history
+            .undo
+            .iter_mut()
+            .chain(history.redo.iter_mut())
+            .flat_map(|operation| operation.changes.iter_mut())
+            .flat_map(|change| [&mut change.before, &mut change.after])
+            .flatten();
+        for snapshot in snapshots {
+            if let Some((_, restored)) = replacements
+                .iter()
+                .find(|(original, _)| original.id == snapshot.id && unchanged(Some(snapshot), Some(original)))
+            {
+                *snapshot = (*restored).clone();
+            }
+        }
+    }
+
+    /* The user's undo and redo stacks, newest first, for showing what an undo would do. */
+    pub async fn peek(&self, tenant: &str, user_id: &str) -> (Vec<OperationSummary>, Vec<OperationSummary>) {
+        let users = self.users.lock().await;
+        match users.get(&(tenant.to_string(), user_id.to_string())) {
+            Some(history) => (
+                history.undo.iter().rev().map(Operation::summary).collect(),
+                history.redo.iter().rev().map(Operation::summary).collect(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+}
+
+/* True if the todo is still exactly as the operation left it. Every change made through the API sets 'updatedAt' (or 'deletedAt' for the trash), so comparing those is enough to notice later edits. */
+fn unchanged(current: Option<&Todo>, expected: Option<&Todo>) -> bool {
+    match (current, expected) {
+        (None, None) => true,
+        (Some(current), Some(expected)) => {
+            current.updatedAt == expected.updatedAt && current.deletedAt == expected.deletedAt
+        }
+        _ => false,
+    }
+}
+
+/* Works out how to reverse an operation on a workspace's todos: each todo goes back to its 'before' state, newest change first. Nothing is returned unless every todo is still as the operation left it; otherwise the error says which one changed. The result is the list of changes to record (see 'Auditor::record_replay'), which also undo this undo (that is what redo uses). */
+pub fn revert(todos: &[Todo], operation: &Operation) -> Result<Vec<Change>, String> {
+    let find = |todos: &[Todo], id: &str| todos.iter().position(|todo| todo.id.as_deref() == Some(id));
+    let id_of = |change: &Change| change.after.as_ref().or(change.before.as_ref()).and_then(|todo| todo.id.clone());
+
+    /* Only a todo's last change in the operation says how it was left (a move can follow a rebalance of the same list, for example). */
+    let mut checked = HashSet::new();
+    for change in operation.changes.iter().rev() {
+        let Some(id) = id_of(change) else {
+            continue;
+        };
+        let current = find(todos, &id).map(|pos| &todos[pos]);
+        if checked.insert(id.clone()) && !unchanged(current, change.after.as_ref()) {
+            return Err(format!("Todo with ID: {} has been changed since, so this can't be undone", id));
+        }
+
+        /* Putting a todo back to (or re-creating it with) an old title mustn't clash with another live todo. */
+        if let Some(before) = change.before.as_ref().filter(|before| before.deletedAt.is_none()) {
+            if title_taken(todos, &before.title, before.id.as_deref(), before.seriesId.as_deref()) {
+                return Err(format!("Todo with title: '{}' already exists", before.title));
+            }
+        }
+    }
+
+    /* Plays the reversal out on a copy, so removing a todo can also drop the dependencies on it. */
+    let mut scratch = todos.to_vec();
+    let now = Utc::now();
+    let mut inverse = Vec::new();
+    for change in operation.changes.iter().rev() {
+        let Some(id) = id_of(change) else {
+            continue;
+        };
+        let restored = change.before.clone().map(|before| Todo {
+            updatedAt: Some(now),
+            ..before
+        });
+            // A fresh 'updatedAt' marks the undo itself as a change, so a stale redo is caught the same way (see 'UndoStore::refresh').
+        let current = find(&scratch, &id).map(|pos| scratch[pos].clone());
+        event_store::apply(&mut scratch, &id, restored.as_ref());
+        inverse.push(Change {
+            before: current,
+            after: restored.clone(),
+        });
+
+        if restored.is_none() {
+            for (before, after) in dependency::remove_references(&scratch, &id) {
+                let dependent = after.id.clone().unwrap_or_default();
+                event_store::apply(&mut scratch, &dependent, Some(&after));
+                inverse.push(Change {
+                    before: Some(before),
+                    after: Some(after),
+                });
+            }
+        }
+    }
+    Ok(inverse)
+}
+
+/* The audit action that best describes a change, for changes made by undo/redo. */
+pub fn action_for(change: &Change) -> AuditAction {
+    match (&change.before, &change.after) {
+        (None, _) => AuditAction::Create,
+        (_, None) => AuditAction::Purge,
+        (Some(before), Some(after)) => match (before.deletedAt.is_some(), after.deletedAt.is_some()) {
+            (false, true) => AuditAction::Delete,
+            (true, false) => AuditAction::Restore,
+            _ => AuditAction::Edit,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::{
+        model::AppState,
+        testing::{app_with, create_todo, login, send},
+    };
+
+    #[tokio::test]
+    async fn undo_redo_and_a_stale_undo() {
+        let (router, _) = app_with(AppState::for_tests());
+        let alice = login(&router, "default", "alice").await;
+        let bob = login(&router, "default", "bob").await;
+        let auth = [("authorization", alice.as_str())];
+        let todo = create_todo(&router, &alice, json!({ "title": "Old", "content": "a" })).await;
+        let todo_uri = format!("/api/todos/{}", todo["id"].as_str().unwrap());
+        let title = |body: &Value| body["data"]["todo"]["title"].clone();
+
+        /* Nothing has been undone yet, so there is nothing to redo. */
+        let (status, _) = send(&router, Method::POST, "/api/redo", &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        /* Undo puts the old title back, and redo the new one. */
+        let (status, _) = send(&router, Method::PATCH, &todo_uri, &auth, json!({ "title": "New" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&router, Method::POST, "/api/undo", &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["action"], "edit");
+        let (_, body) = send(&router, Method::GET, &todo_uri, &auth, Value::Null).await;
+        assert_eq!(title(&body), "Old");
+        let (status, body) = send(&router, Method::POST, "/api/redo", &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let (_, body) = send(&router, Method::GET, &todo_uri, &auth, Value::Null).await;
+        assert_eq!(title(&body), "New");
+
+        /* Once someone else has changed the todo, undoing the share is refused and the todo is left alone. */
+        let share_uri = format!("{}/share", todo_uri);
+        let (status, _) = send(&router, Method::POST, &share_uri, &auth, json!({ "username": "bob", "role": "editor" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let bob_auth = [("authorization", bob.as_str())];
+        let (status, _) = send(&router, Method::PATCH, &todo_uri, &bob_auth, json!({ "title": "Bob's" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&router, Method::POST, "/api/undo", &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+        assert_eq!(body["status"], "fail");
+        let (_, body) = send(&router, Method::GET, &todo_uri, &auth, Value::Null).await;
+        assert_eq!(title(&body), "Bob's");
+    }
+}
@@ -0,0 +1,155 @@
+/* This file holds the handlers for undo, redo and the undo history (see undo.rs). */
+
+/* Imports the parts of the axum webframework the handlers below use (see handler.rs for what each one does). */
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+/* Imports Arc, used to share the undo stacks. */
+use std::sync::Arc;
+
+/* Imports structures and functions from our local crate (IE our project). */
+use crate::{
+    audit::Auditor,
+    auth::CurrentUser,
+    dependency,
+    model::{Todo, DB},
+    response::{GenericResponse, UndoResponse, UndoStackResponse},
+    undo::{self, Direction, Operation, UndoStore},
+};
+
+/* Undoes the caller's most recent operation (see undo.rs). */
+#[utoipa::path(
+    post,
+    path = "/api/undo",
+    tag = "undo",
+    summary = "Undoes the caller's most recent operation",
+    responses(
+        (status = 200, description = "The operation that was undone", body = UndoResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 409, description = "Nothing to undo, or the todos changed since", body = GenericResponse),
+    ),
+)]
+pub async fn undo_handler(
+    State(db): State<DB>,
+    State(undo): State<Arc<UndoStore>>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    step_history(db, undo, user, audit, Direction::Undo).await
+}
+
+/* Redoes the operation the caller most recently undid. */
+#[utoipa::path(
+    post,
+    path = "/api/redo",
+    tag = "undo",
+    summary = "Redoes the most recently undone operation",
+    responses(
+        (status = 200, description = "The operation that was redone", body = UndoResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 409, description = "Nothing to redo, or the todos changed since", body = GenericResponse),
+    ),
+)]
+pub async fn redo_handler(
+    State(db): State<DB>,
+    State(undo): State<Arc<UndoStore>>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    step_history(db, undo, user, audit, Direction::Redo).await
+}
+
+/* Shared by undo and redo: takes the newest operation off one stack, reverses it, and puts the reversal on the other stack. An operation that can no longer be reversed safely is dropped, so the next undo moves on to the one before it. */
+async fn step_history(
+    db: DB,
+    undo: Arc<UndoStore>,
+    user: CurrentUser,
+    audit: Auditor,
+    direction: Direction,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let mut store = db.lock().await;
+    let vec = store.entry(user.tenant.clone()).or_default();
+        // The todo list stays locked until the operation has moved stacks, so nothing can change in between.
+
+    let Some(operation) = undo.pop(&user.tenant, &user.id, direction).await else {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": match direction {
+                Direction::Undo => "Nothing to undo",
+                Direction::Redo => "Nothing to redo",
+            },
+        });
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    };
+
+    let inverse = match undo::revert(vec, &operation) {
+        Ok(inverse) => inverse,
+        Err(message) => {
+            let error_response = serde_json::json!({
+                "status": "fail",
+                "message": message,
+            });
+            return Err((StatusCode::CONFLICT, Json(error_response)));
+        }
+    };
+    for change in &inverse {
+        audit.record_replay(vec, &user, change).await;
+    }
+
+    let todos: Vec<Todo> = inverse
+        .iter()
+        .filter_map(|change| change.after.as_ref())
+        .map(|todo| dependency::annotate(vec, todo))
+        .collect();
+    undo.refresh(&user.tenant, &user.id, &operation, &inverse).await;
+    let action = operation.action;
+    let opposite = match direction {
+        Direction::Undo => Direction::Redo,
+        Direction::Redo => Direction::Undo,
+    };
+    undo.put(
+        &user.tenant,
+        &user.id,
+        opposite,
+        Operation {
+            at: chrono::Utc::now(),
+            changes: inverse,
+            ..operation
+        },
+    )
+    .await;
+
+    let json_response = UndoResponse {
+        status: "success".to_string(),
+        action,
+        results: todos.len(),
+        todos,
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Shows what the caller can undo and redo, newest first. */
+#[utoipa::path(
+    get,
+    path = "/api/undo",
+    tag = "undo",
+    summary = "Shows the caller's undo and redo stacks",
+    responses(
+        (status = 200, description = "The operations that can be undone and redone, newest first", body = UndoStackResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn undo_history_handler(State(undo): State<Arc<UndoStore>>, user: CurrentUser) -> impl IntoResponse {
+    let (undo, redo) = undo.peek(&user.tenant, &user.id).await;
+
+    let json_response = UndoStackResponse {
+        status: "success".to_string(),
+        undo,
+        redo,
+    };
+    Json(json_response)
+}
@@ -0,0 +1,289 @@
+/* This file holds the shapes of version 2 of the todo API (see version.rs): the same todo as version 1, with snake_case names and real types instead of Options. */
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    model::{CreateTodoSchema, GetTodoQuery, QueryOptions, Reminder, Share, Todo, UpdateTodoSchema},
+    policy::Role,
+    response::{SingleTodoResponse, TodoListResponse},
+};
+
+/* A todo, as version 2 sends it. */
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TodoV2 {
+    pub id: Uuid,
+    pub title: String,
+    pub content: String,
+    pub completed: bool,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub due_date: Option<DateTime<Utc>>,
+    pub recurrence: Option<String>,
+    pub series_id: Option<Uuid>,
+    pub occurrence: Option<u32>,
+    pub list: Option<String>,
+    pub tags: Vec<String>,
+    pub position: String,
+    pub owner_id: String,
+        // Not a UUID for users who log in with a JWT; it's their 'sub' claim.
+    pub blocked_by: Vec<Uuid>,
+    pub blocks: Vec<Uuid>,
+    pub blocked: bool,
+    pub reminders: Vec<ReminderV2>,
+    pub shared_with: Vec<ShareV2>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReminderV2 {
+    pub id: Uuid,
+    pub remind_at: Option<DateTime<Utc>>,
+    pub offset_minutes: Option<i64>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ShareV2 {
+    pub user_id: String,
+    pub role: Role,
+}
+
+/* Reads one of the IDs the server wrote itself. They are always UUIDs; the nil UUID only shows up for data that was edited by hand. */
+fn uuid(id: &str) -> Uuid {
+    Uuid::parse_str(id).unwrap_or_default()
+}
+
+impl From<Todo> for TodoV2 {
+    fn from(todo: Todo) -> Self {
+        TodoV2 {
+            id: uuid(todo.id.as_deref().unwrap_or_default()),
+            title: todo.title,
+            content: todo.content,
+            completed: todo.completed.unwrap_or(false),
+            status: todo.status.unwrap_or_default(),
+            created_at: todo.createdAt.unwrap_or_default(),
+            updated_at: todo.updatedAt.or(todo.createdAt).unwrap_or_default(),
+            due_date: todo.dueDate,
+            recurrence: todo.recurrence,
+            series_id: todo.seriesId.as_deref().map(uuid),
+            occurrence: todo.occurrence,
+            list: todo.list,
+            tags: todo.tags,
+            position: todo.position.unwrap_or_default(),
+            owner_id: todo.ownerId.unwrap_or_default(),
+            blocked_by: todo.blockedBy.iter().map(|id| uuid(id)).collect(),
+            blocks: todo.blocks.iter().map(|id| uuid(id)).collect(),
+            blocked: todo.blocked,
+            reminders: todo.reminders.into_iter().map(ReminderV2::from).collect(),
+            shared_with: todo.sharedWith.into_iter().map(ShareV2::from).collect(),
+            deleted_at: todo.deletedAt,
+        }
+    }
+}
+
+impl From<Reminder> for ReminderV2 {
+    fn from(reminder: Reminder) -> Self {
+        ReminderV2 {
+            id: uuid(&reminder.id),
+            remind_at: reminder.remindAt,
+            offset_minutes: reminder.offsetMinutes,
+            sent_at: reminder.sentAt,
+        }
+    }
+}
+
+impl From<Share> for ShareV2 {
+    fn from(share: Share) -> Self {
+        ShareV2 {
+            user_id: share.userId,
+            role: share.role,
+        }
+    }
+}
+
+/* Body for creating a todo. The same fields as version 1, in snake_case. */
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateTodoV2 {
+    pub title: String,
+    pub content: String,
+    pub status: Option<String>,
+    pub due_date: Option<DateTime<Utc>>,
+    pub recurrence: Option<String>,
+    pub list: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+impl From<CreateTodoV2> for CreateTodoSchema {
+    fn from(body: CreateTodoV2) -> Self {
+        CreateTodoSchema {
+            title: body.title,
+            content: body.content,
+            status: body.status,
+            dueDate: body.due_date,
+            recurrence: body.recurrence,
+            list: body.list,
+            tags: body.tags,
+        }
+    }
+}
+
+/* Body for changing a todo. Only the fields that are sent change. */
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateTodoV2 {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub completed: Option<bool>,
+    pub status: Option<String>,
+    pub due_date: Option<DateTime<Utc>>,
+    pub recurrence: Option<String>,
+        // An empty string stops the todo from recurring.
+    pub list: Option<String>,
+        // An empty string moves the todo back to the default list.
+    pub tags: Option<Vec<String>>,
+        // Replaces the todo's tags. An empty array removes them all.
+}
+
+impl From<UpdateTodoV2> for UpdateTodoSchema {
+    fn from(body: UpdateTodoV2) -> Self {
+        UpdateTodoSchema {
+            title: body.title,
+            content: body.content,
+            completed: body.completed,
+            status: body.status,
+            dueDate: body.due_date,
+            recurrence: body.recurrence,
+            list: body.list,
+            tags: body.tags,
+        }
+    }
+}
+
+/* Query options for listing todos. */
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TodoListQueryV2 {
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+    pub actionable: Option<bool>,
+    pub list: Option<String>,
+    pub include_deleted: Option<bool>,
+    pub as_of: Option<DateTime<Utc>>,
+}
+
+impl From<TodoListQueryV2> for QueryOptions {
+    fn from(query: TodoListQueryV2) -> Self {
+        QueryOptions {
+            page: query.page,
+            limit: query.limit,
+            actionable: query.actionable,
+            list: query.list,
+            includeDeleted: query.include_deleted,
+            as_of: query.as_of,
+        }
+    }
+}
+
+/* Query options for fetching one todo. */
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetTodoQueryV2 {
+    pub include_deleted: Option<bool>,
+    pub as_of: Option<DateTime<Utc>>,
+}
+
+impl From<GetTodoQueryV2> for GetTodoQuery {
+    fn from(query: GetTodoQueryV2) -> Self {
+        GetTodoQuery {
+            includeDeleted: query.include_deleted,
+            as_of: query.as_of,
+        }
+    }
+}
+
+/* The responses, with the same envelope as version 1 around the new todo shape. */
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TodoDataV2 {
+    pub todo: TodoV2,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<TodoV2>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SingleTodoResponseV2 {
+    pub status: String,
+    pub data: TodoDataV2,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TodoListResponseV2 {
+    pub status: String,
+    pub results: usize,
+    pub todos: Vec<TodoV2>,
+}
+
+impl From<SingleTodoResponse> for SingleTodoResponseV2 {
+    fn from(response: SingleTodoResponse) -> Self {
+        SingleTodoResponseV2 {
+            status: response.status,
+            data: TodoDataV2 {
+                todo: response.data.todo.into(),
+                next: response.data.next.map(TodoV2::from),
+            },
+        }
+    }
+}
+
+impl From<TodoListResponse> for TodoListResponseV2 {
+    fn from(response: TodoListResponse) -> Self {
+        TodoListResponseV2 {
+            status: response.status,
+            results: response.results,
+            todos: response.todos.into_iter().map(TodoV2::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::{
+        testing::{app, login, send},
+        version::V2_MEDIA_TYPE,
+    };
+
+    #[tokio::test]
+    async fn v2_answers_by_url_or_accept_header() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+        let body = json!({ "title": "Plan", "content": "a", "due_date": "2030-01-01T09:00:00Z" });
+        let (status, body) = send(&router, Method::POST, "/api/v2/todos", &auth, body).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        assert_eq!(body["data"]["todo"]["due_date"], "2030-01-01T09:00:00Z");
+        let id = body["data"]["todo"]["id"].as_str().unwrap().to_string();
+
+        /* The old URL answers in version 1 unless the client asks for version 2. */
+        let uri = format!("/api/todos/{}", id);
+        let (_, body) = send(&router, Method::GET, &uri, &auth, Value::Null).await;
+        assert_eq!(body["data"]["todo"]["dueDate"], "2030-01-01T09:00:00Z");
+        assert!(body["data"]["todo"].get("due_date").is_none());
+        let v2 = [("authorization", alice.as_str()), ("accept", V2_MEDIA_TYPE)];
+        let (_, body) = send(&router, Method::GET, &uri, &v2, Value::Null).await;
+        assert_eq!(body["data"]["todo"]["due_date"], "2030-01-01T09:00:00Z");
+        assert!(body["data"]["todo"].get("dueDate").is_none());
+
+        /* Deleting answers 204 with no body at all. */
+        let v2_uri = format!("/api/v2/todos/{}", id);
+        let (status, body) = send(&router, Method::DELETE, &v2_uri, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(body, Value::Null);
+        let (status, _) = send(&router, Method::GET, &v2_uri, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,158 @@
+/* This file holds the handlers for version 2 of the todo routes ("/api/v2/todos", see v2.rs). */
+
+/* Imports the parts of the axum webframework the handlers below use (see handler.rs for what each one does). */
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+/* Imports Uuid, used for the todo IDs in the paths, and Arc for shared settings. */
+use uuid::Uuid;
+use std::sync::Arc;
+
+/* Imports the version 1 handlers these call, and the structures they use. */
+use crate::{
+    handler::{create_todo_handler, delete_todo_handler, edit_todo_handler, get_todo_handler, todos_list_handler},
+    audit::Auditor,
+    auth::CurrentUser,
+    event_store::EventStore,
+    model::{ListShareDB, DB},
+    response::GenericResponse,
+    tenant::TenantConfig,
+    v2::{CreateTodoV2, GetTodoQueryV2, SingleTodoResponseV2, TodoListQueryV2, TodoListResponseV2, UpdateTodoV2},
+    workflow::Workflow,
+};
+
+/* Version 2 of the todo routes (see v2.rs). Each one converts its input to the version 1 shape, calls the version 1 handler, and converts the answer back, so the two versions can't drift apart. */
+#[utoipa::path(
+    get,
+    path = "/api/v2/todos",
+    tag = "todos (v2)",
+    summary = "Lists the caller's todos in their own order",
+    params(TodoListQueryV2),
+    responses(
+        (status = 200, description = "One page of todos", body = TodoListResponseV2),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn v2_todos_list_handler(
+    opts: Option<Query<TodoListQueryV2>>,
+    db: State<DB>,
+    list_shares: State<ListShareDB>,
+    event_store: State<Arc<EventStore>>,
+    user: CurrentUser,
+) -> Result<Json<TodoListResponseV2>, (StatusCode, Json<serde_json::Value>)> {
+    let Query(opts) = opts.unwrap_or_default();
+    let Json(response) = todos_list_handler(Some(Query(opts.into())), db, list_shares, event_store, user).await?;
+    Ok(Json(response.into()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v2/todos",
+    tag = "todos (v2)",
+    summary = "Creates a todo",
+    request_body = CreateTodoV2,
+    responses(
+        (status = 201, description = "The new todo", body = SingleTodoResponseV2),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The workspace is full", body = GenericResponse),
+        (status = 409, description = "A todo with this title already exists", body = GenericResponse),
+    ),
+)]
+pub async fn v2_create_todo_handler(
+    db: State<DB>,
+    workflow: State<Arc<Workflow>>,
+    tenants: State<Arc<TenantConfig>>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(body): Json<CreateTodoV2>,
+) -> Result<(StatusCode, Json<SingleTodoResponseV2>), (StatusCode, Json<serde_json::Value>)> {
+    let (status, Json(response)) = create_todo_handler(db, workflow, tenants, user, audit, Json(body.into())).await?;
+    Ok((status, Json(response.into())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/todos/{id}",
+    tag = "todos (v2)",
+    summary = "Gets a todo",
+    params(("id" = Uuid, Path, description = "The todo's ID"), GetTodoQueryV2),
+    responses(
+        (status = 200, description = "The todo", body = SingleTodoResponseV2),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn v2_get_todo_handler(
+    id: Path<Uuid>,
+    opts: Option<Query<GetTodoQueryV2>>,
+    db: State<DB>,
+    list_shares: State<ListShareDB>,
+    event_store: State<Arc<EventStore>>,
+    user: CurrentUser,
+) -> Result<(StatusCode, Json<SingleTodoResponseV2>), (StatusCode, Json<serde_json::Value>)> {
+    let Query(opts) = opts.unwrap_or_default();
+    let (status, Json(response)) =
+        get_todo_handler(id, Some(Query(opts.into())), db, list_shares, event_store, user).await?;
+    Ok((status, Json(response.into())))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v2/todos/{id}",
+    tag = "todos (v2)",
+    summary = "Changes the fields that are sent",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    request_body = UpdateTodoV2,
+    responses(
+        (status = 200, description = "The todo, and its next occurrence if completing it scheduled one", body = SingleTodoResponseV2),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+        (status = 409, description = "The title is taken, the workflow doesn't allow the move, or the todo is blocked by unfinished todos", body = GenericResponse),
+    ),
+)]
+pub async fn v2_edit_todo_handler(
+    id: Path<Uuid>,
+    db: State<DB>,
+    workflow: State<Arc<Workflow>>,
+    list_shares: State<ListShareDB>,
+    user: CurrentUser,
+    audit: Auditor,
+    Json(body): Json<UpdateTodoV2>,
+) -> Result<(StatusCode, Json<SingleTodoResponseV2>), (StatusCode, Json<serde_json::Value>)> {
+    let (status, Json(response)) =
+        edit_todo_handler(id, db, workflow, list_shares, user, audit, Json(body.into())).await?;
+    Ok((status, Json(response.into())))
+}
+
+/* Deleting sends nothing back, so version 2 only passes on the status: a 204 with no body. */
+#[utoipa::path(
+    delete,
+    path = "/api/v2/todos/{id}",
+    tag = "todos (v2)",
+    summary = "Moves a todo to the trash",
+    params(("id" = Uuid, Path, description = "The todo's ID")),
+    responses(
+        (status = 204, description = "The todo is in the trash"),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 403, description = "The caller may see the todo but not do this to it", body = GenericResponse),
+        (status = 404, description = "No todo with this ID that the caller can see", body = GenericResponse),
+    ),
+)]
+pub async fn v2_delete_todo_handler(
+    id: Path<Uuid>,
+    db: State<DB>,
+    list_shares: State<ListShareDB>,
+    user: CurrentUser,
+    audit: Auditor,
+) -> Result<StatusCode, (StatusCode, Json<serde_json::Value>)> {
+    delete_todo_handler(id, db, list_shares, user, audit).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
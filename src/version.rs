@@ -0,0 +1,105 @@
+/* This file decides which version of the todo API answers a request: "/api/v2/todos", or "Accept: application/vnd.todo.v2+json" on the old URLs, gets version 2. Version 1 answers carry 'Deprecation', 'Sunset' and 'Link' headers pointing at version 2. */
+
+use axum::{
+    extract::Request,
+    http::{
+        header::{ACCEPT, LINK},
+        HeaderMap, HeaderName, HeaderValue,
+    },
+    response::{IntoResponse, Response},
+    routing::{any, MethodRouter},
+};
+use chrono::prelude::*;
+use tower::ServiceExt;
+    // Gives the method routers below 'oneshot', which sends them a single request.
+
+use crate::model::AppState;
+
+/* The media type that asks "/api/todos" for a version 2 answer. */
+pub const V2_MEDIA_TYPE: &str = "application/vnd.todo.v2+json";
+
+/* When version 1 was deprecated and when it may be switched off. */
+#[derive(Clone, Copy, Debug)]
+pub struct VersionConfig {
+    pub deprecated_at: DateTime<Utc>,
+    pub sunset: DateTime<Utc>,
+}
+
+impl VersionConfig {
+    /* Reads API_V1_DEPRECATED_AT and API_V1_SUNSET (RFC 3339, like "2027-05-01T00:00:00Z"). */
+    pub fn from_env() -> Self {
+        let env_time = |name: &str, default: DateTime<Utc>| match std::env::var(name) {
+            Ok(value) => DateTime::parse_from_rfc3339(value.trim())
+                .map(|time| time.with_timezone(&Utc))
+                .unwrap_or_else(|_| {
+                    eprintln!("Ignoring bad {}: '{}'", name, value);
+                    default
+                }),
+            Err(_) => default,
+        };
+
+        VersionConfig {
+            deprecated_at: env_time("API_V1_DEPRECATED_AT", Utc.with_ymd_and_hms(2026, 11, 1, 0, 0, 0).unwrap()),
+            sunset: env_time("API_V1_SUNSET", Utc.with_ymd_and_hms(2027, 5, 1, 0, 0, 0).unwrap()),
+        }
+    }
+}
+
+/* Whether the Accept header lists the version 2 media type (parameters like ";q=0.9" are ignored). */
+pub fn wants_v2(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| {
+            media_type
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .eq_ignore_ascii_case(V2_MEDIA_TYPE)
+        })
+}
+
+/* Serves one of the old "/api/todos" URLs with either version: 'v2' when the Accept header asks for it, otherwise 'v1' with the deprecation headers added. The CORS layer in main.rs adds "Vary: Accept", so caches keep the two answers apart. */
+pub fn negotiate(
+    state: AppState,
+    config: VersionConfig,
+    v1: MethodRouter<AppState>,
+    v2: MethodRouter<AppState>,
+) -> MethodRouter<AppState> {
+    let v1 = v1.with_state(state.clone());
+    let v2 = v2.with_state(state);
+        // The inner routers get the state now, since they are called directly instead of through the main router.
+    any(move |request: Request| async move {
+        if wants_v2(request.headers()) {
+            return v2.oneshot(request).await.into_response();
+        }
+        let successor = request.uri().path().replacen("/api/", "/api/v2/", 1);
+        let mut response = v1.oneshot(request).await.into_response();
+        deprecate(&mut response, config, &successor);
+        response
+    })
+}
+
+/* Adds the headers that tell version 1 clients to move on. */
+fn deprecate(response: &mut Response, config: VersionConfig, successor: &str) {
+    let headers = response.headers_mut();
+    let values = [
+        (
+            HeaderName::from_static("deprecation"),
+            format!("@{}", config.deprecated_at.timestamp()),
+        ),
+        (
+            HeaderName::from_static("sunset"),
+            config.sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        ),
+        (LINK, format!("<{}>; rel=\"successor-version\"", successor)),
+    ];
+    for (name, value) in values {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+}
@@ -0,0 +1,594 @@
+/* This file sends outgoing webhooks: a signed JSON POST to each subscribed URL for every change its owner may see, retried with a growing wait until the receiver answers with a 2xx. The "X-Webhook-Signature" header is "t=<unix time>,v1=<hex>", the HMAC-SHA256 of "<unix time>.<raw body>" keyed with the subscription's secret. */
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::prelude::*;
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::{broadcast::error::RecvError, mpsc, Mutex, Semaphore};
+use uuid::Uuid;
+
+use crate::{
+    auth::CurrentUser,
+    live::{self, ChangeKind, LiveFilter},
+    model::AppState,
+};
+
+/* Headers sent with every delivery. */
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+pub const DELIVERY_HEADER: &str = "x-webhook-delivery";
+
+/* How many deliveries are sent at the same time. */
+const CONCURRENT_DELIVERIES: usize = 8;
+
+/* The longest wait between two tries, however many tries are allowed. */
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/* The kinds of event a subscription can receive. 'Ping' is only sent by the "test" endpoint, and always gets through the filter. */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Created,
+    Updated,
+    Deleted,
+    Ping,
+}
+
+impl From<ChangeKind> for WebhookEvent {
+    fn from(kind: ChangeKind) -> Self {
+        match kind {
+            ChangeKind::Created => WebhookEvent::Created,
+            ChangeKind::Updated => WebhookEvent::Updated,
+            ChangeKind::Deleted => WebhookEvent::Deleted,
+        }
+    }
+}
+
+impl WebhookEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Created => "created",
+            WebhookEvent::Updated => "updated",
+            WebhookEvent::Deleted => "deleted",
+            WebhookEvent::Ping => "ping",
+        }
+    }
+}
+
+/* A subscription. An empty 'events' list means every event. The secret is never sent back after creation. */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Webhook {
+    pub id: String,
+    pub tenantId: String,
+    pub ownerId: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub active: bool,
+    pub createdAt: DateTime<Utc>,
+    pub updatedAt: DateTime<Utc>,
+}
+
+impl Webhook {
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.active && (event == WebhookEvent::Ping || self.events.is_empty() || self.events.contains(&event))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+/* One entry in a subscription's delivery log. 'responseStatus' and 'error' describe the latest attempt. */
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Delivery {
+    pub id: String,
+    pub webhookId: String,
+    pub event: WebhookEvent,
+    pub eventId: Option<u64>,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub responseStatus: Option<u16>,
+    pub error: Option<String>,
+    pub createdAt: DateTime<Utc>,
+    pub lastAttemptAt: Option<DateTime<Utc>>,
+    pub nextAttemptAt: Option<DateTime<Utc>>,
+}
+
+/* A delivery waiting in the queue. The body is fixed when the event happens; the URL and secret are looked up on every attempt, so editing a subscription affects retries too. */
+#[derive(Debug, Clone)]
+struct Job {
+    webhook_id: String,
+    delivery_id: String,
+    event: WebhookEvent,
+    body: String,
+}
+
+/* Every subscription and delivery log, plus the queue deliveries wait in. */
+pub struct WebhookStore {
+    pub hooks: Mutex<Vec<Webhook>>,
+    deliveries: Mutex<HashMap<String, VecDeque<Delivery>>>,
+        // Delivery logs by subscription ID, oldest first.
+    queue: mpsc::Sender<Job>,
+    receiver: Mutex<Option<mpsc::Receiver<Job>>>,
+        // Taken by the dispatcher when it starts.
+    client: reqwest::Client,
+    pub allow_private_urls: bool,
+        // Lets subscriptions point at loopback and private network addresses, for testing against a local receiver.
+    max_attempts: u32,
+    retry_base: Duration,
+    log_size: usize,
+}
+
+impl WebhookStore {
+    /* Reads WEBHOOK_MAX_ATTEMPTS (tries per delivery, default 6), WEBHOOK_RETRY_BASE_SECONDS (wait before the first retry, doubled after each one, default 2), WEBHOOK_TIMEOUT_SECONDS (default 10), WEBHOOK_LOG_SIZE (deliveries kept per subscription, default 50), WEBHOOK_QUEUE_SIZE (default 1000) and WEBHOOK_ALLOW_PRIVATE_URLS (set to "true" to allow local receivers). */
+    pub fn from_env() -> Self {
+        let env_number = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+
+        let allow_private_urls = std::env::var("WEBHOOK_ALLOW_PRIVATE_URLS").is_ok_and(|value| value == "true");
+
+        let mut client = reqwest::Client::builder().timeout(Duration::from_secs(env_number("WEBHOOK_TIMEOUT_SECONDS", 10)));
+        if !allow_private_urls {
+            client = client
+                .dns_resolver(Arc::new(PublicOnly))
+                .redirect(reqwest::redirect::Policy::none());
+                // A host name can still resolve to a private address, and a receiver could redirect to one, so both are checked when sending too.
+        }
+
+        let (queue, receiver) = mpsc::channel(env_number("WEBHOOK_QUEUE_SIZE", 1000) as usize);
+        WebhookStore {
+            hooks: Mutex::new(Vec::new()),
+            deliveries: Mutex::new(HashMap::new()),
+            queue,
+            receiver: Mutex::new(Some(receiver)),
+            client: client.build().unwrap_or_default(),
+            allow_private_urls,
+            max_attempts: env_number("WEBHOOK_MAX_ATTEMPTS", 6) as u32,
+            retry_base: Duration::from_secs(env_number("WEBHOOK_RETRY_BASE_SECONDS", 2)),
+            log_size: env_number("WEBHOOK_LOG_SIZE", 50) as usize,
+        }
+    }
+
+    /* A subscription's delivery log, newest first. */
+    pub async fn deliveries(&self, webhook_id: &str) -> Vec<Delivery> {
+        self.deliveries
+            .lock()
+            .await
+            .get(webhook_id)
+            .map(|log| log.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /* Drops a deleted subscription's log. Deliveries still queued for it fail when their turn comes. */
+    pub async fn forget(&self, webhook_id: &str) {
+        self.deliveries.lock().await.remove(webhook_id);
+    }
+
+    /* Logs a new delivery and puts it in the queue. If the queue is full it is logged as failed straight away rather than waiting, so a dead receiver can't hold up everything else. */
+    pub async fn enqueue(&self, hook: &Webhook, event: WebhookEvent, event_id: Option<u64>, payload: serde_json::Value) -> Delivery {
+        let delivery = Delivery {
+            id: Uuid::new_v4().to_string(),
+            webhookId: hook.id.clone(),
+            event,
+            eventId: event_id,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            responseStatus: None,
+            error: None,
+            createdAt: Utc::now(),
+            lastAttemptAt: None,
+            nextAttemptAt: Some(Utc::now()),
+        };
+
+        let mut body = payload;
+        body["id"] = serde_json::json!(delivery.id);
+        body["type"] = serde_json::json!(event);
+        body["webhookId"] = serde_json::json!(hook.id);
+        let job = Job {
+            webhook_id: hook.id.clone(),
+            delivery_id: delivery.id.clone(),
+            event,
+            body: body.to_string(),
+        };
+
+        let mut deliveries = self.deliveries.lock().await;
+        let log = deliveries.entry(hook.id.clone()).or_default();
+        log.push_back(delivery);
+        if log.len() > self.log_size {
+            log.pop_front();
+        }
+        let delivery = log.back_mut().unwrap();
+        if self.queue.try_send(job).is_err() {
+            delivery.status = DeliveryStatus::Failed;
+            delivery.error = Some("The delivery queue is full".to_string());
+            delivery.nextAttemptAt = None;
+        }
+        delivery.clone()
+    }
+
+    /* Changes a logged delivery, if it is still in the log. */
+    async fn update(&self, job: &Job, change: impl FnOnce(&mut Delivery)) {
+        let mut deliveries = self.deliveries.lock().await;
+        if let Some(delivery) = deliveries
+            .get_mut(&job.webhook_id)
+            .and_then(|log| log.iter_mut().find(|delivery| delivery.id == job.delivery_id))
+        {
+            change(delivery);
+        }
+    }
+
+    /* Makes one attempt at a delivery, then either logs the result or schedules the next try. */
+    async fn attempt(self: Arc<Self>, job: Job) {
+        let hook = self
+            .hooks
+            .lock()
+            .await
+            .iter()
+            .find(|hook| hook.id == job.webhook_id && hook.active)
+            .cloned();
+        let result = match &hook {
+            Some(hook) => self.post(hook, &job).await,
+            None => Err((None, "The webhook was deleted or disabled".to_string())),
+        };
+
+        let now = Utc::now();
+        let mut retry_in = None;
+        let max_attempts = self.max_attempts;
+        let retry_base = self.retry_base;
+        self.update(&job, |delivery| {
+            delivery.attempts += 1;
+            delivery.lastAttemptAt = Some(now);
+            delivery.nextAttemptAt = None;
+            match result {
+                Ok(status) => {
+                    delivery.status = DeliveryStatus::Succeeded;
+                    delivery.responseStatus = Some(status);
+                    delivery.error = None;
+                }
+                Err((status, error)) => {
+                    delivery.responseStatus = status;
+                    delivery.error = Some(error);
+                    if delivery.attempts < max_attempts && hook.is_some() {
+                        let wait = retry_wait(retry_base, delivery.attempts);
+                        delivery.nextAttemptAt = chrono::Duration::from_std(wait).ok().map(|wait| now + wait);
+                        retry_in = Some(wait);
+                    } else {
+                        delivery.status = DeliveryStatus::Failed;
+                    }
+                }
+            }
+        })
+        .await;
+
+        if let Some(wait) = retry_in {
+            tokio::spawn(async move {
+                tokio::time::sleep(wait).await;
+                let _ = self.queue.send(job).await;
+            });
+        }
+    }
+
+    /* Sends the signed POST. Returns the status code, or the status (if there was a response) and what went wrong. */
+    async fn post(&self, hook: &Webhook, job: &Job) -> Result<u16, (Option<u16>, String)> {
+        let timestamp = Utc::now().timestamp();
+        let response = self
+            .client
+            .post(&hook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(reqwest::header::USER_AGENT, "rust-axum-crud-api-webhooks")
+            .header(EVENT_HEADER, job.event.as_str())
+            .header(DELIVERY_HEADER, &job.delivery_id)
+            .header(SIGNATURE_HEADER, format!("t={},v1={}", timestamp, sign(&hook.secret, timestamp, &job.body)))
+            .body(job.body.clone())
+            .send()
+            .await
+            .map_err(|err| (None, err.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(status.as_u16())
+        } else {
+            Err((Some(status.as_u16()), format!("The receiver responded with {}", status)))
+        }
+    }
+}
+
+/* How long to wait after the given number of failed tries: 1x, 2x, 4x... the base wait, but never more than MAX_RETRY_WAIT. */
+fn retry_wait(base: Duration, attempts: u32) -> Duration {
+    let doublings = attempts.saturating_sub(1).min(31);
+    base.checked_mul(1 << doublings).unwrap_or(MAX_RETRY_WAIT).min(MAX_RETRY_WAIT)
+}
+
+/* The hex HMAC-SHA256 of "<timestamp>.<body>" under the secret. */
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/* Makes a new signing secret. */
+pub fn generate_secret() -> String {
+    format!("whsec_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/* Checks a subscription URL: it has to be a full http(s) URL. Unless 'allow_private' is set, it also can't point at this machine or a private network, or anyone who can add a webhook could make the server send requests to services that aren't meant to be reachable from outside. */
+pub fn validate_url(url: &str, allow_private: bool) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).ok();
+    let Some(host) = parsed
+        .as_ref()
+        .filter(|parsed| matches!(parsed.scheme(), "http" | "https"))
+        .and_then(|parsed| parsed.host_str())
+    else {
+        return Err(format!("'{}' is not a valid http(s) URL", url));
+    };
+    if allow_private {
+        return Ok(());
+    }
+
+    let public = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => is_public(ip),
+        Err(_) => host != "localhost" && !host.ends_with(".localhost"),
+    };
+    if public {
+        Ok(())
+    } else {
+        Err(format!("'{}' points at a local or private address", url))
+    }
+}
+
+/* False for addresses on this machine or a private network: loopback, private ranges, link-local (which includes cloud metadata services) and the like. */
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || (a == 100 && (64..128).contains(&b)))
+                // 100.64.0.0/10 is shared address space for carrier networks.
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback() || ip.is_unspecified() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80)
+                    // fc00::/7 is private (unique local), fe80::/10 link-local.
+            }
+        },
+    }
+}
+
+/* Looks up receiver host names, keeping only public addresses (see 'validate_url'). */
+struct PublicOnly;
+
+impl Resolve for PublicOnly {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} doesn't resolve to a public address", name.as_str()).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/* Starts the dispatcher: one task turns published changes into deliveries, another works through the queue. Returns straight away. */
+pub fn spawn_webhook_dispatcher(state: AppState) {
+    let store = state.webhooks.clone();
+    tokio::spawn(async move {
+        let Some(mut receiver) = store.receiver.lock().await.take() else {
+            return;
+        };
+        let permits = Arc::new(Semaphore::new(CONCURRENT_DELIVERIES));
+
+        while let Some(job) = receiver.recv().await {
+            let Ok(permit) = permits.clone().acquire_owned().await else {
+                break;
+            };
+            let store = store.clone();
+            tokio::spawn(async move {
+                store.attempt(job).await;
+                drop(permit);
+            });
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut changes = state.events.subscribe();
+        loop {
+            match changes.recv().await {
+                Ok(change) => queue_change(&state, &change).await,
+                Err(RecvError::Lagged(missed)) => eprintln!("⚠️ Webhooks fell behind and skipped {} changes", missed),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/* Queues a delivery of one change for every active subscription in its workspace whose owner can see the todo and that wants this kind of event. */
+async fn queue_change(state: &AppState, change: &live::TodoChange) {
+    let hooks: Vec<Webhook> = state
+        .webhooks
+        .hooks
+        .lock()
+        .await
+        .iter()
+        .filter(|hook| hook.active && hook.tenantId == change.tenantId)
+        .cloned()
+        .collect();
+    if hooks.is_empty() {
+        return;
+    }
+
+    let list_shares = state.list_shares.lock().await.clone();
+    for hook in hooks {
+        let owner = CurrentUser {
+            id: hook.ownerId.clone(),
+            api_key_id: None,
+            tenant: hook.tenantId.clone(),
+        };
+        let Some(event) = live::event_for(change, &owner, &LiveFilter::default(), &list_shares) else {
+            continue;
+        };
+        let kind = WebhookEvent::from(event.kind);
+        if !hook.wants(kind) {
+            continue;
+        }
+
+        let payload = serde_json::json!({
+            "eventId": change.id,
+            "at": change.at,
+            "tenantId": change.tenantId,
+            "actorId": change.actorId,
+            "todo": event.todo,
+        });
+        state.webhooks.enqueue(&hook, kind, Some(change.id), payload).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{
+        http::{HeaderMap, Method, StatusCode},
+        routing::post,
+        Router,
+    };
+    use chrono::Utc;
+    use serde_json::{json, Value};
+    use tokio::sync::{mpsc, Mutex};
+
+    use super::{retry_wait, sign, validate_url, DeliveryStatus, Webhook, WebhookEvent, WebhookStore, MAX_RETRY_WAIT, SIGNATURE_HEADER};
+    use crate::testing::{app, login, send};
+
+    fn hook(url: &str) -> Webhook {
+        Webhook {
+            id: "hook-1".to_string(),
+            tenantId: "default".to_string(),
+            ownerId: "alice".to_string(),
+            url: url.to_string(),
+            events: Vec::new(),
+            secret: "whsec_test".to_string(),
+            active: true,
+            createdAt: Utc::now(),
+            updatedAt: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn signature_matches_a_known_vector() {
+        /* Worked out independently, with Python's hmac module. */
+        assert_eq!(
+            sign("whsec_test", 1700000000, r#"{"type":"ping"}"#),
+            "bc08c591847b765241711bcbe7067e3869a219e424d3fdd9d00b3b6f915baf97"
+        );
+    }
+
+    #[test]
+    fn retries_back_off_up_to_a_cap() {
+        let base = Duration::from_secs(2);
+        assert_eq!(retry_wait(base, 1), Duration::from_secs(2));
+        assert_eq!(retry_wait(base, 3), Duration::from_secs(8));
+        assert_eq!(retry_wait(base, 40), MAX_RETRY_WAIT);
+            // Far more tries than a u32 of doublings can hold.
+    }
+
+    #[tokio::test]
+    async fn local_and_private_receivers_are_refused() {
+        for url in ["http://127.0.0.1:8080/hook", "http://localhost/hook", "http://10.1.2.3/", "http://169.254.169.254/latest", "http://[::1]/", "http://[fd00::1]/"] {
+            assert!(validate_url(url, false).is_err(), "{} was allowed", url);
+            assert!(validate_url(url, true).is_ok());
+        }
+        assert!(validate_url("https://hooks.example.com/todo", false).is_ok());
+        assert!(validate_url("ftp://hooks.example.com/todo", true).is_err());
+
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let auth = [("authorization", alice.as_str())];
+        let (status, _) = send(&router, Method::POST, "/api/webhooks", &auth, json!({ "url": "http://127.0.0.1:9000/hook" })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&router, Method::POST, "/api/webhooks", &auth, json!({ "url": "https://hooks.example.com/todo" })).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_signed() {
+        /* A receiver on this machine that hands over what it gets. */
+        let (sender, mut received) = mpsc::channel::<(HeaderMap, String)>(1);
+        let receiver = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: String| async move {
+                sender.send((headers, body)).await.unwrap();
+                StatusCode::OK
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let mut store = WebhookStore::from_env();
+        store.allow_private_urls = true;
+        let store = Arc::new(store);
+        let hook = hook(&format!("http://{}/hook", address));
+        store.hooks.lock().await.push(hook.clone());
+        store.enqueue(&hook, WebhookEvent::Ping, None, json!({})).await;
+        let job = store.receiver.lock().await.as_mut().unwrap().recv().await.unwrap();
+        store.clone().attempt(job).await;
+
+        let (headers, body) = received.recv().await.unwrap();
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+        let (timestamp, digest) = signature.strip_prefix("t=").unwrap().split_once(",v1=").unwrap();
+        assert_eq!(digest, sign(&hook.secret, timestamp.parse().unwrap(), &body));
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["type"], "ping");
+
+        let log = store.deliveries(&hook.id).await;
+        assert_eq!((log[0].status, log[0].attempts, log[0].responseStatus), (DeliveryStatus::Succeeded, 1, Some(200)));
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_fails_deliveries_and_the_log_is_capped() {
+        let mut store = WebhookStore::from_env();
+        let (queue, receiver) = mpsc::channel(1);
+        store.queue = queue;
+        store.receiver = Mutex::new(Some(receiver));
+        store.log_size = 2;
+        let hook = hook("https://hooks.example.com/todo");
+
+        for _ in 0..3 {
+            store.enqueue(&hook, WebhookEvent::Ping, None, json!({})).await;
+        }
+        /* Only the first fit in the queue, and only the newest two are still logged. */
+        let log = store.deliveries(&hook.id).await;
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().all(|delivery| delivery.status == DeliveryStatus::Failed));
+        assert_eq!(log[0].error.as_deref(), Some("The delivery queue is full"));
+    }
+}
@@ -0,0 +1,307 @@
+/* This file holds the handlers for managing webhook subscriptions and reading their delivery log (see webhook.rs). */
+
+/* Imports the parts of the axum webframework the handlers below use (see handler.rs for what each one does). */
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+/* Imports Uuid, used for the subscription IDs in the paths, and Arc for the shared store. */
+use uuid::Uuid;
+use std::sync::Arc;
+
+/* Imports structures and functions from our local crate (IE our project). */
+use crate::{
+    auth::CurrentUser,
+    model::{CreateWebhookSchema, UpdateWebhookSchema},
+    response::{
+        DeliveryListResponse, DeliveryResponse, GenericResponse, WebhookData, WebhookListResponse, WebhookResponse,
+    },
+    webhook::{self, Webhook, WebhookEvent, WebhookStore},
+};
+
+/* Finds one of the caller's webhook subscriptions. Other users' subscriptions are reported as not found. */
+fn find_webhook(hooks: &[Webhook], id: &str, user: &CurrentUser) -> Result<usize, (StatusCode, Json<serde_json::Value>)> {
+    hooks
+        .iter()
+        .position(|hook| hook.id == id && hook.ownerId == user.id && hook.tenantId == user.tenant)
+        .ok_or_else(|| {
+            let error_response = serde_json::json!({
+                "status": "fail",
+                "message": format!("Webhook with ID: {} not found", id)
+            });
+            (StatusCode::NOT_FOUND, Json(error_response))
+        })
+}
+
+/* Turns an invalid webhook URL into a 400. */
+fn check_webhook_url(webhooks: &WebhookStore, url: &str) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    webhook::validate_url(url, webhooks.allow_private_urls).map_err(|message| {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": message,
+        });
+        (StatusCode::BAD_REQUEST, Json(error_response))
+    })
+}
+
+/* Subscribes a URL to todo changes the caller can see (see webhook.rs). The signing secret is in this response only. */
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    summary = "Subscribes a URL to todo changes",
+    request_body = CreateWebhookSchema,
+    responses(
+        (status = 201, description = "The subscription; 'secret' is only ever shown here", body = WebhookResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn create_webhook_handler(
+    State(webhooks): State<Arc<WebhookStore>>,
+    user: CurrentUser,
+    Json(body): Json<CreateWebhookSchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    check_webhook_url(&webhooks, &body.url)?;
+
+    let now = chrono::Utc::now();
+    let hook = Webhook {
+        id: Uuid::new_v4().to_string(),
+        tenantId: user.tenant.clone(),
+        ownerId: user.id.clone(),
+        url: body.url,
+        events: body.events,
+        secret: body.secret.filter(|secret| !secret.is_empty()).unwrap_or_else(webhook::generate_secret),
+        active: true,
+        createdAt: now,
+        updatedAt: now,
+    };
+    webhooks.hooks.lock().await.push(hook.clone());
+
+    let json_response = WebhookResponse {
+        status: "success".to_string(),
+        data: WebhookData {
+            secret: Some(hook.secret.clone()),
+            webhook: hook,
+        },
+    };
+    Ok((StatusCode::CREATED, Json(json_response)))
+}
+
+/* Lists the caller's webhook subscriptions. */
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    summary = "Lists the caller's webhook subscriptions",
+    responses(
+        (status = 200, description = "The caller's subscriptions", body = WebhookListResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+    ),
+)]
+pub async fn list_webhooks_handler(State(webhooks): State<Arc<WebhookStore>>, user: CurrentUser) -> impl IntoResponse {
+    let hooks: Vec<Webhook> = webhooks
+        .hooks
+        .lock()
+        .await
+        .iter()
+        .filter(|hook| hook.ownerId == user.id && hook.tenantId == user.tenant)
+        .cloned()
+        .collect();
+
+    let json_response = WebhookListResponse {
+        status: "success".to_string(),
+        results: hooks.len(),
+        webhooks: hooks,
+    };
+    Json(json_response)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    summary = "Gets a subscription",
+    params(("id" = Uuid, Path, description = "The subscription's ID")),
+    responses(
+        (status = 200, description = "The subscription", body = WebhookResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No subscription with this ID", body = GenericResponse),
+    ),
+)]
+pub async fn get_webhook_handler(
+    Path(id): Path<Uuid>,
+    State(webhooks): State<Arc<WebhookStore>>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let hooks = webhooks.hooks.lock().await;
+    let pos = find_webhook(&hooks, &id.to_string(), &user)?;
+
+    let json_response = WebhookResponse {
+        status: "success".to_string(),
+        data: WebhookData {
+            webhook: hooks[pos].clone(),
+            secret: None,
+        },
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Changes a subscription's URL or event types, or pauses it ("active": false). Paused subscriptions get no new deliveries, and queued ones fail. */
+#[utoipa::path(
+    patch,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    summary = "Changes a subscription",
+    params(("id" = Uuid, Path, description = "The subscription's ID")),
+    request_body = UpdateWebhookSchema,
+    responses(
+        (status = 200, description = "The subscription", body = WebhookResponse),
+        (status = 400, description = "The request is invalid", body = GenericResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No subscription with this ID", body = GenericResponse),
+    ),
+)]
+pub async fn edit_webhook_handler(
+    Path(id): Path<Uuid>,
+    State(webhooks): State<Arc<WebhookStore>>,
+    user: CurrentUser,
+    Json(body): Json<UpdateWebhookSchema>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    if let Some(url) = &body.url {
+        check_webhook_url(&webhooks, url)?;
+    }
+
+    let mut hooks = webhooks.hooks.lock().await;
+    let pos = find_webhook(&hooks, &id.to_string(), &user)?;
+    let hook = &mut hooks[pos];
+    if let Some(url) = body.url {
+        hook.url = url;
+    }
+    if let Some(events) = body.events {
+        hook.events = events;
+    }
+    if let Some(active) = body.active {
+        hook.active = active;
+    }
+    hook.updatedAt = chrono::Utc::now();
+
+    let json_response = WebhookResponse {
+        status: "success".to_string(),
+        data: WebhookData {
+            webhook: hook.clone(),
+            secret: None,
+        },
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Removes a subscription and its delivery log. */
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    summary = "Removes a subscription",
+    params(("id" = Uuid, Path, description = "The subscription's ID")),
+    responses(
+        (status = 204, description = "The subscription is gone"),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No subscription with this ID", body = GenericResponse),
+    ),
+)]
+pub async fn delete_webhook_handler(
+    Path(id): Path<Uuid>,
+    State(webhooks): State<Arc<WebhookStore>>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    {
+        let mut hooks = webhooks.hooks.lock().await;
+        let pos = find_webhook(&hooks, &id, &user)?;
+        hooks.remove(pos);
+    }
+    webhooks.forget(&id).await;
+
+    Ok((StatusCode::NO_CONTENT, Json("")))
+}
+
+/* Shows a subscription's most recent deliveries, newest first, with how each attempt went. */
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    summary = "Shows a subscription's recent deliveries",
+    params(("id" = Uuid, Path, description = "The subscription's ID")),
+    responses(
+        (status = 200, description = "The delivery log, newest first", body = DeliveryListResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No subscription with this ID", body = GenericResponse),
+    ),
+)]
+pub async fn webhook_deliveries_handler(
+    Path(id): Path<Uuid>,
+    State(webhooks): State<Arc<WebhookStore>>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let id = id.to_string();
+    find_webhook(&webhooks.hooks.lock().await, &id, &user)?;
+    let deliveries = webhooks.deliveries(&id).await;
+
+    let json_response = DeliveryListResponse {
+        status: "success".to_string(),
+        results: deliveries.len(),
+        deliveries,
+    };
+    Ok((StatusCode::OK, Json(json_response)))
+}
+
+/* Queues a "ping" delivery, so a receiver (and its signature check) can be tried out without changing a todo. It is sent and retried like any other delivery; watch the delivery log for the result. */
+#[utoipa::path(
+    post,
+    path = "/api/webhooks/{id}/test",
+    tag = "webhooks",
+    summary = "Sends a subscription a signed ping",
+    params(("id" = Uuid, Path, description = "The subscription's ID")),
+    responses(
+        (status = 202, description = "The ping is queued", body = DeliveryResponse),
+        (status = 401, description = "Not logged in, or the token or API key is invalid", body = GenericResponse),
+        (status = 404, description = "No subscription with this ID", body = GenericResponse),
+        (status = 409, description = "The subscription is paused", body = GenericResponse),
+    ),
+)]
+pub async fn test_webhook_handler(
+    Path(id): Path<Uuid>,
+    State(webhooks): State<Arc<WebhookStore>>,
+    user: CurrentUser,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let hook = {
+        let hooks = webhooks.hooks.lock().await;
+        let pos = find_webhook(&hooks, &id.to_string(), &user)?;
+        hooks[pos].clone()
+    };
+    if !hook.active {
+        let error_response = serde_json::json!({
+            "status": "fail",
+            "message": "This webhook is paused; set 'active' to true first",
+        });
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+
+    let payload = serde_json::json!({
+        "eventId": null,
+        "at": chrono::Utc::now(),
+        "tenantId": user.tenant,
+        "actorId": user.id,
+        "todo": null,
+    });
+    let delivery = webhooks.enqueue(&hook, WebhookEvent::Ping, None, payload).await;
+
+    let json_response = DeliveryResponse {
+        status: "success".to_string(),
+        delivery,
+    };
+    Ok((StatusCode::ACCEPTED, Json(json_response)))
+}
@@ -0,0 +1,160 @@
+/* This file describes the Kanban workflow a todo moves through (for example backlog → in progress → review → done). The columns and which moves between them are allowed can be changed with a JSON file (see 'from_env'), so each team can match its own process. The 'completed' flag on a todo is derived from its status: a todo is completed exactly when it sits in the workflow's done column. */
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::model::Todo;
+
+/* A workflow: the ordered columns of the board, the column that counts as done, and the allowed moves out of each column. */
+#[derive(Debug, Clone, Deserialize)]
+pub struct Workflow {
+    pub columns: Vec<String>,
+        // Board columns in display order. The first column is where new todos start.
+    pub done: String,
+        // The column that means "completed".
+    pub transitions: HashMap<String, Vec<String>>,
+        // For each column, the columns a todo is allowed to move to next.
+}
+
+impl Default for Workflow {
+    /* The built-in workflow: backlog → in_progress → review → done, with the option to step back. */
+    fn default() -> Self {
+        let transitions = [
+            ("backlog", vec!["in_progress"]),
+            ("in_progress", vec!["backlog", "review"]),
+            ("review", vec!["in_progress", "done"]),
+            ("done", vec!["review"]),
+        ]
+        .into_iter()
+        .map(|(from, to)| (from.to_string(), to.into_iter().map(String::from).collect()))
+        .collect();
+
+        Workflow {
+            columns: ["backlog", "in_progress", "review", "done"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            done: "done".to_string(),
+            transitions,
+        }
+    }
+}
+
+impl Workflow {
+    /* Loads the workflow from the JSON file named by WORKFLOW_FILE, falling back to the built-in one. A broken file stops the server at startup instead of silently using the wrong workflow. */
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("WORKFLOW_FILE") else {
+            return Workflow::default();
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Couldn't read workflow file {}: {}", path, err));
+        let workflow: Workflow = serde_json::from_str(&contents)
+            .unwrap_or_else(|err| panic!("Invalid workflow file {}: {}", path, err));
+        if let Err(message) = workflow.check() {
+            panic!("Invalid workflow file {}: {}", path, message);
+        }
+        workflow
+    }
+
+    /* Makes sure every column the workflow mentions actually exists. */
+    fn check(&self) -> Result<(), String> {
+        if self.columns.is_empty() {
+            return Err("a workflow needs at least one column".to_string());
+        }
+        if !self.has_column(&self.done) {
+            return Err(format!("done column '{}' is not one of the columns", self.done));
+        }
+        for (from, targets) in &self.transitions {
+            for column in std::iter::once(from).chain(targets) {
+                if !self.has_column(column) {
+                    return Err(format!("transition uses unknown column '{}'", column));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* The column new todos start in. */
+    pub fn initial(&self) -> &str {
+        &self.columns[0]
+    }
+
+    pub fn has_column(&self, column: &str) -> bool {
+        self.columns.iter().any(|c| c == column)
+    }
+
+    pub fn is_done(&self, column: &str) -> bool {
+        self.done == column
+    }
+
+    /* The column a todo is in. Todos saved before workflows existed only have 'completed', which puts them in the done column or the first one. */
+    pub fn status_of(&self, todo: &Todo) -> String {
+        todo.status.clone().unwrap_or_else(|| {
+            if todo.completed.unwrap_or(false) {
+                self.done.clone()
+            } else {
+                self.initial().to_string()
+            }
+        })
+    }
+
+    /* Checks a move from one column to another. Staying in the same column is always fine, even one a changed workflow file no longer has. */
+    pub fn check_transition(&self, from: &str, to: &str) -> Result<(), String> {
+        if from == to {
+            return Ok(());
+        }
+        if !self.has_column(to) {
+            return Err(format!("Unknown status: '{}'", to));
+        }
+
+        let allowed = self
+            .transitions
+            .get(from)
+            .map(|targets| targets.iter().any(|target| target == to))
+            .unwrap_or(false);
+        if allowed {
+            Ok(())
+        } else {
+            Err(format!("Can't move a todo from '{}' to '{}'", from, to))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::testing::{app, create_todo, login, send};
+
+    #[tokio::test]
+    async fn completed_flag_follows_the_transitions() {
+        let (router, _) = app();
+        let alice = login(&router, "default", "alice").await;
+        let todo = create_todo(&router, &alice, json!({ "title": "Ship it", "content": "a" })).await;
+        let uri = format!("/api/todos/{}", todo["id"].as_str().unwrap());
+        let auth = [("authorization", alice.as_str())];
+        let edit = |body: Value| send(&router, Method::PATCH, &uri, &auth, body);
+
+        /* backlog → done skips two columns. */
+        let (status, _) = edit(json!({ "completed": true })).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        for column in ["in_progress", "review"] {
+            let (status, _) = edit(json!({ "status": column })).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, body) = edit(json!({ "completed": true })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["todo"]["status"], "done");
+
+        /* done → backlog isn't allowed either, and neither is restoring the backlog revision. */
+        let (status, _) = edit(json!({ "completed": false })).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let restore = format!("{}/revisions/1/restore", uri);
+        let (status, _) = send(&router, Method::POST, &restore, &auth, Value::Null).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+}